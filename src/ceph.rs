@@ -17,13 +17,22 @@
 use crate::JsonData;
 
 use crate::admin_sockets::*;
+use crate::checksum::{self, ChecksumType, Verification, DEFAULT_VERIFY_CHUNK_SIZE};
+use crate::cluster_log::{self, ClusterLogLevel, ClusterLogWatch};
+use crate::codec::{Codec, JsonCodec};
+use crate::copy::{self, CopyMethod, CopyOptions};
 use crate::error::*;
 use crate::json::*;
+use crate::mon_command::MonCommand;
+use crate::object_io::RadosObject;
+use crate::transfer::{self, TransferOptions};
 use crate::JsonValue;
 use byteorder::{LittleEndian, WriteBytesExt};
 use libc::*;
 use nom::number::complete::le_u32;
 use nom::IResult;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json;
 
 use crate::rados::*;
@@ -32,12 +41,14 @@ use crate::rados_striper::*;
 use crate::status::*;
 use std::ffi::{CStr, CString};
 use std::marker::PhantomData;
-use std::sync::Arc;
-use std::{ptr, str};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::{ptr, slice, str};
 
 use crate::utils::*;
-use std::io::{BufRead, Cursor};
+use std::io::{BufRead, Cursor, Read};
 use std::net::IpAddr;
+use std::path::Path;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use uuid::Uuid;
@@ -47,6 +58,13 @@ const CEPH_OSD_TMAP_SET: char = 's';
 const CEPH_OSD_TMAP_CREATE: char = 'c';
 const CEPH_OSD_TMAP_RM: char = 'r';
 
+// Largest single read issued when reading a whole object
+const MAX_READ_CHUNK: u64 = 4 * 1024 * 1024;
+// Number of omap keys fetched per read operation
+const OMAP_PAGE_SIZE: u64 = 1000;
+// Largest xattr value rados_object_getxattr_full will grow its buffer to
+const MAX_XATTR_SIZE: usize = 4 * 1024 * 1024;
+
 #[derive(Debug, Clone)]
 pub enum CephHealth {
     Ok,
@@ -54,11 +72,14 @@ pub enum CephHealth {
     Error,
 }
 
+/// Where `Rados::ceph_command` sends a command
 #[derive(Debug, Clone)]
 pub enum CephCommandTypes {
     Mon,
-    Osd,
-    Pgs,
+    /// An OSD id
+    Osd(i32),
+    /// A pgid such as `1.2f`
+    Pgs(String),
 }
 
 named!(
@@ -234,6 +255,35 @@ pub struct ReadOperation {
     /// all the other flags are documented in rados.rs
     pub flags: u32,
     read_op_handle: rados_read_op_t,
+    checksums: Vec<ChecksumStep>,
+    execs: Vec<ExecStep>,
+}
+
+// librados writes into reply and prval when the operation runs, so both are
+// kept on the heap where their addresses don't change
+#[derive(Debug)]
+struct ChecksumStep {
+    kind: ChecksumType,
+    reply: Vec<u8>,
+    prval: Box<c_int>,
+}
+
+// Output of an exec step.  librados allocates buf when the operation runs.
+#[derive(Debug)]
+struct ExecStep {
+    buf: Box<*mut c_char>,
+    len: Box<size_t>,
+    prval: Box<c_int>,
+}
+
+impl Drop for ExecStep {
+    fn drop(&mut self) {
+        if !self.buf.is_null() {
+            unsafe {
+                rados_buffer_free(*self.buf);
+            }
+        }
+    }
 }
 
 impl Drop for ReadOperation {
@@ -244,6 +294,135 @@ impl Drop for ReadOperation {
     }
 }
 
+impl ReadOperation {
+    /// Create an empty read operation on an object.  Add steps to it, run
+    /// them all atomically with IoCtx::rados_perform_read_operations and then
+    /// collect each step's result.
+    pub fn new(object_name: &str) -> ReadOperation {
+        ReadOperation {
+            object_name: object_name.to_string(),
+            flags: 0,
+            read_op_handle: unsafe { rados_create_read_op() },
+            checksums: Vec::new(),
+            execs: Vec::new(),
+        }
+    }
+
+    /// Checksum len bytes starting at offset on the OSD.  With a chunk_size
+    /// of 0 a single checksum covers the whole range, otherwise len must be
+    /// a multiple of chunk_size and one checksum is returned per chunk.
+    /// Returns the step number to pass to checksum_result.
+    pub fn checksum(
+        &mut self,
+        kind: ChecksumType,
+        init_value: u64,
+        offset: u64,
+        len: usize,
+        chunk_size: usize,
+    ) -> usize {
+        let count = if chunk_size == 0 {
+            1
+        } else {
+            len.div_ceil(chunk_size).max(1)
+        };
+        let mut step = ChecksumStep {
+            kind,
+            reply: vec![0; 4 + count * kind.size()],
+            prval: Box::new(0),
+        };
+        let init = kind.encode_init(init_value);
+        unsafe {
+            rados_read_op_checksum(
+                self.read_op_handle,
+                kind.as_raw(),
+                init.as_ptr() as *const c_char,
+                init.len(),
+                offset,
+                len,
+                chunk_size,
+                step.reply.as_mut_ptr() as *mut c_char,
+                step.reply.len(),
+                &mut *step.prval,
+            );
+        }
+        self.checksums.push(step);
+        self.checksums.len() - 1
+    }
+
+    /// The checksums computed by a checksum step, one per chunk
+    pub fn checksum_result(&self, step: usize) -> RadosResult<Vec<u64>> {
+        let step = self
+            .checksums
+            .get(step)
+            .ok_or_else(|| RadosError::new(format!("No checksum step {}", step)))?;
+        if *step.prval < 0 {
+            return Err((*step.prval).into());
+        }
+        step.kind.decode_reply(&step.reply)
+    }
+
+    /// Call method_name of the object class class_name with input.
+    /// Returns the step number to pass to exec_result.
+    pub fn exec(
+        &mut self,
+        class_name: &str,
+        method_name: &str,
+        input: &[u8],
+    ) -> RadosResult<usize> {
+        let class_name_str = CString::new(class_name)?;
+        let method_name_str = CString::new(method_name)?;
+        let mut step = ExecStep {
+            buf: Box::new(ptr::null_mut()),
+            len: Box::new(0),
+            prval: Box::new(0),
+        };
+        unsafe {
+            rados_read_op_exec(
+                self.read_op_handle,
+                class_name_str.as_ptr(),
+                method_name_str.as_ptr(),
+                input.as_ptr() as *const c_char,
+                input.len(),
+                &mut *step.buf,
+                &mut *step.len,
+                &mut *step.prval,
+            );
+        }
+        self.execs.push(step);
+        Ok(self.execs.len() - 1)
+    }
+
+    /// The reply of an exec step
+    pub fn exec_result(&self, step: usize) -> RadosResult<Vec<u8>> {
+        let step = self
+            .execs
+            .get(step)
+            .ok_or_else(|| RadosError::new(format!("No exec step {}", step)))?;
+        if *step.prval < 0 {
+            return Err((*step.prval).into());
+        }
+        if step.buf.is_null() {
+            return Ok(Vec::new());
+        }
+        let reply = unsafe { slice::from_raw_parts(*step.buf as *const u8, *step.len) };
+        Ok(reply.to_vec())
+    }
+
+    /// Compare the object's data at offset with cmp_buf.  If they differ the
+    /// whole operation fails with RadosError::CompareMismatch.
+    pub fn cmpext(&mut self, cmp_buf: &[u8], offset: u64) {
+        unsafe {
+            rados_read_op_cmpext(
+                self.read_op_handle,
+                cmp_buf.as_ptr() as *const c_char,
+                cmp_buf.len(),
+                offset,
+                ptr::null_mut(),
+            );
+        }
+    }
+}
+
 /// A helper to create rados write operation
 /// An object write operation stores a number of operations which can be
 /// executed atomically.
@@ -266,6 +445,220 @@ impl Drop for WriteOperation {
     }
 }
 
+impl WriteOperation {
+    /// Create an empty write operation on an object.  Add steps to it and
+    /// then run them all atomically with IoCtx::rados_commit_write_operations.
+    /// An mtime of 0 lets the OSD use the current time.
+    pub fn new(object_name: &str) -> WriteOperation {
+        WriteOperation {
+            object_name: object_name.to_string(),
+            flags: 0,
+            mtime: 0,
+            write_op_handle: unsafe { rados_create_write_op() },
+        }
+    }
+
+    /// Create the object.  With exclusive set the operation fails if the
+    /// object already exists.
+    pub fn create(&mut self, exclusive: bool) {
+        unsafe {
+            rados_write_op_create(self.write_op_handle, exclusive as c_int, ptr::null());
+        }
+    }
+
+    /// Write buffer into the object at offset.
+    pub fn write(&mut self, buffer: &[u8], offset: u64) {
+        unsafe {
+            rados_write_op_write(
+                self.write_op_handle,
+                buffer.as_ptr() as *const c_char,
+                buffer.len(),
+                offset,
+            );
+        }
+    }
+
+    /// Replace the object's contents with buffer.
+    pub fn write_full(&mut self, buffer: &[u8]) {
+        unsafe {
+            rados_write_op_write_full(
+                self.write_op_handle,
+                buffer.as_ptr() as *const c_char,
+                buffer.len(),
+            );
+        }
+    }
+
+    /// Resize the object to offset bytes.
+    pub fn truncate(&mut self, offset: u64) {
+        unsafe {
+            rados_write_op_truncate(self.write_op_handle, offset);
+        }
+    }
+
+    /// Write pattern repeatedly to fill write_len bytes starting at offset.
+    /// write_len must be a multiple of the pattern's length.
+    pub fn writesame(&mut self, pattern: &[u8], write_len: usize, offset: u64) {
+        unsafe {
+            rados_write_op_writesame(
+                self.write_op_handle,
+                pattern.as_ptr() as *const c_char,
+                pattern.len(),
+                write_len,
+                offset,
+            );
+        }
+    }
+
+    /// Zero len bytes starting at offset.  The object is not extended: any
+    /// part of the range past the end is ignored.
+    pub fn zero(&mut self, offset: u64, len: u64) {
+        unsafe {
+            rados_write_op_zero(self.write_op_handle, offset, len);
+        }
+    }
+
+    /// Set an extended attribute on the object.
+    pub fn setxattr(&mut self, name: &str, value: &[u8]) -> RadosResult<()> {
+        let name_str = CString::new(name)?;
+        unsafe {
+            rados_write_op_setxattr(
+                self.write_op_handle,
+                name_str.as_ptr(),
+                value.as_ptr() as *const c_char,
+                value.len(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Set key/value pairs in the object's omap.
+    pub fn omap_set(&mut self, values: &[(String, Vec<u8>)]) -> RadosResult<()> {
+        let keys: Vec<CString> = values
+            .iter()
+            .map(|(key, _)| CString::new(key.as_str()))
+            .collect::<Result<_, _>>()?;
+        let key_ptrs: Vec<*const c_char> = keys.iter().map(|k| k.as_ptr()).collect();
+        let val_ptrs: Vec<*const c_char> = values
+            .iter()
+            .map(|(_, val)| val.as_ptr() as *const c_char)
+            .collect();
+        let lens: Vec<size_t> = values.iter().map(|(_, val)| val.len()).collect();
+        unsafe {
+            rados_write_op_omap_set(
+                self.write_op_handle,
+                key_ptrs.as_ptr(),
+                val_ptrs.as_ptr(),
+                lens.as_ptr(),
+                values.len(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Copy the data, xattrs and omap of another object on the OSD.
+    /// The source may be in a different pool.  A src_version of 0 copies
+    /// whatever version is current.  Requires Octopus or later.
+    pub fn copy_from(
+        &mut self,
+        src_ioctx: &IoCtx,
+        src_object_name: &str,
+        src_version: u64,
+    ) -> RadosResult<()> {
+        src_ioctx.ioctx_guard()?;
+        let src_name_str = CString::new(src_object_name)?;
+        unsafe {
+            rados_write_op_copy_from(
+                self.write_op_handle,
+                src_name_str.as_ptr(),
+                src_ioctx.ioctx,
+                src_version,
+                0,
+            );
+        }
+        Ok(())
+    }
+
+    /// Call method_name of the object class class_name with input.  Any
+    /// reply from the method is discarded.
+    pub fn exec(&mut self, class_name: &str, method_name: &str, input: &[u8]) -> RadosResult<()> {
+        let class_name_str = CString::new(class_name)?;
+        let method_name_str = CString::new(method_name)?;
+        unsafe {
+            rados_write_op_exec(
+                self.write_op_handle,
+                class_name_str.as_ptr(),
+                method_name_str.as_ptr(),
+                input.as_ptr() as *const c_char,
+                input.len(),
+                ptr::null_mut(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Compare the object's data at offset with cmp_buf.  If they differ
+    /// none of the operation's writes are applied and it fails with
+    /// RadosError::CompareMismatch.
+    pub fn cmpext(&mut self, cmp_buf: &[u8], offset: u64) {
+        unsafe {
+            rados_write_op_cmpext(
+                self.write_op_handle,
+                cmp_buf.as_ptr() as *const c_char,
+                cmp_buf.len(),
+                offset,
+                ptr::null_mut(),
+            );
+        }
+    }
+}
+
+/// An asynchronous operation in flight.  Dropping it doesn't cancel the
+/// operation, the result is just discarded.
+#[derive(Debug)]
+pub struct AioCompletion {
+    completion: rados_completion_t,
+}
+
+impl Drop for AioCompletion {
+    fn drop(&mut self) {
+        unsafe {
+            rados_aio_release(self.completion);
+        }
+    }
+}
+
+impl AioCompletion {
+    fn new() -> RadosResult<AioCompletion> {
+        let mut completion: rados_completion_t = ptr::null_mut();
+        unsafe {
+            let ret_code =
+                rados_aio_create_completion(ptr::null_mut(), None, None, &mut completion);
+            if ret_code < 0 {
+                return Err(ret_code.into());
+            }
+        }
+        Ok(AioCompletion { completion })
+    }
+
+    /// Whether the operation has finished
+    pub fn is_complete(&self) -> bool {
+        unsafe { rados_aio_is_complete(self.completion) != 0 }
+    }
+
+    /// Block until the operation has finished and return its result
+    pub fn wait(self) -> RadosResult<()> {
+        unsafe {
+            rados_aio_wait_for_complete(self.completion);
+            let ret_code = rados_aio_get_return_value(self.completion);
+            if ret_code < 0 {
+                return Err(ret_code.into());
+            }
+        }
+        Ok(())
+    }
+}
+
 /// A rados object extended attribute with name and value.
 /// Can be iterated over
 #[derive(Debug)]
@@ -335,6 +728,8 @@ impl Iterator for XAttr {
 pub struct IoCtx {
     ioctx: rados_ioctx_t,
     _rados: Arc<Rados>,
+    // librados has no getter for the locator key
+    locator_key: Mutex<String>,
 }
 
 unsafe impl Send for IoCtx {}
@@ -371,8 +766,11 @@ impl Drop for RadosStriper {
 pub struct Rados {
     rados: rados_t,
     phantom: PhantomData<IoCtx>,
+    /// Set while a ClusterLogWatch holds the handle's log callback
+    pub(crate) log_watch: AtomicBool,
 }
 
+unsafe impl Send for Rados {}
 unsafe impl Sync for Rados {}
 
 impl Drop for Rados {
@@ -387,30 +785,278 @@ impl Drop for Rados {
 
 /// Connect to a Ceph cluster and return a connection handle rados_t
 pub fn connect_to_ceph(user_id: &str, config_file: &str) -> RadosResult<Rados> {
-    let connect_id = CString::new(user_id)?;
-    let conf_file = CString::new(config_file)?;
-    unsafe {
-        let mut cluster_handle: rados_t = ptr::null_mut();
-        let ret_code = rados_create(&mut cluster_handle, connect_id.as_ptr());
-        if ret_code < 0 {
-            return Err(ret_code.into());
+    RadosBuilder::new(user_id)
+        .with_config_file(config_file)
+        .connect()
+}
+
+/// Connection settings for a cluster, for when a config file path isn't
+/// enough, e.g. in a container with only the mon addresses and a key:
+///
+/// ```no_run
+/// # fn main() -> ceph::error::RadosResult<()> {
+/// let cluster = ceph::ceph::RadosBuilder::new("admin")
+///     .with_mon_host("10.0.0.1,10.0.0.2")
+///     .with_key("AQBS2hFjAAAAABAAp7eE4pVxE3T9UW8uE6N4Fg==")
+///     .with_mon_op_timeout(std::time::Duration::from_secs(30))
+///     .connect()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Settings are applied in order: the config file or `with_conf`, then the
+/// environment, then command line arguments and then the options set here,
+/// so the later ones win.
+#[derive(Clone, Debug)]
+pub struct RadosBuilder {
+    cluster_name: String,
+    name: String,
+    flags: u64,
+    config_file: Option<String>,
+    conf: Option<crate::config::CephConf>,
+    env_var: Option<Option<String>>,
+    argv: Option<Vec<String>>,
+    options: Vec<(String, String)>,
+}
+
+/// Timeout options are parsed as whole seconds, so 1.5s is sent as 2 and
+/// anything under a second as 1.  Zero stays zero.
+pub(crate) fn timeout_secs(timeout: Duration) -> String {
+    let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+    secs.to_string()
+}
+
+impl RadosBuilder {
+    /// Connect as client.user_id to the cluster named "ceph"
+    pub fn new(user_id: &str) -> RadosBuilder {
+        RadosBuilder {
+            cluster_name: "ceph".to_string(),
+            name: format!("client.{}", user_id),
+            flags: 0,
+            config_file: None,
+            conf: None,
+            env_var: None,
+            argv: None,
+            options: Vec::new(),
         }
-        // Instantiate Rados struct to call shutdown on drop.
-        // Doc specifies that it's not necessary to call rados_shutdown if
-        // rados_connect hasn't run, but that seems incorrect.
-        let rados = Rados {
-            rados: cluster_handle,
-            phantom: PhantomData,
-        };
-        let ret_code = rados_conf_read_file(cluster_handle, conf_file.as_ptr());
-        if ret_code < 0 {
-            return Err(ret_code.into());
+    }
+
+    /// The cluster name, which picks the default config file and keyring
+    /// paths such as /etc/ceph/<name>.conf
+    pub fn with_cluster_name(mut self, cluster_name: &str) -> RadosBuilder {
+        self.cluster_name = cluster_name.to_string();
+        self
+    }
+
+    /// Flags passed to rados_create2
+    pub fn with_flags(mut self, flags: u64) -> RadosBuilder {
+        self.flags = flags;
+        self
+    }
+
+    /// Read a config file.  An empty path searches the default locations.
+    pub fn with_config_file(mut self, config_file: &str) -> RadosBuilder {
+        self.config_file = Some(config_file.to_string());
+        self
+    }
+
+    /// Use the options a parsed config file gives this client, in place of
+    /// reading a file.  Options librados doesn't know are skipped, as when
+    /// it reads a file itself.  Metavariables like `$cluster` are expanded
+    /// on connect, so the cluster name can be set before or after.
+    pub fn with_conf(mut self, conf: &crate::config::CephConf) -> RadosBuilder {
+        self.conf = Some(conf.clone());
+        self
+    }
+
+    /// Use this client's secret from a parsed keyring, in place of reading a
+    /// keyring file.  Does nothing if the keyring has no entry for it.
+    pub fn with_key_from(self, keyring: &crate::config::Keyring) -> RadosBuilder {
+        match keyring.key(&self.name) {
+            Some(key) => {
+                let key = key.to_string();
+                self.with_key(&key)
+            }
+            None => self,
         }
-        let ret_code = rados_connect(cluster_handle);
-        if ret_code < 0 {
-            return Err(ret_code.into());
+    }
+
+    /// Parse arguments from the CEPH_ARGS environment variable
+    pub fn with_env(mut self) -> RadosBuilder {
+        self.env_var = Some(None);
+        self
+    }
+
+    /// Parse arguments from another environment variable
+    pub fn with_env_var(mut self, var: &str) -> RadosBuilder {
+        self.env_var = Some(Some(var.to_string()));
+        self
+    }
+
+    /// Parse command line arguments such as `--mon-host`.  As with
+    /// `std::env::args()` the first one is the program name and is skipped.
+    pub fn with_args<I, S>(mut self, args: I) -> RadosBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.argv = Some(args.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Set any config option with rados_conf_set
+    pub fn with_option(mut self, name: &str, value: &str) -> RadosBuilder {
+        self.options.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Comma separated mon addresses
+    pub fn with_mon_host(self, mon_host: &str) -> RadosBuilder {
+        self.with_option("mon_host", mon_host)
+    }
+
+    /// The base64 secret key, instead of reading a keyring
+    pub fn with_key(self, key: &str) -> RadosBuilder {
+        self.with_option("key", key)
+    }
+
+    /// Path to a keyring file
+    pub fn with_keyring(self, keyring: &str) -> RadosBuilder {
+        self.with_option("keyring", keyring)
+    }
+
+    /// How long connect() waits for the mons.  Rounded up to whole seconds.
+    pub fn with_client_mount_timeout(self, timeout: Duration) -> RadosBuilder {
+        self.with_option("client_mount_timeout", &timeout_secs(timeout))
+    }
+
+    /// How long an OSD operation can take before failing with ETIMEDOUT.
+    /// Rounded up to whole seconds.  Zero waits forever.
+    pub fn with_osd_op_timeout(self, timeout: Duration) -> RadosBuilder {
+        self.with_option("rados_osd_op_timeout", &timeout_secs(timeout))
+    }
+
+    /// How long a mon command can take before failing with ETIMEDOUT.
+    /// Rounded up to whole seconds.  Zero waits forever.
+    pub fn with_mon_op_timeout(self, timeout: Duration) -> RadosBuilder {
+        self.with_option("rados_mon_op_timeout", &timeout_secs(timeout))
+    }
+
+    /// Create the handle, apply the settings and connect.  The builder can be
+    /// reused to connect again.
+    pub fn connect(&self) -> RadosResult<Rados> {
+        let cluster_name = CString::new(self.cluster_name.as_str())?;
+        let name = CString::new(self.name.as_str())?;
+        let config_file = match &self.config_file {
+            Some(path) => Some(CString::new(path.as_str())?),
+            None => None,
+        };
+        let env_var = match &self.env_var {
+            Some(Some(var)) => Some(Some(CString::new(var.as_str())?)),
+            Some(None) => Some(None),
+            None => None,
+        };
+        let argv = match &self.argv {
+            Some(args) => Some(
+                args.iter()
+                    .map(|a| CString::new(a.as_str()))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+        let c_options = |options: &[(String, String)]| -> RadosResult<Vec<(CString, CString)>> {
+            let mut c_options = Vec::with_capacity(options.len());
+            for (option, value) in options {
+                c_options.push((
+                    CString::new(option.as_str())?,
+                    CString::new(value.as_str())?,
+                ));
+            }
+            Ok(c_options)
+        };
+        let conf_options: Vec<(String, String)> = match &self.conf {
+            Some(conf) => conf
+                .options_for(&self.cluster_name, &self.name)
+                .into_iter()
+                .collect(),
+            None => Vec::new(),
+        };
+        let conf_options = c_options(&conf_options)?;
+        let options = c_options(&self.options)?;
+
+        unsafe {
+            let mut cluster_handle: rados_t = ptr::null_mut();
+            let ret_code = rados_create2(
+                &mut cluster_handle,
+                cluster_name.as_ptr(),
+                name.as_ptr(),
+                self.flags,
+            );
+            if ret_code < 0 {
+                return Err(ret_code.into());
+            }
+            // Instantiate Rados struct to call shutdown on drop.
+            // Doc specifies that it's not necessary to call rados_shutdown if
+            // rados_connect hasn't run, but that seems incorrect.
+            let rados = Rados {
+                rados: cluster_handle,
+                phantom: PhantomData,
+                log_watch: AtomicBool::new(false),
+            };
+            if let Some(config_file) = &config_file {
+                let path = if config_file.as_bytes().is_empty() {
+                    ptr::null()
+                } else {
+                    config_file.as_ptr()
+                };
+                let ret_code = rados_conf_read_file(cluster_handle, path);
+                if ret_code < 0 {
+                    return Err(ret_code.into());
+                }
+            }
+            for (option, value) in &conf_options {
+                let ret_code = rados_conf_set(cluster_handle, option.as_ptr(), value.as_ptr());
+                if ret_code < 0 && ret_code != -ENOENT {
+                    return Err(RadosError::new(format!(
+                        "Unable to set {} to {:?}: {}",
+                        option.to_string_lossy(),
+                        value.to_string_lossy(),
+                        RadosError::from(ret_code)
+                    )));
+                }
+            }
+            if let Some(env_var) = &env_var {
+                let var = env_var.as_ref().map_or(ptr::null(), |v| v.as_ptr());
+                let ret_code = rados_conf_parse_env(cluster_handle, var);
+                if ret_code < 0 {
+                    return Err(ret_code.into());
+                }
+            }
+            if let Some(argv) = &argv {
+                let mut ptrs: Vec<*const c_char> = argv.iter().map(|a| a.as_ptr()).collect();
+                let ret_code =
+                    rados_conf_parse_argv(cluster_handle, ptrs.len() as c_int, ptrs.as_mut_ptr());
+                if ret_code < 0 {
+                    return Err(ret_code.into());
+                }
+            }
+            for (option, value) in &options {
+                let ret_code = rados_conf_set(cluster_handle, option.as_ptr(), value.as_ptr());
+                if ret_code < 0 {
+                    return Err(RadosError::new(format!(
+                        "Unable to set {} to {:?}: {}",
+                        option.to_string_lossy(),
+                        value.to_string_lossy(),
+                        RadosError::from(ret_code)
+                    )));
+                }
+            }
+            let ret_code = rados_connect(cluster_handle);
+            if ret_code < 0 {
+                return Err(ret_code.into());
+            }
+            Ok(rados)
         }
-        Ok(rados)
     }
 }
 
@@ -498,6 +1144,7 @@ impl Rados {
             Ok(IoCtx {
                 ioctx,
                 _rados: self.clone(),
+                locator_key: Mutex::new(String::new()),
             })
         }
     }
@@ -516,6 +1163,7 @@ impl Rados {
             Ok(IoCtx {
                 ioctx,
                 _rados: self.clone(),
+                locator_key: Mutex::new(String::new()),
             })
         }
     }
@@ -661,9 +1309,15 @@ impl IoCtx {
         unsafe {
             rados_ioctx_locator_set_key(self.ioctx, key_str.as_ptr());
         }
+        *self.locator_key.lock().unwrap() = key.to_string();
         Ok(())
     }
 
+    /// The locator key set with `rados_locator_set_key`, empty if none
+    pub fn rados_get_locator_key(&self) -> String {
+        self.locator_key.lock().unwrap().clone()
+    }
+
     /// Set the namespace for objects within an io context
     /// The namespace specification further refines a pool into different
     /// domains. The mapping of objects to pgs is also based on this value.
@@ -676,6 +1330,33 @@ impl IoCtx {
         Ok(())
     }
 
+    /// Get the namespace of the io context, empty for the default one
+    pub fn rados_get_namespace(&self) -> RadosResult<String> {
+        self.ioctx_guard()?;
+        let mut buffer: Vec<u8> = Vec::with_capacity(256);
+        loop {
+            // length of string stored, or -ERANGE if buffer too small
+            let ret_code = unsafe {
+                rados_ioctx_get_namespace(
+                    self.ioctx,
+                    buffer.as_mut_ptr() as *mut c_char,
+                    buffer.capacity() as c_uint,
+                )
+            };
+            // Namespaces are short, so a huge one means something is wrong
+            if ret_code == -ERANGE && buffer.capacity() < 65536 {
+                let capacity = buffer.capacity();
+                buffer.reserve(capacity * 2);
+                continue;
+            }
+            if ret_code < 0 {
+                return Err(ret_code.into());
+            }
+            unsafe { buffer.set_len(ret_code as usize) };
+            return Ok(String::from_utf8_lossy(&buffer).into_owned());
+        }
+    }
+
     /// Start listing objects in a pool
     pub fn rados_list_pool_objects(&self) -> RadosResult<rados_list_ctx_t> {
         self.ioctx_guard()?;
@@ -945,10 +1626,55 @@ impl IoCtx {
         Ok(())
     }
 
-    /// Efficiently copy a portion of one object to another
-    /// If the underlying filesystem on the OSD supports it, this will be a
-    /// copy-on-write clone.
-    /// The src and dest objects must be in the same pg. To ensure this, the io
+    /// Fill total_len bytes of an object starting at offset with repeated
+    /// copies of pattern.  Only the pattern is sent to the OSD.
+    /// total_len must be a multiple of the pattern's length.
+    pub fn writesame(
+        &self,
+        object_name: &str,
+        pattern: &[u8],
+        total_len: usize,
+        offset: u64,
+    ) -> RadosResult<()> {
+        self.ioctx_guard()?;
+        if pattern.is_empty() || !total_len.is_multiple_of(pattern.len()) {
+            return Err(RadosError::new(format!(
+                "writesame length {} is not a multiple of the {} byte pattern",
+                total_len,
+                pattern.len()
+            )));
+        }
+        let obj_name_str = CString::new(object_name)?;
+
+        unsafe {
+            let ret_code = rados_writesame(
+                self.ioctx,
+                obj_name_str.as_ptr(),
+                pattern.as_ptr() as *const c_char,
+                pattern.len(),
+                total_len,
+                offset,
+            );
+            if ret_code < 0 {
+                return Err(ret_code.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Zero len bytes of an object starting at offset without sending any
+    /// data.  The OSD may deallocate the range instead of writing zeros.  The
+    /// object is not extended: any part of the range past the end is ignored.
+    pub fn zero(&self, object_name: &str, offset: u64, len: u64) -> RadosResult<()> {
+        let mut op = WriteOperation::new(object_name);
+        op.zero(offset, len);
+        self.rados_commit_write_operations(&mut op)
+    }
+
+    /// Efficiently copy a portion of one object to another
+    /// If the underlying filesystem on the OSD supports it, this will be a
+    /// copy-on-write clone.
+    /// The src and dest objects must be in the same pg. To ensure this, the io
     /// context should
     /// have a locator key set (see rados_ioctx_locator_set_key()).
     pub fn rados_object_clone_range(
@@ -998,6 +1724,77 @@ impl IoCtx {
         Ok(())
     }
 
+    /// Compare the object's data at offset with cmp_buf.
+    /// Returns RadosError::CompareMismatch with the offset of the first
+    /// differing byte if they don't match.
+    pub fn rados_object_cmpext(
+        &self,
+        object_name: &str,
+        cmp_buf: &[u8],
+        offset: u64,
+    ) -> RadosResult<()> {
+        self.ioctx_guard()?;
+        let obj_name_str = CString::new(object_name)?;
+
+        unsafe {
+            let ret_code = rados_cmpext(
+                self.ioctx,
+                obj_name_str.as_ptr(),
+                cmp_buf.as_ptr() as *const c_char,
+                cmp_buf.len(),
+                offset,
+            );
+            if ret_code < 0 {
+                return Err(ret_code.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Asynchronous version of rados_object_cmpext.  cmp_buf is copied
+    /// before this returns.
+    pub fn rados_async_object_cmpext(
+        &self,
+        object_name: &str,
+        cmp_buf: &[u8],
+        offset: u64,
+    ) -> RadosResult<AioCompletion> {
+        self.ioctx_guard()?;
+        let obj_name_str = CString::new(object_name)?;
+        let completion = AioCompletion::new()?;
+
+        unsafe {
+            let ret_code = rados_aio_cmpext(
+                self.ioctx,
+                obj_name_str.as_ptr(),
+                completion.completion,
+                cmp_buf.as_ptr() as *const c_char,
+                cmp_buf.len(),
+                offset,
+            );
+            if ret_code < 0 {
+                return Err(ret_code.into());
+            }
+        }
+        Ok(completion)
+    }
+
+    /// Write buffer at write_offset only if the object holds cmp_buf at
+    /// cmp_offset.  The comparison and the write happen atomically.
+    pub fn rados_object_compare_and_write(
+        &self,
+        object_name: &str,
+        cmp_buf: &[u8],
+        cmp_offset: u64,
+        buffer: &[u8],
+        write_offset: u64,
+    ) -> RadosResult<()> {
+        let mut op = WriteOperation::new(object_name);
+        op.cmpext(cmp_buf, cmp_offset);
+        op.write(buffer, write_offset);
+        self.rados_commit_write_operations(&mut op)
+    }
+
     /// Read data from an object.  This fills the slice given and returns the
     /// amount of bytes read
     /// The io context determines the snapshot to read from, if any was set by
@@ -1248,6 +2045,8 @@ impl IoCtx {
     /// methods. The input and output formats are defined by the class. Classes
     /// in ceph.git can
     /// be found in src/cls subdirectories
+    /// The reply must fit in output_buffer; use exec when its length isn't
+    /// known up front.
     pub fn rados_object_exec(
         &self,
         object_name: &str,
@@ -1279,6 +2078,21 @@ impl IoCtx {
         Ok(())
     }
 
+    /// Call method_name of the object class class_name on an object and
+    /// return its reply, however long it is.
+    pub fn exec(
+        &self,
+        object_name: &str,
+        class_name: &str,
+        method_name: &str,
+        input: &[u8],
+    ) -> RadosResult<Vec<u8>> {
+        let mut read_op = ReadOperation::new(object_name);
+        let step = read_op.exec(class_name, method_name, input)?;
+        self.rados_perform_read_operations(&mut read_op)?;
+        read_op.exec_result(step)
+    }
+
     /// Sychronously notify watchers of an object
     /// This blocks until all watchers of the object have received and reacted
     /// to the notify, or a timeout is reached.
@@ -1380,8 +2194,9 @@ impl IoCtx {
         Ok(())
     }
 
-    // Perform a compound read operation synchronously
-    pub fn rados_perform_read_operations(&self, read_op: ReadOperation) -> RadosResult<()> {
+    // Perform a compound read operation synchronously.  Step results can be
+    // collected from read_op afterwards.
+    pub fn rados_perform_read_operations(&self, read_op: &mut ReadOperation) -> RadosResult<()> {
         self.ioctx_guard()?;
         let object_name_str = CString::new(read_op.object_name.clone())?;
 
@@ -1409,7 +2224,11 @@ impl IoCtx {
                 write_op.write_op_handle,
                 self.ioctx,
                 object_name_str.as_ptr(),
-                &mut write_op.mtime,
+                if write_op.mtime == 0 {
+                    ptr::null_mut()
+                } else {
+                    &mut write_op.mtime
+                },
                 write_op.flags as i32,
             );
             if ret_code < 0 {
@@ -1590,6 +2409,303 @@ impl IoCtx {
     }
 }
 
+impl IoCtx {
+    /// Open a buffered handle to an object that implements std::io::Read,
+    /// Write and Seek.  See RadosObject for the available options.
+    pub fn open(&self, object_name: &str) -> RadosObject<'_> {
+        RadosObject::new(self, object_name)
+    }
+
+    /// Copy a local file into an object in chunks, several at a time.
+    /// Returns the number of bytes in the object.  See TransferOptions for
+    /// chunking, progress reporting and resuming.
+    pub fn upload_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+        object_name: &str,
+        opts: TransferOptions,
+    ) -> RadosResult<u64> {
+        self.ioctx_guard()?;
+        transfer::upload_file(self, path.as_ref(), object_name, opts)
+    }
+
+    /// Copy an object into a local file in chunks, several at a time.
+    /// Returns the number of bytes in the file.  See TransferOptions for
+    /// chunking, progress reporting and resuming.
+    pub fn download_to_file<P: AsRef<Path>>(
+        &self,
+        object_name: &str,
+        path: P,
+        opts: TransferOptions,
+    ) -> RadosResult<u64> {
+        self.ioctx_guard()?;
+        transfer::download_to_file(self, object_name, path.as_ref(), opts)
+    }
+
+    /// Read the entire contents of an object.
+    /// The object is stat'd first and then read in chunks of at most
+    /// MAX_READ_CHUNK bytes until its full size has been returned.
+    pub fn rados_object_read_full(&self, object_name: &str) -> RadosResult<Vec<u8>> {
+        self.ioctx_guard()?;
+        let (size, _) = self.rados_object_stat(object_name)?;
+        let mut buffer: Vec<u8> = Vec::with_capacity(size as usize);
+        while (buffer.len() as u64) < size {
+            let remaining = size - buffer.len() as u64;
+            let mut chunk: Vec<u8> = Vec::with_capacity(remaining.min(MAX_READ_CHUNK) as usize);
+            let read = self.rados_object_read(object_name, &mut chunk, buffer.len() as u64)?;
+            if read == 0 {
+                // The object was truncated underneath us
+                break;
+            }
+            buffer.extend_from_slice(&chunk);
+        }
+        Ok(buffer)
+    }
+
+    /// Get the full value of an extended attribute on an object.
+    /// The buffer is grown until Ceph stops returning -ERANGE, up to 4 MiB.
+    /// Larger values are an error.
+    pub fn rados_object_getxattr_full(
+        &self,
+        object_name: &str,
+        attr_name: &str,
+    ) -> RadosResult<Vec<u8>> {
+        let mut buffer: Vec<u8> = vec![0; 4096];
+        loop {
+            match self.rados_object_getxattr(object_name, attr_name, &mut buffer) {
+                Ok(len) => {
+                    buffer.truncate(len as usize);
+                    return Ok(buffer);
+                }
+                Err(RadosError::ApiError(nix::errno::Errno::ERANGE)) => {
+                    if buffer.len() >= MAX_XATTR_SIZE {
+                        return Err(RadosError::new(format!(
+                            "xattr {} on {} is larger than {} bytes",
+                            attr_name, object_name, MAX_XATTR_SIZE
+                        )));
+                    }
+                    let len = (buffer.len() * 2).min(MAX_XATTR_SIZE);
+                    buffer.resize(len, 0);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Get every extended attribute on an object with its raw value.
+    pub fn rados_object_getxattrs(&self, object_name: &str) -> RadosResult<Vec<(String, Vec<u8>)>> {
+        let iter = self.rados_get_xattr_iterator(object_name)?;
+        let mut xattrs: Vec<(String, Vec<u8>)> = Vec::new();
+        unsafe {
+            loop {
+                let mut name: *const c_char = ptr::null();
+                let mut value: *const c_char = ptr::null();
+                let mut val_length: usize = 0;
+                let ret_code = rados_getxattrs_next(iter, &mut name, &mut value, &mut val_length);
+                if ret_code < 0 {
+                    rados_getxattrs_end(iter);
+                    return Err(ret_code.into());
+                }
+                // end of iterator reached
+                if name.is_null() {
+                    break;
+                }
+                let bytes = if value.is_null() {
+                    Vec::new()
+                } else {
+                    std::slice::from_raw_parts(value as *const u8, val_length).to_vec()
+                };
+                xattrs.push((CStr::from_ptr(name).to_string_lossy().into_owned(), bytes));
+            }
+            rados_getxattrs_end(iter);
+        }
+        Ok(xattrs)
+    }
+
+    /// Get every key/value pair in an object's omap.
+    /// Values are fetched OMAP_PAGE_SIZE keys at a time.
+    pub fn rados_object_omap_get_all(
+        &self,
+        object_name: &str,
+    ) -> RadosResult<Vec<(String, Vec<u8>)>> {
+        self.ioctx_guard()?;
+        let object_name_str = CString::new(object_name)?;
+        let filter_prefix = CString::new("")?;
+        let mut values: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut start_after = CString::new("")?;
+        loop {
+            let mut count: u64 = 0;
+            unsafe {
+                let read_op = rados_create_read_op();
+                let mut iter: rados_omap_iter_t = ptr::null_mut();
+                let mut prval: c_int = 0;
+                rados_read_op_omap_get_vals(
+                    read_op,
+                    start_after.as_ptr(),
+                    filter_prefix.as_ptr(),
+                    OMAP_PAGE_SIZE,
+                    &mut iter,
+                    &mut prval,
+                );
+                let ret_code =
+                    rados_read_op_operate(read_op, self.ioctx, object_name_str.as_ptr(), 0);
+                if ret_code < 0 || prval < 0 {
+                    if !iter.is_null() {
+                        rados_omap_get_end(iter);
+                    }
+                    rados_release_read_op(read_op);
+                    return Err(if ret_code < 0 { ret_code } else { prval }.into());
+                }
+                loop {
+                    let mut key: *mut c_char = ptr::null_mut();
+                    let mut val: *mut c_char = ptr::null_mut();
+                    let mut len: size_t = 0;
+                    let ret_code = rados_omap_get_next(iter, &mut key, &mut val, &mut len);
+                    if ret_code < 0 {
+                        rados_omap_get_end(iter);
+                        rados_release_read_op(read_op);
+                        return Err(ret_code.into());
+                    }
+                    // end of iterator reached
+                    if key.is_null() {
+                        break;
+                    }
+                    let bytes = if val.is_null() {
+                        Vec::new()
+                    } else {
+                        std::slice::from_raw_parts(val as *const u8, len).to_vec()
+                    };
+                    values.push((CStr::from_ptr(key).to_string_lossy().into_owned(), bytes));
+                    count += 1;
+                }
+                rados_omap_get_end(iter);
+                rados_release_read_op(read_op);
+            }
+            match values.last() {
+                Some((key, _)) if count == OMAP_PAGE_SIZE => {
+                    start_after = CString::new(key.as_str())?
+                }
+                _ => break,
+            }
+        }
+        Ok(values)
+    }
+
+    /// Copy an object, with its xattrs and omap, to another name, namespace
+    /// or pool.  Returns how the copy was made.  See CopyOptions for
+    /// preserving mtime and moving instead of copying.
+    pub fn copy_object(
+        &self,
+        src_object_name: &str,
+        dst_ioctx: &IoCtx,
+        dst_object_name: &str,
+        opts: &CopyOptions,
+    ) -> RadosResult<CopyMethod> {
+        self.ioctx_guard()?;
+        dst_ioctx.ioctx_guard()?;
+        copy::copy_object(self, src_object_name, dst_ioctx, dst_object_name, opts)
+    }
+
+    /// Check an object against local data without reading the object back.
+    /// The OSD computes an xxhash64 of every 4MiB chunk and the same is done
+    /// over local, so only the checksums cross the network.
+    pub fn verify_against<R: Read>(
+        &self,
+        object_name: &str,
+        local: R,
+    ) -> RadosResult<Verification> {
+        self.verify_against_with(
+            object_name,
+            local,
+            ChecksumType::XxHash64,
+            DEFAULT_VERIFY_CHUNK_SIZE,
+        )
+    }
+
+    /// Like verify_against with a choice of checksum and chunk size.
+    pub fn verify_against_with<R: Read>(
+        &self,
+        object_name: &str,
+        local: R,
+        kind: ChecksumType,
+        chunk_size: usize,
+    ) -> RadosResult<Verification> {
+        self.ioctx_guard()?;
+        checksum::verify_against(self, object_name, local, kind, chunk_size)
+    }
+
+    /// Read an object and decode its contents as JSON.
+    pub fn get_typed<T: DeserializeOwned>(&self, object_name: &str) -> RadosResult<T> {
+        self.get_typed_with::<JsonCodec, T>(object_name)
+    }
+
+    /// Read an object and decode its contents with the given codec.
+    pub fn get_typed_with<C: Codec, T: DeserializeOwned>(
+        &self,
+        object_name: &str,
+    ) -> RadosResult<T> {
+        let buffer = self.rados_object_read_full(object_name)?;
+        C::decode(&buffer)
+    }
+
+    /// Encode a value as JSON and replace the object's contents with it.
+    pub fn put_typed<T: Serialize>(&self, object_name: &str, value: &T) -> RadosResult<()> {
+        self.put_typed_with::<JsonCodec, T>(object_name, value)
+    }
+
+    /// Encode a value with the given codec and replace the object's contents
+    /// with it.
+    pub fn put_typed_with<C: Codec, T: Serialize>(
+        &self,
+        object_name: &str,
+        value: &T,
+    ) -> RadosResult<()> {
+        let buffer = C::encode(value)?;
+        self.rados_object_write_full(object_name, &buffer)
+    }
+
+    /// Get an extended attribute and decode its value as JSON.
+    pub fn get_xattr_typed<T: DeserializeOwned>(
+        &self,
+        object_name: &str,
+        attr_name: &str,
+    ) -> RadosResult<T> {
+        self.get_xattr_typed_with::<JsonCodec, T>(object_name, attr_name)
+    }
+
+    /// Get an extended attribute and decode its value with the given codec.
+    pub fn get_xattr_typed_with<C: Codec, T: DeserializeOwned>(
+        &self,
+        object_name: &str,
+        attr_name: &str,
+    ) -> RadosResult<T> {
+        let buffer = self.rados_object_getxattr_full(object_name, attr_name)?;
+        C::decode(&buffer)
+    }
+
+    /// Encode a value as JSON and store it in an extended attribute.
+    pub fn set_xattr_typed<T: Serialize>(
+        &self,
+        object_name: &str,
+        attr_name: &str,
+        value: &T,
+    ) -> RadosResult<()> {
+        self.set_xattr_typed_with::<JsonCodec, T>(object_name, attr_name, value)
+    }
+
+    /// Encode a value with the given codec and store it in an extended
+    /// attribute.
+    pub fn set_xattr_typed_with<C: Codec, T: Serialize>(
+        &self,
+        object_name: &str,
+        attr_name: &str,
+        value: &T,
+    ) -> RadosResult<()> {
+        let mut buffer = C::encode(value)?;
+        self.rados_object_setxattr(object_name, attr_name, &mut buffer)
+    }
+}
+
 impl Rados {
     pub fn rados_blacklist_client(&self, client: IpAddr, expire_seconds: u32) -> RadosResult<()> {
         self.conn_guard()?;
@@ -1800,6 +2916,62 @@ impl Rados {
         Ok(fsid_str.parse()?)
     }
 
+    /// The global id the mons gave this client, the N in client.N as it
+    /// shows in `ceph tell` output and session lists
+    pub fn instance_id(&self) -> RadosResult<u64> {
+        self.conn_guard()?;
+        unsafe { Ok(rados_get_instance_id(self.rados)) }
+    }
+
+    /// This client's addresses, e.g. `v2:10.0.0.1:0/3710147553`, as passed
+    /// to `osd blocklist add` to fence it
+    pub fn client_addrs(&self) -> RadosResult<Vec<String>> {
+        self.conn_guard()?;
+        let mut addrs: *mut c_char = ptr::null_mut();
+        let addrs = unsafe {
+            let ret_code = rados_getaddrs(self.rados, &mut addrs);
+            if ret_code < 0 {
+                return Err(ret_code.into());
+            }
+            if addrs.is_null() {
+                return Ok(Vec::new());
+            }
+            // librados strdup()s the string
+            let s = CStr::from_ptr(addrs).to_string_lossy().into_owned();
+            free(addrs as *mut c_void);
+            s
+        };
+        // A single address prints bare, several as [a,b]
+        Ok(addrs
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .split(',')
+            .filter(|a| !a.is_empty())
+            .map(|a| a.to_string())
+            .collect())
+    }
+
+    /// Block until this client has the newest OSD map, e.g. so a pool that
+    /// was just created can be opened
+    pub fn wait_for_latest_osdmap(&self) -> RadosResult<()> {
+        self.conn_guard()?;
+        let ret_code = unsafe { rados_wait_for_latest_osdmap(self.rados) };
+        if ret_code < 0 {
+            return Err(ret_code.into());
+        }
+        Ok(())
+    }
+
+    /// Stream cluster log entries at level and above, like `ceph -w`.  Only
+    /// one watch can be active per handle.
+    pub fn watch_cluster_log(
+        self: &Arc<Self>,
+        level: ClusterLogLevel,
+    ) -> RadosResult<ClusterLogWatch> {
+        self.conn_guard()?;
+        cluster_log::watch(self, level)
+    }
+
     /// Ping a monitor to assess liveness
     /// May be used as a simply way to assess liveness, or to obtain
     /// information about the monitor in a simple way even in the
@@ -1866,6 +3038,43 @@ pub fn ceph_version_parse() -> Option<String> {
     }
 }
 
+/// Copy out and free the buffers of a mon, mgr, OSD or PG command.  On failure
+/// the status string, if any, becomes the error message.
+unsafe fn command_output(
+    ret_code: i32,
+    outbuf: *mut c_char,
+    outbuf_len: usize,
+    outs: *mut c_char,
+    outs_len: usize,
+) -> RadosResult<(Vec<u8>, Option<String>)> {
+    let mut out: Vec<u8> = vec![];
+    let mut status_string: Option<String> = None;
+
+    // Ceph librados allocates these buffers internally and the pointer that comes
+    // back must be freed by call `rados_buffer_free`
+    if outbuf_len > 0 && !outbuf.is_null() {
+        out = ::std::slice::from_raw_parts(outbuf as *const u8, outbuf_len).to_vec();
+    }
+    if !outbuf.is_null() {
+        rados_buffer_free(outbuf);
+    }
+    if outs_len > 0 && !outs.is_null() {
+        let slice = ::std::slice::from_raw_parts(outs as *const u8, outs_len);
+        status_string = Some(String::from_utf8_lossy(slice).into_owned());
+    }
+    if !outs.is_null() {
+        rados_buffer_free(outs);
+    }
+
+    if ret_code < 0 {
+        return Err(match status_string {
+            Some(message) => RadosError::new(message),
+            None => ret_code.into(),
+        });
+    }
+    Ok((out, status_string))
+}
+
 impl Rados {
     /// Only single String value
     pub fn ceph_status(&self, keys: &[&str]) -> RadosResult<String> {
@@ -1928,31 +3137,38 @@ impl Rados {
         keys: &[&str],
     ) -> RadosResult<JsonData> {
         self.conn_guard()?;
-        match cmd_type {
-            CephCommandTypes::Osd => Err(RadosError::new("OSD CMDs Not implemented.".to_string())),
-            CephCommandTypes::Pgs => Err(RadosError::new("PGS CMDS Not implemented.".to_string())),
-            _ => match self.ceph_mon_command(name, value, Some("json")) {
-                Ok((json, _)) => match json {
-                    Some(json) => match json_data(&json) {
-                        Some(jsondata) => {
-                            if let Some(data) = json_find(jsondata, keys) {
-                                Ok(data)
-                            } else {
-                                Err(RadosError::new(
-                                    "The attributes were not found in the output.".to_string(),
-                                ))
-                            }
-                        }
-                        _ => Err(RadosError::new("JSON data not found.".to_string())),
-                    },
-                    _ => Err(RadosError::new("JSON data not found.".to_string())),
-                },
-                Err(e) => Err(e),
+        let json = match cmd_type {
+            CephCommandTypes::Mon => self.ceph_mon_command(name, value, Some("json"))?.0,
+            CephCommandTypes::Osd(osd_id) => {
+                let cmd = json!({ name: value, "format": "json" });
+                let (out, _) = self.ceph_osd_command_with_input(osd_id, &cmd, &[])?;
+                Some(String::from_utf8(out)?)
+            }
+            CephCommandTypes::Pgs(pgid) => {
+                let cmd = json!({ name: value, "pgid": pgid, "format": "json" });
+                let (out, _) = self.ceph_pg_command_with_input(&pgid, &cmd, &[])?;
+                Some(String::from_utf8(out)?)
+            }
+        };
+        match json {
+            Some(json) => match json_data(&json) {
+                Some(jsondata) => {
+                    if let Some(data) = json_find(jsondata, keys) {
+                        Ok(data)
+                    } else {
+                        Err(RadosError::new(
+                            "The attributes were not found in the output.".to_string(),
+                        ))
+                    }
+                }
+                _ => Err(RadosError::new("JSON data not found.".to_string())),
             },
+            _ => Err(RadosError::new("JSON data not found.".to_string())),
         }
     }
 
-    /// Returns the list of available commands
+    /// Returns the list of available commands.  `cmd::get_command_descriptions`
+    /// parses them into signatures.
     pub fn ceph_commands(&self, keys: Option<&[&str]>) -> RadosResult<JsonData> {
         self.conn_guard()?;
         match self.ceph_mon_command("prefix", "get_command_descriptions", Some("json")) {
@@ -1993,24 +3209,27 @@ impl Rados {
     pub fn ceph_mon_command_without_data(
         &self,
         cmd: &serde_json::Value,
+    ) -> RadosResult<(Vec<u8>, Option<String>)> {
+        self.ceph_mon_command_with_input(cmd, &[])
+    }
+
+    /// Mon command with an input buffer, such as the map passed to
+    /// `osd setcrushmap`.
+    pub fn ceph_mon_command_with_input(
+        &self,
+        cmd: &serde_json::Value,
+        input: &[u8],
     ) -> RadosResult<(Vec<u8>, Option<String>)> {
         self.conn_guard()?;
         let cmd_string = cmd.to_string();
-        debug!("ceph_mon_command_without_data: {}", cmd_string);
-        let data: Vec<*mut c_char> = Vec::with_capacity(1);
+        debug!("ceph_mon_command_with_input: {}", cmd_string);
         let cmds = CString::new(cmd_string).unwrap();
 
+        let mut outbuf = ptr::null_mut();
         let mut outbuf_len = 0;
         let mut outs = ptr::null_mut();
         let mut outs_len = 0;
 
-        // Ceph librados allocates these buffers internally and the pointer that comes
-        // back must be
-        // freed by call `rados_buffer_free`
-        let mut outbuf = ptr::null_mut();
-        let mut out: Vec<u8> = vec![];
-        let mut status_string: Option<String> = None;
-
         debug!("Calling rados_mon_command with {:?}", cmd);
 
         unsafe {
@@ -2019,38 +3238,253 @@ impl Rados {
                 self.rados,
                 &mut cmds.as_ptr(),
                 1,
-                data.as_ptr() as *mut c_char,
-                data.len() as usize,
+                input.as_ptr() as *const c_char,
+                input.len(),
                 &mut outbuf,
                 &mut outbuf_len,
                 &mut outs,
                 &mut outs_len,
             );
             debug!("return code: {}", ret_code);
-            if ret_code < 0 {
-                if outs_len > 0 && !outs.is_null() {
-                    let slice = ::std::slice::from_raw_parts(outs as *const u8, outs_len);
-                    rados_buffer_free(outs);
-                    return Err(RadosError::new(String::from_utf8_lossy(slice).into_owned()));
-                }
-                return Err(ret_code.into());
-            }
+            command_output(ret_code, outbuf, outbuf_len, outs, outs_len)
+        }
+    }
 
-            // Copy the data from outbuf and then call rados_buffer_free instead libc::free
-            if outbuf_len > 0 && !outbuf.is_null() {
-                let slice = ::std::slice::from_raw_parts(outbuf as *const u8, outbuf_len as usize);
-                out = slice.to_vec();
+    /// Run a `MonCommand`, passing its input payload if it has one
+    pub fn mon_command(&self, cmd: &MonCommand) -> RadosResult<(Vec<u8>, Option<String>)> {
+        self.ceph_mon_command_with_input(cmd.as_value(), cmd.input())
+    }
 
-                rados_buffer_free(outbuf);
-            }
-            if outs_len > 0 && !outs.is_null() {
-                let slice = ::std::slice::from_raw_parts(outs as *const u8, outs_len as usize);
-                status_string = Some(String::from_utf8(slice.to_vec())?);
-                rados_buffer_free(outs);
-            }
+    /// Mon command sent to one mon by name rather than whichever the client
+    /// is talking to.  Useful for mons outside of quorum.
+    pub fn ceph_mon_command_target_with_input(
+        &self,
+        mon_name: &str,
+        cmd: &serde_json::Value,
+        input: &[u8],
+    ) -> RadosResult<(Vec<u8>, Option<String>)> {
+        self.conn_guard()?;
+        let cmd_string = cmd.to_string();
+        debug!(
+            "Calling rados_mon_command_target on mon.{} with {}",
+            mon_name, cmd_string
+        );
+        let name = CString::new(mon_name)?;
+        let cmds = CString::new(cmd_string)?;
+
+        let mut outbuf = ptr::null_mut();
+        let mut outbuf_len = 0;
+        let mut outs = ptr::null_mut();
+        let mut outs_len = 0;
+
+        unsafe {
+            let ret_code = rados_mon_command_target(
+                self.rados,
+                name.as_ptr(),
+                &mut cmds.as_ptr(),
+                1,
+                input.as_ptr() as *const c_char,
+                input.len(),
+                &mut outbuf,
+                &mut outbuf_len,
+                &mut outs,
+                &mut outs_len,
+            );
+            debug!("return code: {}", ret_code);
+            command_output(ret_code, outbuf, outbuf_len, outs, outs_len)
         }
+    }
+
+    /// Run a `MonCommand` on the named mon, e.g. `a` for mon.a
+    pub fn mon_command_target(
+        &self,
+        mon_name: &str,
+        cmd: &MonCommand,
+    ) -> RadosResult<(Vec<u8>, Option<String>)> {
+        self.ceph_mon_command_target_with_input(mon_name, cmd.as_value(), cmd.input())
+    }
+
+    /// Command for the active ceph-mgr, such as `balancer status` or
+    /// `crash ls`
+    pub fn ceph_mgr_command_with_input(
+        &self,
+        cmd: &serde_json::Value,
+        input: &[u8],
+    ) -> RadosResult<(Vec<u8>, Option<String>)> {
+        self.conn_guard()?;
+        let cmd_string = cmd.to_string();
+        debug!("Calling rados_mgr_command with {}", cmd_string);
+        let cmds = CString::new(cmd_string)?;
+
+        let mut outbuf = ptr::null_mut();
+        let mut outbuf_len = 0;
+        let mut outs = ptr::null_mut();
+        let mut outs_len = 0;
 
-        Ok((out, status_string))
+        unsafe {
+            let ret_code = rados_mgr_command(
+                self.rados,
+                &mut cmds.as_ptr(),
+                1,
+                input.as_ptr() as *const c_char,
+                input.len(),
+                &mut outbuf,
+                &mut outbuf_len,
+                &mut outs,
+                &mut outs_len,
+            );
+            debug!("return code: {}", ret_code);
+            command_output(ret_code, outbuf, outbuf_len, outs, outs_len)
+        }
+    }
+
+    /// Run a `MonCommand` on the active ceph-mgr
+    pub fn mgr_command(&self, cmd: &MonCommand) -> RadosResult<(Vec<u8>, Option<String>)> {
+        self.ceph_mgr_command_with_input(cmd.as_value(), cmd.input())
+    }
+
+    /// Command for the named ceph-mgr, active or standby.  Needs an Octopus
+    /// or later librados.
+    pub fn ceph_mgr_command_target_with_input(
+        &self,
+        mgr_name: &str,
+        cmd: &serde_json::Value,
+        input: &[u8],
+    ) -> RadosResult<(Vec<u8>, Option<String>)> {
+        self.conn_guard()?;
+        let cmd_string = cmd.to_string();
+        debug!(
+            "Calling rados_mgr_command_target on mgr.{} with {}",
+            mgr_name, cmd_string
+        );
+        let name = CString::new(mgr_name)?;
+        let cmds = CString::new(cmd_string)?;
+
+        let mut outbuf = ptr::null_mut();
+        let mut outbuf_len = 0;
+        let mut outs = ptr::null_mut();
+        let mut outs_len = 0;
+
+        unsafe {
+            let ret_code = rados_mgr_command_target(
+                self.rados,
+                name.as_ptr(),
+                &mut cmds.as_ptr(),
+                1,
+                input.as_ptr() as *const c_char,
+                input.len(),
+                &mut outbuf,
+                &mut outbuf_len,
+                &mut outs,
+                &mut outs_len,
+            );
+            debug!("return code: {}", ret_code);
+            command_output(ret_code, outbuf, outbuf_len, outs, outs_len)
+        }
+    }
+
+    /// Run a `MonCommand` on the named ceph-mgr
+    pub fn mgr_command_target(
+        &self,
+        mgr_name: &str,
+        cmd: &MonCommand,
+    ) -> RadosResult<(Vec<u8>, Option<String>)> {
+        self.ceph_mgr_command_target_with_input(mgr_name, cmd.as_value(), cmd.input())
+    }
+
+    /// OSD command with JSON arguments and an input payload
+    pub fn ceph_osd_command_with_input(
+        &self,
+        osd_id: i32,
+        cmd: &serde_json::Value,
+        input: &[u8],
+    ) -> RadosResult<(Vec<u8>, Option<String>)> {
+        self.conn_guard()?;
+        let cmd_string = cmd.to_string();
+        debug!(
+            "Calling rados_osd_command on osd.{} with {}",
+            osd_id, cmd_string
+        );
+        let cmds = CString::new(cmd_string)?;
+
+        let mut outbuf = ptr::null_mut();
+        let mut outbuf_len = 0;
+        let mut outs = ptr::null_mut();
+        let mut outs_len = 0;
+
+        unsafe {
+            let ret_code = rados_osd_command(
+                self.rados,
+                osd_id,
+                &mut cmds.as_ptr(),
+                1,
+                input.as_ptr() as *const c_char,
+                input.len(),
+                &mut outbuf,
+                &mut outbuf_len,
+                &mut outs,
+                &mut outs_len,
+            );
+            debug!("return code: {}", ret_code);
+            command_output(ret_code, outbuf, outbuf_len, outs, outs_len)
+        }
+    }
+
+    /// Run a `MonCommand` on an OSD, like `ceph tell osd.N`
+    pub fn osd_command(
+        &self,
+        osd_id: i32,
+        cmd: &MonCommand,
+    ) -> RadosResult<(Vec<u8>, Option<String>)> {
+        self.ceph_osd_command_with_input(osd_id, cmd.as_value(), cmd.input())
+    }
+
+    /// PG command with JSON arguments and an input payload.  It goes to the
+    /// primary OSD of the PG, which also expects the pgid among the arguments.
+    pub fn ceph_pg_command_with_input(
+        &self,
+        pgid: &str,
+        cmd: &serde_json::Value,
+        input: &[u8],
+    ) -> RadosResult<(Vec<u8>, Option<String>)> {
+        self.conn_guard()?;
+        let cmd_string = cmd.to_string();
+        debug!("Calling rados_pg_command on {} with {}", pgid, cmd_string);
+        let pg_str = CString::new(pgid)?;
+        let cmds = CString::new(cmd_string)?;
+
+        let mut outbuf = ptr::null_mut();
+        let mut outbuf_len = 0;
+        let mut outs = ptr::null_mut();
+        let mut outs_len = 0;
+
+        unsafe {
+            let ret_code = rados_pg_command(
+                self.rados,
+                pg_str.as_ptr(),
+                &mut cmds.as_ptr(),
+                1,
+                input.as_ptr() as *const c_char,
+                input.len(),
+                &mut outbuf,
+                &mut outbuf_len,
+                &mut outs,
+                &mut outs_len,
+            );
+            debug!("return code: {}", ret_code);
+            command_output(ret_code, outbuf, outbuf_len, outs, outs_len)
+        }
+    }
+
+    /// Run a `MonCommand` on a PG, like `ceph pg <pgid> query`.  The pgid
+    /// argument is added for you.
+    pub fn pg_command(
+        &self,
+        pgid: &str,
+        cmd: &MonCommand,
+    ) -> RadosResult<(Vec<u8>, Option<String>)> {
+        let cmd = cmd.clone().with("pgid", pgid);
+        self.ceph_pg_command_with_input(pgid, cmd.as_value(), cmd.input())
     }
 
     /// Mon command that does pass in a data payload.
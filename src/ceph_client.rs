@@ -6,9 +6,11 @@ use crate::rados;
 
 use libc::c_char;
 use std::ffi::CString;
+use std::time::Duration;
 use std::{ptr, str};
 
 use crate::error::RadosError;
+use crate::status::{ClusterStatus, HealthDetail};
 use crate::{CephVersion, MonCommand, OsdOption, PoolOption};
 
 /// A CephClient is a struct that handles communicating with Ceph
@@ -71,7 +73,7 @@ impl CephClient {
         let osd_id = osd_id.to_string();
         let cmd = MonCommand::new()
             .with_prefix("osd out")
-            .with("ids", &osd_id);
+            .with_strings("ids", &[osd_id]);
 
         if !self.simulate {
             self.run_command(cmd)?;
@@ -188,6 +190,65 @@ impl CephClient {
         }
     }
 
+    /// `status`, parsed
+    pub fn cluster_status(&self) -> Result<ClusterStatus, RadosError> {
+        min_version!(Luminous, self);
+        cmd::cluster_status(&self.rados_t)
+    }
+
+    /// Health checks by code, e.g. to see whether OSD_DOWN is firing
+    pub fn health_detail(&self) -> Result<HealthDetail, RadosError> {
+        min_version!(Luminous, self);
+        cmd::health_detail(&self.rados_t)
+    }
+
+    /// Mute a health check such as OSD_DOWN, for ttl if given.  A sticky
+    /// mute outlives the check clearing.
+    pub fn health_mute(
+        &self,
+        code: &str,
+        ttl: Option<Duration>,
+        sticky: bool,
+    ) -> Result<(), RadosError> {
+        min_version!(Octopus, self);
+        cmd::health_mute(&self.rados_t, code, ttl, sticky, self.simulate)
+    }
+
+    pub fn health_unmute(&self, code: &str) -> Result<(), RadosError> {
+        min_version!(Octopus, self);
+        cmd::health_unmute(&self.rados_t, Some(code), self.simulate)
+    }
+
+    /// Mute a health check until the returned guard is dropped, e.g. for the
+    /// length of planned maintenance.  The ttl is a backstop in case the
+    /// process dies before unmuting.  If the check is already muted, say by
+    /// an operator, that mute is left as it is and the guard does nothing.
+    pub fn mute_health_check(
+        &self,
+        code: &str,
+        ttl: Option<Duration>,
+        sticky: bool,
+    ) -> Result<HealthMuteGuard<'_>, RadosError> {
+        min_version!(Octopus, self);
+        // Simulating makes no cluster calls, so assume the check isn't muted
+        let owned = self.simulate
+            || !self
+                .health_detail()?
+                .mutes
+                .iter()
+                .any(|mute| mute.code == code);
+        if owned {
+            self.health_mute(code, ttl, sticky)?;
+        } else {
+            debug!("{} is already muted, leaving the existing mute", code);
+        }
+        Ok(HealthMuteGuard {
+            client: self,
+            code: code.to_string(),
+            owned,
+        })
+    }
+
     /// List all the monitors in the cluster and their current rank
     pub fn mon_dump(&self) -> Result<cmd::MonDump, RadosError> {
         cmd::mon_dump(&self.rados_t)
@@ -198,6 +259,14 @@ impl CephClient {
         cmd::mon_quorum(&self.rados_t)
     }
 
+    /// mon_status from every mon, keyed by name, to find the ones out of
+    /// quorum
+    pub fn mon_status_all(
+        &self,
+    ) -> Result<HashMap<String, Result<cmd::MonStatus, RadosError>>, RadosError> {
+        cmd::mon_status_all(&self.rados_t)
+    }
+
     /// Show mon daemon version
     pub fn version(&self) -> Result<CephVersion, RadosError> {
         cmd::version(&self.rados_t)?.parse()
@@ -305,7 +374,7 @@ impl CephClient {
 
     pub fn run_command(&self, command: MonCommand) -> Result<String, RadosError> {
         let cmd = command.as_json();
-        let data: Vec<*mut c_char> = Vec::with_capacity(1);
+        let data = command.input();
 
         debug!("Calling rados_mon_command with {:?}", cmd);
         let cmds = CString::new(cmd).unwrap();
@@ -326,7 +395,7 @@ impl CephClient {
                 *self.rados_t.inner(),
                 &mut cmds.as_ptr(),
                 1,
-                data.as_ptr() as *mut c_char,
+                data.as_ptr() as *const c_char,
                 data.len(),
                 &mut outbuf,
                 &mut outbuf_len,
@@ -374,4 +443,87 @@ impl CephClient {
         // Ok((str_outbuf, str_outs))
         Ok(str_outbuf)
     }
+
+    /// Run a command on the active ceph-mgr, for the modules like balancer,
+    /// pg_autoscaler, orchestrator or crash
+    pub fn run_mgr_command(&self, command: MonCommand) -> Result<String, RadosError> {
+        min_version!(Luminous, self);
+        let (out, _) = self.rados_t.mgr_command(&command)?;
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    /// Run a command on the named ceph-mgr, which may be a standby
+    pub fn run_mgr_command_target(
+        &self,
+        mgr_name: &str,
+        command: MonCommand,
+    ) -> Result<String, RadosError> {
+        min_version!(Octopus, self);
+        let (out, _) = self.rados_t.mgr_command_target(mgr_name, &command)?;
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    /// Run a command on an OSD, like `ceph tell osd.N`
+    pub fn osd_command(&self, osd_id: u64, command: MonCommand) -> Result<String, RadosError> {
+        let (out, _) = self
+            .rados_t
+            .osd_command(cmd::osd_id_i32(osd_id)?, &command)?;
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    /// Run a command on the primary OSD of a PG, like `ceph pg <pgid>`
+    pub fn pg_command(&self, pgid: &str, command: MonCommand) -> Result<String, RadosError> {
+        let (out, _) = self.rados_t.pg_command(pgid, &command)?;
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    pub fn pg_query(&self, pgid: &str) -> Result<cmd::PgQuery, RadosError> {
+        cmd::pg_query(&self.rados_t, pgid)
+    }
+
+    /// OSDs answer admin socket commands like `perf dump` over the network
+    /// since Octopus
+    pub fn osd_perf_dump(&self, osd_id: u64) -> Result<cmd::OsdPerfDump, RadosError> {
+        min_version!(Octopus, self);
+        cmd::osd_perf_dump(&self.rados_t, osd_id)
+    }
+}
+
+/// Unmutes its health check when dropped, unless the check was already
+/// muted when the guard was made.  Use `unmute` to see whether unmuting
+/// worked.
+pub struct HealthMuteGuard<'a> {
+    client: &'a CephClient,
+    code: String,
+    owned: bool,
+}
+
+impl<'a> HealthMuteGuard<'a> {
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Whether this guard made the mute, and so will remove it
+    pub fn is_owned(&self) -> bool {
+        self.owned
+    }
+
+    /// Unmute now rather than on drop
+    pub fn unmute(mut self) -> Result<(), RadosError> {
+        if !self.owned {
+            return Ok(());
+        }
+        self.owned = false;
+        self.client.health_unmute(&self.code)
+    }
+}
+
+impl<'a> Drop for HealthMuteGuard<'a> {
+    fn drop(&mut self) {
+        if self.owned {
+            if let Err(e) = self.client.health_unmute(&self.code) {
+                error!("Unable to unmute health check {}: {}", self.code, e);
+            }
+        }
+    }
 }
@@ -0,0 +1,249 @@
+//! Server side checksums of object data, and the matching local
+//! implementations used to check an object against a file without reading
+//! the object back.
+
+use std::io::Read;
+
+use crate::ceph::{IoCtx, ReadOperation};
+use crate::error::{RadosError, RadosResult};
+use crate::rados::{
+    rados_checksum_type_t, LIBRADOS_CHECKSUM_TYPE_CRC32C, LIBRADOS_CHECKSUM_TYPE_XXHASH32,
+    LIBRADOS_CHECKSUM_TYPE_XXHASH64,
+};
+
+/// Default chunk size for `IoCtx::verify_against`
+pub const DEFAULT_VERIFY_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_computes_crc32c() {
+        // Ceph's crc32c has no final inversion so apply it to compare
+        // against the standard check value
+        let crc = ChecksumType::Crc32c.compute(u32::MAX as u64, b"123456789");
+        assert_eq!(crc ^ u32::MAX as u64, 0xe306_9283);
+    }
+
+    #[test]
+    fn it_computes_xxhash() {
+        assert_eq!(ChecksumType::XxHash32.compute(0, b""), 0x02cc_5d05);
+        assert_eq!(
+            ChecksumType::XxHash64.compute(0, b""),
+            0xef46_db37_51d8_e999
+        );
+    }
+
+    #[test]
+    fn it_encodes_init_values() {
+        assert_eq!(ChecksumType::Crc32c.encode_init(u32::MAX as u64).len(), 4);
+        assert_eq!(ChecksumType::XxHash64.encode_init(0).len(), 8);
+    }
+
+    #[test]
+    fn it_decodes_checksum_replies() {
+        let reply = [2, 0, 0, 0, 1, 0, 0, 0, 0xff, 0, 0, 0];
+        assert_eq!(
+            ChecksumType::XxHash32.decode_reply(&reply).unwrap(),
+            vec![1, 0xff]
+        );
+        assert!(ChecksumType::XxHash32.decode_reply(&reply[..8]).is_err());
+    }
+}
+
+/// Checksum algorithms supported by the OSD
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumType {
+    XxHash32,
+    XxHash64,
+    Crc32c,
+}
+
+impl ChecksumType {
+    pub(crate) fn as_raw(self) -> rados_checksum_type_t {
+        match self {
+            ChecksumType::XxHash32 => LIBRADOS_CHECKSUM_TYPE_XXHASH32,
+            ChecksumType::XxHash64 => LIBRADOS_CHECKSUM_TYPE_XXHASH64,
+            ChecksumType::Crc32c => LIBRADOS_CHECKSUM_TYPE_CRC32C,
+        }
+    }
+
+    /// Size in bytes of one checksum value
+    pub fn size(self) -> usize {
+        match self {
+            ChecksumType::XxHash64 => 8,
+            _ => 4,
+        }
+    }
+
+    /// The init value Ceph uses by default: all ones for crc32c and a zero
+    /// seed for xxhash
+    pub fn default_init(self) -> u64 {
+        match self {
+            ChecksumType::Crc32c => u32::MAX as u64,
+            _ => 0,
+        }
+    }
+
+    /// Compute the checksum locally exactly as the OSD would
+    pub fn compute(self, init: u64, data: &[u8]) -> u64 {
+        match self {
+            ChecksumType::XxHash32 => xxhash_rust::xxh32::xxh32(data, init as u32) as u64,
+            ChecksumType::XxHash64 => xxhash_rust::xxh64::xxh64(data, init),
+            ChecksumType::Crc32c => crc32c(init as u32, data) as u64,
+        }
+    }
+
+    /// Little-endian init value sized for this algorithm
+    pub(crate) fn encode_init(self, init: u64) -> Vec<u8> {
+        init.to_le_bytes()[..self.size()].to_vec()
+    }
+
+    /// The OSD replies with a u32 count followed by that many checksums
+    pub(crate) fn decode_reply(self, reply: &[u8]) -> RadosResult<Vec<u64>> {
+        if reply.len() < 4 {
+            return Err(RadosError::new("checksum reply is too short".to_string()));
+        }
+        let count = u32::from_le_bytes([reply[0], reply[1], reply[2], reply[3]]) as usize;
+        let size = self.size();
+        let values = &reply[4..];
+        if values.len() < count * size {
+            return Err(RadosError::new(format!(
+                "checksum reply holds {} bytes but {} checksums were returned",
+                values.len(),
+                count
+            )));
+        }
+        Ok(values
+            .chunks(size)
+            .take(count)
+            .map(|c| {
+                let mut bytes = [0u8; 8];
+                bytes[..size].copy_from_slice(c);
+                u64::from_le_bytes(bytes)
+            })
+            .collect())
+    }
+}
+
+/// Castagnoli CRC without the final inversion, matching ceph_crc32c
+fn crc32c(init: u32, data: &[u8]) -> u32 {
+    const POLY: u32 = 0x82f6_3b78;
+    let mut crc = init;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// Result of `IoCtx::verify_against`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    /// Every chunk matched
+    Match,
+    /// The object and the local data are different lengths
+    SizeMismatch { object_size: u64, local_size: u64 },
+    /// The chunk starting at offset differs
+    ChunkMismatch { offset: u64 },
+}
+
+impl Verification {
+    pub fn is_match(&self) -> bool {
+        *self == Verification::Match
+    }
+}
+
+/// Fill buf from reader, stopping early only at end of input
+fn fill<R: Read>(reader: &mut R, buf: &mut [u8]) -> RadosResult<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+pub(crate) fn verify_against<R: Read>(
+    ioctx: &IoCtx,
+    object_name: &str,
+    mut local: R,
+    kind: ChecksumType,
+    chunk_size: usize,
+) -> RadosResult<Verification> {
+    if chunk_size == 0 {
+        return Err(RadosError::new(
+            "chunk_size must be greater than 0".to_string(),
+        ));
+    }
+    let (object_size, _) = ioctx.rados_object_stat(object_name)?;
+    let init = kind.default_init();
+
+    // The OSD requires the length to be a multiple of the chunk size, so any
+    // tail is checksummed by a second step
+    let tail = (object_size % chunk_size as u64) as usize;
+    let aligned = object_size - tail as u64;
+    let mut read_op = ReadOperation::new(object_name);
+    let aligned_step = if aligned > 0 {
+        Some(read_op.checksum(kind, init, 0, aligned as usize, chunk_size))
+    } else {
+        None
+    };
+    let tail_step = if tail > 0 {
+        Some(read_op.checksum(kind, init, aligned, tail, 0))
+    } else {
+        None
+    };
+    if aligned_step.is_some() || tail_step.is_some() {
+        ioctx.rados_perform_read_operations(&mut read_op)?;
+    }
+    let mut remote: Vec<u64> = Vec::new();
+    if let Some(step) = aligned_step {
+        remote.extend(read_op.checksum_result(step)?);
+    }
+    if let Some(step) = tail_step {
+        remote.extend(read_op.checksum_result(step)?);
+    }
+
+    let mut buf = vec![0u8; chunk_size];
+    let mut local_size: u64 = 0;
+    let mut mismatch: Option<u64> = None;
+    for expected in remote {
+        let len = fill(&mut local, &mut buf)?;
+        if mismatch.is_none() && kind.compute(init, &buf[..len]) != expected {
+            mismatch = Some(local_size);
+        }
+        local_size += len as u64;
+        if len < chunk_size {
+            break;
+        }
+    }
+    // Count whatever local data is left past the end of the object
+    loop {
+        let len = fill(&mut local, &mut buf)?;
+        if len == 0 {
+            break;
+        }
+        local_size += len as u64;
+    }
+
+    if local_size != object_size {
+        return Ok(Verification::SizeMismatch {
+            object_size,
+            local_size,
+        });
+    }
+    match mismatch {
+        Some(offset) => Ok(Verification::ChunkMismatch { offset }),
+        None => Ok(Verification::Match),
+    }
+}
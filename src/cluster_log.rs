@@ -0,0 +1,275 @@
+//! Streaming the cluster log, as `ceph -w` does.
+//!
+//! librados calls back once per log entry from its messenger thread.  The
+//! callback parses the entry and sends it down a channel owned by a
+//! `ClusterLogWatch`, which unsubscribes when dropped.
+
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::ops::Deref;
+use std::os::raw::c_void;
+use std::ptr;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc::c_char;
+
+use crate::ceph::Rados;
+use crate::error::{RadosError, RadosResult};
+use crate::rados::rados_monitor_log;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_levels() {
+        assert_eq!(
+            "[WRN]".parse::<ClusterLogLevel>().unwrap(),
+            ClusterLogLevel::Warning
+        );
+        assert_eq!(
+            "info".parse::<ClusterLogLevel>().unwrap(),
+            ClusterLogLevel::Info
+        );
+        assert_eq!(
+            "[SEC]".parse::<ClusterLogLevel>().unwrap(),
+            ClusterLogLevel::Security
+        );
+        assert!("[XYZ]".parse::<ClusterLogLevel>().is_err());
+        assert!(ClusterLogLevel::Error > ClusterLogLevel::Warning);
+        assert_eq!(ClusterLogLevel::Warning.to_string(), "[WRN]");
+    }
+
+    #[test]
+    fn it_can_be_sent_to_another_thread() {
+        fn assert_send<T: Send>() {}
+        assert_send::<ClusterLogWatch>();
+    }
+
+    #[test]
+    fn it_delivers_entries_from_the_callback() {
+        let (tx, rx) = channel();
+        let sender = Mutex::new(tx);
+        let c = |s: &str| CString::new(s).unwrap();
+        let (line, who, level, msg) = (
+            c("2024-05-01T10:00:00.000000+0000 mon.a (mon.0) 12 : cluster [WRN] Health check failed"),
+            c("mon.0 [v2:10.0.0.1:3300/0,v1:10.0.0.1:6789/0]"),
+            c("[WRN]"),
+            c("Health check failed"),
+        );
+        log_callback(
+            &sender as *const _ as *mut c_void,
+            line.as_ptr(),
+            who.as_ptr(),
+            1_714_557_600,
+            500,
+            12,
+            level.as_ptr(),
+            msg.as_ptr(),
+        );
+        let entry: ClusterLogEntry = rx.recv().unwrap();
+        assert_eq!(entry.who, "mon.0 [v2:10.0.0.1:3300/0,v1:10.0.0.1:6789/0]");
+        assert_eq!(entry.rank, "mon.0");
+        assert_eq!(entry.addrs, "[v2:10.0.0.1:3300/0,v1:10.0.0.1:6789/0]");
+        assert_eq!(entry.seq, 12);
+        assert_eq!(entry.level, ClusterLogLevel::Warning);
+        assert_eq!(entry.message, "Health check failed");
+        assert_eq!(entry.stamp, UNIX_EPOCH + Duration::new(1_714_557_600, 500));
+    }
+}
+
+/// Severity of a cluster log entry, lowest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ClusterLogLevel {
+    Debug,
+    Info,
+    Security,
+    Warning,
+    Error,
+}
+
+impl ClusterLogLevel {
+    /// The name `rados_monitor_log` takes for the minimum level
+    fn subscribe_name(self) -> &'static str {
+        match self {
+            ClusterLogLevel::Debug => "debug",
+            ClusterLogLevel::Info => "info",
+            ClusterLogLevel::Security => "sec",
+            ClusterLogLevel::Warning => "warn",
+            ClusterLogLevel::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for ClusterLogLevel {
+    /// The tag used in log lines
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tag = match self {
+            ClusterLogLevel::Debug => "[DBG]",
+            ClusterLogLevel::Info => "[INF]",
+            ClusterLogLevel::Security => "[SEC]",
+            ClusterLogLevel::Warning => "[WRN]",
+            ClusterLogLevel::Error => "[ERR]",
+        };
+        f.write_str(tag)
+    }
+}
+
+impl FromStr for ClusterLogLevel {
+    type Err = RadosError;
+
+    /// Accepts both the tags in log lines and the subscription names
+    fn from_str(s: &str) -> RadosResult<ClusterLogLevel> {
+        match s {
+            "[DBG]" | "debug" => Ok(ClusterLogLevel::Debug),
+            "[INF]" | "info" => Ok(ClusterLogLevel::Info),
+            "[SEC]" | "sec" => Ok(ClusterLogLevel::Security),
+            "[WRN]" | "warn" | "warning" => Ok(ClusterLogLevel::Warning),
+            "[ERR]" | "error" => Ok(ClusterLogLevel::Error),
+            _ => Err(RadosError::new(format!("Unknown cluster log level {}", s))),
+        }
+    }
+}
+
+/// One cluster log entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterLogEntry {
+    /// The entry formatted the way `ceph -w` prints it
+    pub line: String,
+    /// The rank and addresses of the daemon that logged it, as librados
+    /// passes them, e.g. `mon.0 [v2:10.0.0.1:3300/0,v1:10.0.0.1:6789/0]`.
+    /// The daemon name, such as mon.a, is only in `line`.
+    pub who: String,
+    /// The first part of `who`, e.g. mon.0
+    pub rank: String,
+    /// The rest of `who`, e.g. `[v2:10.0.0.1:3300/0,v1:10.0.0.1:6789/0]`
+    pub addrs: String,
+    pub stamp: SystemTime,
+    pub seq: u64,
+    pub level: ClusterLogLevel,
+    pub message: String,
+}
+
+/// Cluster log entries as they arrive.  Dereferences to the `Receiver`, so
+/// use `recv`, `recv_timeout` or `iter` on it.  Dropping it unsubscribes.
+/// It keeps the handle alive and can be moved to another thread.
+pub struct ClusterLogWatch {
+    rados: Arc<Rados>,
+    receiver: Receiver<ClusterLogEntry>,
+    sender: *mut Mutex<Sender<ClusterLogEntry>>,
+}
+
+// The sender is only touched by the callback, which takes its lock, and is
+// freed in Drop after librados has unregistered the callback, so the raw
+// pointer doesn't tie the watch to the thread that created it.
+unsafe impl Send for ClusterLogWatch {}
+
+impl Deref for ClusterLogWatch {
+    type Target = Receiver<ClusterLogEntry>;
+
+    fn deref(&self) -> &Receiver<ClusterLogEntry> {
+        &self.receiver
+    }
+}
+
+impl Drop for ClusterLogWatch {
+    fn drop(&mut self) {
+        // librados holds its lock while calling back, so once this returns
+        // the callback can't be running and the sender can be freed
+        let level = CString::new("info").unwrap();
+        unsafe {
+            rados_monitor_log(*self.rados.inner(), level.as_ptr(), None, ptr::null_mut());
+            drop(Box::from_raw(self.sender));
+        }
+        self.rados.log_watch.store(false, Ordering::SeqCst);
+    }
+}
+
+pub(crate) fn watch(rados: &Arc<Rados>, level: ClusterLogLevel) -> RadosResult<ClusterLogWatch> {
+    // librados keeps a single callback per handle
+    if rados.log_watch.swap(true, Ordering::SeqCst) {
+        return Err(RadosError::new(
+            "The cluster log is already being watched on this handle".to_string(),
+        ));
+    }
+    let (tx, rx) = channel();
+    let sender = Box::into_raw(Box::new(Mutex::new(tx)));
+    let level = CString::new(level.subscribe_name())?;
+    let ret_code = unsafe {
+        rados_monitor_log(
+            *rados.inner(),
+            level.as_ptr(),
+            Some(log_callback),
+            sender as *mut c_void,
+        )
+    };
+    if ret_code < 0 {
+        unsafe { drop(Box::from_raw(sender)) };
+        rados.log_watch.store(false, Ordering::SeqCst);
+        return Err(ret_code.into());
+    }
+    Ok(ClusterLogWatch {
+        rados: Arc::clone(rados),
+        receiver: rx,
+        sender,
+    })
+}
+
+unsafe fn c_string(s: *const c_char) -> String {
+    if s.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(s).to_string_lossy().into_owned()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+extern "C" fn log_callback(
+    arg: *mut c_void,
+    line: *const c_char,
+    who: *const c_char,
+    sec: u64,
+    nsec: u64,
+    seq: u64,
+    level: *const c_char,
+    msg: *const c_char,
+) {
+    let sender = unsafe { &*(arg as *const Mutex<Sender<ClusterLogEntry>>) };
+    let (line, who, level, message) = unsafe {
+        (
+            c_string(line),
+            c_string(who),
+            c_string(level),
+            c_string(msg),
+        )
+    };
+    let level = match level.parse() {
+        Ok(level) => level,
+        Err(_) => {
+            debug!("Skipping cluster log entry with level {}", level);
+            return;
+        }
+    };
+    let (rank, addrs) = match who.split_once(' ') {
+        Some((rank, addrs)) => (rank.to_string(), addrs.to_string()),
+        None => (who.clone(), String::new()),
+    };
+    let entry = ClusterLogEntry {
+        line,
+        who,
+        rank,
+        addrs,
+        stamp: UNIX_EPOCH + Duration::new(sec, nsec as u32),
+        seq,
+        level,
+        message,
+    };
+    // The watch may be on its way out, in which case nobody is listening
+    if let Ok(sender) = sender.lock() {
+        let _ = sender.send(entry);
+    }
+}
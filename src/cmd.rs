@@ -7,12 +7,15 @@
 //! allow you to test without actually calling Ceph.
 extern crate serde_json;
 
-use crate::ceph::Rados;
+use crate::ceph::{timeout_secs, Rados};
 use crate::error::{RadosError, RadosResult};
+use crate::status::{ClusterStatus, HealthDetail};
 use crate::CephVersion;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Deserialize, Debug)]
@@ -38,6 +41,8 @@ pub struct CrushNode {
     pub primary_affinity: Option<f64>,
 }
 
+/// The `osd tree` view of the hierarchy.  `crush::CrushMap` has the whole
+/// map, with rules, for placing data or editing.
 #[derive(Deserialize, Debug)]
 pub struct CrushTree {
     pub nodes: Vec<CrushNode>,
@@ -239,6 +244,184 @@ pub enum PgStat {
     },
 }
 
+/// Output of `ceph pg <pgid> query`
+#[derive(Deserialize, Debug, Clone)]
+pub struct PgQuery {
+    pub state: String,
+    pub epoch: u64,
+    pub up: Vec<i32>,
+    pub acting: Vec<i32>,
+    pub snap_trimq: Option<String>,
+    pub snap_trimq_len: Option<u64>,
+    #[serde(alias = "actingbackfill")] //Before Mimic
+    pub acting_recovery_backfill: Option<Vec<String>>,
+    pub info: PgInfo,
+    #[serde(default)]
+    pub peer_info: Vec<PgPeerInfo>,
+    #[serde(default)]
+    pub recovery_state: Vec<PgRecoveryState>,
+    // other output not captured through the above attributes
+    #[serde(flatten)]
+    pub other_meta: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PgInfo {
+    pub pgid: String,
+    pub last_update: String,
+    pub last_complete: String,
+    pub log_tail: String,
+    pub last_user_version: u64,
+    pub last_backfill: String,
+    pub history: PgHistory,
+    pub stats: PgQueryStats,
+    pub empty: u8,
+    pub dne: u8,
+    pub incomplete: u8,
+    pub last_epoch_started: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PgPeerInfo {
+    pub peer: String,
+    pub pgid: String,
+    pub last_update: String,
+    pub last_complete: String,
+    pub log_tail: String,
+    pub last_backfill: String,
+    pub empty: u8,
+    pub dne: u8,
+    pub incomplete: u8,
+    pub last_epoch_started: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PgHistory {
+    pub epoch_created: u64,
+    pub last_epoch_started: u64,
+    pub last_epoch_clean: u64,
+    pub same_up_since: u64,
+    pub same_interval_since: u64,
+    pub same_primary_since: u64,
+    pub last_scrub: String,
+    pub last_scrub_stamp: String,
+    pub last_deep_scrub: String,
+    pub last_deep_scrub_stamp: String,
+    pub last_clean_scrub_stamp: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PgQueryStats {
+    pub version: String,
+    pub state: String,
+    pub last_fresh: String,
+    pub last_change: String,
+    pub last_active: String,
+    pub last_clean: String,
+    pub log_size: u64,
+    pub ondisk_log_size: u64,
+    pub stat_sum: PgStatSum,
+    pub up: Vec<i32>,
+    pub acting: Vec<i32>,
+    pub up_primary: i32,
+    pub acting_primary: i32,
+    #[serde(default)]
+    pub blocked_by: Vec<i32>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PgStatSum {
+    pub num_bytes: u64,
+    pub num_objects: u64,
+    pub num_object_clones: u64,
+    pub num_object_copies: u64,
+    pub num_objects_missing_on_primary: u64,
+    pub num_objects_missing: u64,
+    pub num_objects_degraded: u64,
+    pub num_objects_misplaced: u64,
+    pub num_objects_unfound: u64,
+    pub num_read: u64,
+    pub num_read_kb: u64,
+    pub num_write: u64,
+    pub num_write_kb: u64,
+    pub num_scrub_errors: u64,
+}
+
+/// One step of the peering state machine the PG went through
+#[derive(Deserialize, Debug, Clone)]
+pub struct PgRecoveryState {
+    pub name: String,
+    pub enter_time: String,
+    #[serde(flatten)]
+    pub other_meta: HashMap<String, serde_json::Value>,
+}
+
+/// A `perf dump` average: `avgtime` is only present for time counters
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PerfAverage {
+    pub avgcount: u64,
+    pub sum: f64,
+    pub avgtime: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum PerfCounter {
+    Count(u64),
+    Time(f64),
+    Average(PerfAverage),
+    Other(serde_json::Value),
+}
+
+/// The `osd` section of an OSD's `perf dump`
+#[derive(Deserialize, Debug, Clone)]
+pub struct OsdPerfCounters {
+    pub op_wip: u64,
+    pub op: u64,
+    pub op_in_bytes: u64,
+    pub op_out_bytes: u64,
+    pub op_latency: PerfAverage,
+    pub op_process_latency: PerfAverage,
+    pub op_r: u64,
+    pub op_r_out_bytes: u64,
+    pub op_r_latency: PerfAverage,
+    pub op_w: u64,
+    pub op_w_in_bytes: u64,
+    pub op_w_latency: PerfAverage,
+    pub op_rw: u64,
+    pub op_rw_latency: PerfAverage,
+    pub subop: u64,
+    pub subop_latency: PerfAverage,
+    pub recovery_ops: u64,
+    pub numpg: u64,
+    pub numpg_primary: u64,
+    pub numpg_replica: u64,
+    pub stat_bytes: u64,
+    pub stat_bytes_used: u64,
+    pub stat_bytes_avail: u64,
+    // counters not captured through the above attributes
+    #[serde(flatten)]
+    pub other: HashMap<String, PerfCounter>,
+}
+
+/// Output of `perf dump` on an OSD, keyed by subsystem
+#[derive(Deserialize, Debug, Clone)]
+pub struct OsdPerfDump {
+    pub osd: OsdPerfCounters,
+    // bluestore, bluefs, rocksdb, throttles and the rest
+    #[serde(flatten)]
+    pub other: HashMap<String, HashMap<String, PerfCounter>>,
+}
+
+impl OsdPerfDump {
+    /// A counter outside of the `osd` section, e.g. `("bluestore", "kv_flush_lat")`
+    pub fn counter(&self, section: &str, name: &str) -> Option<&PerfCounter> {
+        self.other
+            .get(section)
+            .and_then(|counters| counters.get(name))
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct MgrStandby {
     pub gid: u64,
@@ -311,7 +494,7 @@ pub struct Mon {
     pub addr: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HealthStatus {
     #[serde(rename = "HEALTH_ERR")]
     Err,
@@ -759,6 +942,72 @@ pub fn cluster_health(cluster_handle: &Rados) -> RadosResult<ClusterHealth> {
     Ok(serde_json::from_str(&return_data)?)
 }
 
+/// health checks with their detail lines, as of Luminous
+pub fn health_detail(cluster_handle: &Rados) -> RadosResult<HealthDetail> {
+    let cmd = json!({
+        "prefix": "health",
+        "detail": "detail",
+        "format": "json"
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let return_data = String::from_utf8(result.0)?;
+    Ok(serde_json::from_str(&return_data)?)
+}
+
+/// mute a health check, until ttl passes if given.  The ttl must be at
+/// least a second and is rounded up to whole seconds.  A sticky mute is kept
+/// even after the check clears.
+pub fn health_mute(
+    cluster_handle: &Rados,
+    code: &str,
+    ttl: Option<Duration>,
+    sticky: bool,
+    simulate: bool,
+) -> RadosResult<()> {
+    let mut cmd = json!({
+        "prefix": "health mute",
+        "code": code,
+        "format": "json"
+    });
+    if let Some(ttl) = ttl {
+        if ttl < Duration::from_secs(1) {
+            return Err(RadosError::new(format!(
+                "Health mute ttl must be at least 1s, got {:?}",
+                ttl
+            )));
+        }
+        cmd["ttl"] = json!(format!("{}s", timeout_secs(ttl)));
+    }
+    if sticky {
+        cmd["sticky"] = json!(true);
+    }
+
+    if !simulate {
+        cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    }
+    Ok(())
+}
+
+/// unmute a health check, or every muted check if no code is given
+pub fn health_unmute(
+    cluster_handle: &Rados,
+    code: Option<&str>,
+    simulate: bool,
+) -> RadosResult<()> {
+    let mut cmd = json!({
+        "prefix": "health unmute",
+        "format": "json"
+    });
+    if let Some(code) = code {
+        cmd["code"] = json!(code);
+    }
+
+    if !simulate {
+        cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    }
+    Ok(())
+}
+
 /// Check with the monitor whether a given key exists
 pub fn config_key_exists(cluster_handle: &Rados, key: &str) -> RadosResult<bool> {
     let cmd = json!({
@@ -1010,6 +1259,84 @@ pub fn osd_tree(cluster_handle: &Rados) -> RadosResult<CrushTree> {
     Ok(serde_json::from_str(&return_data)?)
 }
 
+/// Dump the CRUSH map as JSON and build a `CrushMap` from it
+pub fn osd_crush_dump(cluster_handle: &Rados) -> RadosResult<crate::crush::CrushMap> {
+    let cmd = json!({
+        "prefix": "osd crush dump",
+        "format": "json"
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let return_data = String::from_utf8(result.0)?;
+    crate::crush::CrushMap::from_json(&return_data)
+}
+
+/// Fetch the compiled CRUSH map, optionally as of an older OSD map epoch
+pub fn osd_getcrushmap(cluster_handle: &Rados, epoch: Option<u64>) -> RadosResult<Vec<u8>> {
+    let mut cmd = json!({
+        "prefix": "osd getcrushmap"
+    });
+    if let Some(epoch) = epoch {
+        cmd["epoch"] = json!(epoch);
+    }
+
+    Ok(cluster_handle.ceph_mon_command_without_data(&cmd)?.0)
+}
+
+/// Fetch the compiled CRUSH map and decode it
+pub fn osd_getcrushmap_decoded(
+    cluster_handle: &Rados,
+    epoch: Option<u64>,
+) -> RadosResult<crate::crush::CrushMap> {
+    crate::crush::CrushMap::decode(&osd_getcrushmap(cluster_handle, epoch)?)
+}
+
+/// Install a new CRUSH map.  With prior_version set the mons refuse the map
+/// if the CRUSH map has changed since that version, which `osd
+/// getcrushmap` reports in its status string.
+pub fn osd_setcrushmap(
+    cluster_handle: &Rados,
+    crush: &crate::crush::CrushMap,
+    prior_version: Option<i64>,
+) -> RadosResult<()> {
+    let mut cmd = json!({
+        "prefix": "osd setcrushmap"
+    });
+    if let Some(prior_version) = prior_version {
+        cmd["prior_version"] = json!(prior_version);
+    }
+    cluster_handle.ceph_mon_command_with_input(&cmd, &crush.encode())?;
+    Ok(())
+}
+
+/// Dump the OSD map, keeping the parts needed for placement
+pub fn osd_dump(cluster_handle: &Rados) -> RadosResult<crate::placement::OsdMap> {
+    let cmd = json!({
+        "prefix": "osd dump",
+        "format": "json"
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let return_data = String::from_utf8(result.0)?;
+    crate::placement::OsdMap::from_json(&return_data)
+}
+
+/// Ask the mons where an object is placed.  `placement::Placement` gives
+/// the same answer locally.
+pub fn osd_map(
+    cluster_handle: &Rados,
+    pool: &str,
+    object: &str,
+) -> RadosResult<crate::placement::ObjectPlacement> {
+    let cmd = json!({
+        "prefix": "osd map",
+        "pool": pool,
+        "object": object,
+        "format": "json"
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let return_data = String::from_utf8(result.0)?;
+    Ok(serde_json::from_str(&return_data)?)
+}
+
 // Get cluster status
 pub fn status(cluster_handle: &Rados) -> RadosResult<String> {
     let cmd = json!({
@@ -1028,6 +1355,12 @@ pub fn status(cluster_handle: &Rados) -> RadosResult<String> {
     }
 }
 
+/// status of the cluster, parsed
+pub fn cluster_status(cluster_handle: &Rados) -> RadosResult<ClusterStatus> {
+    let return_data = status(cluster_handle)?;
+    Ok(serde_json::from_str(&return_data)?)
+}
+
 /// List all the monitors in the cluster and their current rank
 pub fn mon_dump(cluster_handle: &Rados) -> RadosResult<MonDump> {
     let cmd = json!({
@@ -1050,6 +1383,14 @@ pub fn mon_getmap(cluster_handle: &Rados, epoch: Option<u64>) -> RadosResult<Vec
     Ok(cluster_handle.ceph_mon_command_without_data(&cmd)?.0)
 }
 
+/// Fetch the monmap and decode it
+pub fn mon_getmap_decoded(
+    cluster_handle: &Rados,
+    epoch: Option<u64>,
+) -> RadosResult<crate::monmap::MonMap> {
+    crate::monmap::MonMap::decode(&mon_getmap(cluster_handle, epoch)?)
+}
+
 /// Get the mon quorum
 pub fn mon_quorum(cluster_handle: &Rados) -> RadosResult<String> {
     let cmd = json!({
@@ -1071,6 +1412,48 @@ pub fn mon_status(cluster_handle: &Rados) -> RadosResult<MonStatus> {
     Ok(serde_json::from_str(&return_data)?)
 }
 
+/// mon_status as seen by one mon, whether or not it is in quorum
+pub fn mon_status_target(cluster_handle: &Rados, mon_name: &str) -> RadosResult<MonStatus> {
+    let cmd = json!({
+        "prefix": "mon_status",
+        "format": "json",
+    });
+    let result = cluster_handle.ceph_mon_command_target_with_input(mon_name, &cmd, &[])?;
+    let return_data = String::from_utf8(result.0)?;
+    Ok(serde_json::from_str(&return_data)?)
+}
+
+/// Ask every mon in the monmap for its mon_status, keyed by mon name.  A mon
+/// that doesn't answer gets its error instead.  Unreachable mons are only
+/// given up on after `rados_mon_op_timeout`, so set one.
+pub fn mon_status_all(
+    cluster_handle: &Rados,
+) -> RadosResult<HashMap<String, RadosResult<MonStatus>>> {
+    let monmap = mon_getmap_decoded(cluster_handle, None)?;
+    Ok(monmap
+        .mons
+        .into_iter()
+        .map(|mon| {
+            let status = mon_status_target(cluster_handle, &mon.name);
+            (mon.name, status)
+        })
+        .collect())
+}
+
+/// Fetch the signatures of every command the mons know, to validate
+/// `MonCommand`s against
+pub fn get_command_descriptions(
+    cluster_handle: &Rados,
+) -> RadosResult<crate::command_desc::CommandDescriptions> {
+    let cmd = json!({
+        "prefix": "get_command_descriptions",
+        "format": "json"
+    });
+    let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
+    let return_data = String::from_utf8(result.0)?;
+    crate::command_desc::CommandDescriptions::from_json(&return_data)
+}
+
 /// Show mon daemon version
 pub fn version(cluster_handle: &Rados) -> RadosResult<String> {
     let cmd = json!({
@@ -1405,6 +1788,34 @@ pub fn mgr_versions(cluster_handle: &Rados) -> RadosResult<HashMap<String, u64>>
     Ok(serde_json::from_str(&return_data)?)
 }
 
+/// query a PG on its primary OSD
+pub fn pg_query(cluster_handle: &Rados, pgid: &str) -> RadosResult<PgQuery> {
+    let cmd = json!({
+        "prefix": "query",
+        "pgid": pgid,
+        "format": "json",
+    });
+    let result = cluster_handle.ceph_pg_command_with_input(pgid, &cmd, &[])?;
+    let return_data = String::from_utf8(result.0)?;
+    Ok(serde_json::from_str(&return_data)?)
+}
+
+/// librados takes OSD ids as an int
+pub(crate) fn osd_id_i32(osd_id: u64) -> RadosResult<i32> {
+    i32::try_from(osd_id).map_err(|_| RadosError::new(format!("Invalid osd id {}", osd_id)))
+}
+
+/// dump the performance counters of an osd
+pub fn osd_perf_dump(cluster_handle: &Rados, osd_id: u64) -> RadosResult<OsdPerfDump> {
+    let cmd = json!({
+        "prefix": "perf dump",
+        "format": "json",
+    });
+    let result = cluster_handle.ceph_osd_command_with_input(osd_id_i32(osd_id)?, &cmd, &[])?;
+    let return_data = String::from_utf8(result.0)?;
+    Ok(serde_json::from_str(&return_data)?)
+}
+
 pub fn pg_stat(cluster_handle: &Rados) -> RadosResult<PgStat> {
     let cmd = json!({ "prefix": "pg stat", "format": "json"});
     let result = cluster_handle.ceph_mon_command_without_data(&cmd)?;
@@ -0,0 +1,91 @@
+//! Pluggable serialization for storing typed values in object payloads and
+//! extended attributes.  The typed helpers on `IoCtx` default to
+//! `JsonCodec`; pass another `Codec` to the `*_with` variants to use a
+//! different encoding.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[cfg(feature = "cbor")]
+use crate::error::RadosError;
+use crate::error::RadosResult;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Meta {
+        owner: String,
+        size: u64,
+        tags: Vec<String>,
+    }
+
+    fn meta() -> Meta {
+        Meta {
+            owner: "client.admin".into(),
+            size: 4096,
+            tags: vec!["hot".into(), "replicated".into()],
+        }
+    }
+
+    #[test]
+    fn it_round_trips_json() {
+        let bytes = JsonCodec::encode(&meta()).unwrap();
+        assert_eq!(
+            String::from_utf8(bytes.clone()).unwrap(),
+            r#"{"owner":"client.admin","size":4096,"tags":["hot","replicated"]}"#
+        );
+        let decoded: Meta = JsonCodec::decode(&bytes).unwrap();
+        assert_eq!(decoded, meta());
+    }
+
+    #[test]
+    fn it_rejects_invalid_json() {
+        assert!(JsonCodec::decode::<Meta>(b"{\"owner\":").is_err());
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn it_round_trips_cbor() {
+        let bytes = CborCodec::encode(&meta()).unwrap();
+        let decoded: Meta = CborCodec::decode(&bytes).unwrap();
+        assert_eq!(decoded, meta());
+    }
+}
+
+/// Converts values to and from the bytes stored in RADOS.
+pub trait Codec {
+    fn encode<T: Serialize>(value: &T) -> RadosResult<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> RadosResult<T>;
+}
+
+/// serde_json encoding.  This is the default for the typed `IoCtx` helpers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode<T: Serialize>(value: &T) -> RadosResult<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> RadosResult<T> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// CBOR encoding.  Requires the `cbor` feature.
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl Codec for CborCodec {
+    fn encode<T: Serialize>(value: &T) -> RadosResult<Vec<u8>> {
+        serde_cbor::to_vec(value).map_err(|e| RadosError::new(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> RadosResult<T> {
+        serde_cbor::from_slice(bytes).map_err(|e| RadosError::new(e.to_string()))
+    }
+}
@@ -0,0 +1,429 @@
+//! Command signatures from `get_command_descriptions`, used to check a
+//! `MonCommand` before sending it.
+//!
+//! Each command's `sig` is a list of prefix words followed by argument
+//! descriptors such as
+//! `{"name": "pg_num", "type": "CephInt", "range": "0", "req": "false"}`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::error::{RadosError, RadosResult};
+use crate::mon_command::MonCommand;
+use crate::placement::PgId;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_matches_goodchars() {
+        assert!(goodchars_match("[A-Za-z0-9-_.]", "osd-node_1.a"));
+        assert!(!goodchars_match("[A-Za-z0-9-_.]", "bad name"));
+        assert!(!goodchars_match("[A-Za-z0-9-_.]", "a/b"));
+        // Anything that isn't a simple class isn't checked
+        assert!(goodchars_match("\\w+", "a/b"));
+    }
+
+    #[test]
+    fn it_parses_ranges() {
+        let arg = ArgDesc::parse(
+            json!({"name": "weight", "type": "CephFloat", "range": "0.0|1.0", "req": "true"})
+                .as_object()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            arg.arg_type,
+            ArgType::Float {
+                min: Some(0.0),
+                max: Some(1.0)
+            }
+        );
+        assert!(arg.required);
+        assert!(arg.check(&json!(0.5)).is_ok());
+        assert!(arg.check(&json!("0.5")).is_ok());
+        assert!(arg.check(&json!(1.5)).is_err());
+        assert!(arg.check(&json!("heavy")).is_err());
+    }
+}
+
+/// The type of an argument and the limits the mons put on it
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgType {
+    Int {
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    Float {
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    Bool,
+    /// One of a fixed set of words
+    Choices(Vec<String>),
+    /// goodchars is a character class such as `[A-Za-z0-9-_.]`
+    String {
+        goodchars: Option<String>,
+    },
+    PoolName,
+    ObjectName,
+    PgId,
+    /// osd.N or N
+    OsdName,
+    /// A daemon name such as mon.a
+    EntityName,
+    EntityAddr,
+    Uuid,
+    FilePath,
+    /// Types not checked beyond being present
+    Other(String),
+}
+
+/// One argument of a command
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArgDesc {
+    pub name: String,
+    pub arg_type: ArgType,
+    pub required: bool,
+    /// n=N, a list of values
+    pub multiple: bool,
+}
+
+/// One command signature
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandDesc {
+    /// The words making up the prefix, e.g. ["osd", "pool", "create"]
+    pub prefix: Vec<String>,
+    pub args: Vec<ArgDesc>,
+    pub help: String,
+    /// The service handling it: mon, osd, pg, mgr...
+    pub module: String,
+    /// r, w, x or a mix
+    pub perm: String,
+    pub flags: u64,
+}
+
+/// Every command a cluster knows
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandDescriptions {
+    pub commands: Vec<CommandDesc>,
+}
+
+/// Descriptor fields are strings, except in some releases where req and n
+/// are JSON booleans and numbers
+fn field(desc: &Map<String, Value>, key: &str) -> Option<String> {
+    match desc.get(key)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn range<T: std::str::FromStr>(desc: &Map<String, Value>) -> (Option<T>, Option<T>) {
+    match field(desc, "range") {
+        Some(range) => {
+            let mut parts = range.split('|');
+            let min = parts.next().and_then(|p| p.parse().ok());
+            let max = parts.next().and_then(|p| p.parse().ok());
+            (min, max)
+        }
+        None => (None, None),
+    }
+}
+
+/// Check a value against a simple regex character class like
+/// `[A-Za-z0-9-_.]`.  Anything more complex is let through.
+fn goodchars_match(class: &str, value: &str) -> bool {
+    let inner: Vec<char> = match class.strip_prefix('[').and_then(|c| c.strip_suffix(']')) {
+        Some(inner) if !inner.starts_with('^') => inner.chars().collect(),
+        _ => return true,
+    };
+    let mut ranges = Vec::new();
+    let mut i = 0;
+    while i < inner.len() {
+        if i + 2 < inner.len() && inner[i + 1] == '-' {
+            ranges.push((inner[i], inner[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((inner[i], inner[i]));
+            i += 1;
+        }
+    }
+    value
+        .chars()
+        .all(|c| ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi))
+}
+
+impl ArgDesc {
+    fn parse(desc: &Map<String, Value>) -> RadosResult<ArgDesc> {
+        let name = field(desc, "name")
+            .ok_or_else(|| RadosError::new(format!("Argument without a name: {:?}", desc)))?;
+        let type_name = field(desc, "type").unwrap_or_default();
+        let arg_type = match type_name.as_str() {
+            "CephInt" => {
+                let (min, max) = range(desc);
+                ArgType::Int { min, max }
+            }
+            "CephFloat" => {
+                let (min, max) = range(desc);
+                ArgType::Float { min, max }
+            }
+            "CephBool" => ArgType::Bool,
+            "CephChoices" => ArgType::Choices(
+                field(desc, "strings")
+                    .unwrap_or_default()
+                    .split('|')
+                    .map(|s| s.to_string())
+                    .collect(),
+            ),
+            "CephString" => ArgType::String {
+                goodchars: field(desc, "goodchars"),
+            },
+            "CephPoolname" => ArgType::PoolName,
+            "CephObjectname" => ArgType::ObjectName,
+            "CephPgid" => ArgType::PgId,
+            "CephOsdName" => ArgType::OsdName,
+            "CephName" => ArgType::EntityName,
+            "CephEntityAddr" | "CephIPAddr" => ArgType::EntityAddr,
+            "CephUUID" => ArgType::Uuid,
+            "CephFilepath" => ArgType::FilePath,
+            _ => ArgType::Other(type_name),
+        };
+        Ok(ArgDesc {
+            name,
+            arg_type,
+            required: field(desc, "req").is_none_or(|r| r != "false"),
+            multiple: field(desc, "n").is_some_and(|n| n == "N"),
+        })
+    }
+
+    /// Check a value, or each value of a list argument
+    pub fn check(&self, value: &Value) -> RadosResult<()> {
+        match value {
+            Value::Array(values) if self.multiple => {
+                values.iter().try_for_each(|v| self.check_one(v))
+            }
+            _ => self.check_one(value),
+        }
+    }
+
+    fn invalid(&self, value: &Value, why: &str) -> RadosError {
+        RadosError::new(format!(
+            "Invalid value {} for argument {}: {}",
+            value, self.name, why
+        ))
+    }
+
+    fn check_one(&self, value: &Value) -> RadosResult<()> {
+        // Numbers and booleans are also accepted as strings
+        let text = match value {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            _ => return Err(self.invalid(value, "expected a single value")),
+        };
+        match &self.arg_type {
+            ArgType::Int { min, max } => {
+                let n: i64 = text
+                    .parse()
+                    .map_err(|_| self.invalid(value, "expected an integer"))?;
+                if min.is_some_and(|min| n < min) || max.is_some_and(|max| n > max) {
+                    return Err(self.invalid(value, &format!("out of range {:?}..{:?}", min, max)));
+                }
+            }
+            ArgType::Float { min, max } => {
+                let n: f64 = text
+                    .parse()
+                    .map_err(|_| self.invalid(value, "expected a number"))?;
+                if min.is_some_and(|min| n < min) || max.is_some_and(|max| n > max) {
+                    return Err(self.invalid(value, &format!("out of range {:?}..{:?}", min, max)));
+                }
+            }
+            ArgType::Bool => {
+                if text != "true" && text != "false" {
+                    return Err(self.invalid(value, "expected true or false"));
+                }
+            }
+            ArgType::Choices(choices) => {
+                if !choices.contains(&text) {
+                    return Err(
+                        self.invalid(value, &format!("expected one of {}", choices.join("|")))
+                    );
+                }
+            }
+            ArgType::String { goodchars } => {
+                if let Some(class) = goodchars {
+                    if !goodchars_match(class, &text) {
+                        return Err(
+                            self.invalid(value, &format!("allowed characters are {}", class))
+                        );
+                    }
+                }
+            }
+            ArgType::PgId => {
+                text.parse::<PgId>()
+                    .map_err(|_| self.invalid(value, "expected a pg id like 1.2f"))?;
+            }
+            ArgType::OsdName => {
+                let id = text.strip_prefix("osd.").unwrap_or(&text);
+                id.parse::<u32>()
+                    .map_err(|_| self.invalid(value, "expected osd.N or N"))?;
+            }
+            ArgType::EntityName => {
+                if !text.contains('.') {
+                    return Err(self.invalid(value, "expected a name like mon.a"));
+                }
+            }
+            ArgType::Uuid => {
+                text.parse::<Uuid>()
+                    .map_err(|_| self.invalid(value, "expected a UUID"))?;
+            }
+            ArgType::PoolName
+            | ArgType::ObjectName
+            | ArgType::EntityAddr
+            | ArgType::FilePath
+            | ArgType::Other(_) => {
+                if text.is_empty() {
+                    return Err(self.invalid(value, "expected a non-empty value"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CommandDesc {
+    /// The usage line, like `osd pool create <pool> [<pg_num:int>]`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.prefix.join(" "))?;
+        for arg in &self.args {
+            let dots = if arg.multiple { "..." } else { "" };
+            if arg.required {
+                write!(f, " <{}>{}", arg.name, dots)?;
+            } else {
+                write!(f, " [<{}>{}]", arg.name, dots)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CommandDesc {
+    pub fn prefix_string(&self) -> String {
+        self.prefix.join(" ")
+    }
+
+    pub fn arg(&self, name: &str) -> Option<&ArgDesc> {
+        self.args.iter().find(|a| a.name == name)
+    }
+
+    /// Check a command has every required argument, no unknown ones, and
+    /// values of the right type and range
+    pub fn validate(&self, cmd: &MonCommand) -> RadosResult<()> {
+        for arg in &self.args {
+            match cmd.get(&arg.name) {
+                Some(value) => arg.check(value)?,
+                None if arg.required => {
+                    return Err(RadosError::new(format!(
+                        "Missing required argument {} for {}",
+                        arg.name, self
+                    )))
+                }
+                None => {}
+            }
+        }
+        if let Some(args) = cmd.as_value().as_object() {
+            for name in args.keys() {
+                if name != "prefix" && name != "format" && self.arg(name).is_none() {
+                    return Err(RadosError::new(format!(
+                        "Unknown argument {} for {}",
+                        name, self
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct RawCommand {
+    sig: Vec<Value>,
+    #[serde(default)]
+    help: String,
+    #[serde(default)]
+    module: String,
+    #[serde(default)]
+    perm: String,
+    #[serde(default)]
+    flags: u64,
+}
+
+impl CommandDescriptions {
+    /// Parse the output of `get_command_descriptions`
+    pub fn from_json(json: &str) -> RadosResult<CommandDescriptions> {
+        // Keyed cmd000, cmd001... in the order the mons list them
+        let raw: BTreeMap<String, RawCommand> = serde_json::from_str(json)?;
+        let mut commands = Vec::with_capacity(raw.len());
+        for raw in raw.into_values() {
+            let mut prefix = Vec::new();
+            let mut args = Vec::new();
+            for word in raw.sig {
+                match word {
+                    Value::String(word) => prefix.push(word),
+                    Value::Object(desc) => {
+                        // Old releases describe the prefix words this way
+                        if field(&desc, "type").as_deref() == Some("CephPrefix") {
+                            prefix.extend(field(&desc, "prefix"));
+                        } else {
+                            args.push(ArgDesc::parse(&desc)?);
+                        }
+                    }
+                    other => {
+                        return Err(RadosError::new(format!(
+                            "Unexpected command signature element {}",
+                            other
+                        )))
+                    }
+                }
+            }
+            commands.push(CommandDesc {
+                prefix,
+                args,
+                help: raw.help,
+                module: raw.module,
+                perm: raw.perm,
+                flags: raw.flags,
+            });
+        }
+        Ok(CommandDescriptions { commands })
+    }
+
+    /// Signatures with this prefix.  A few commands have more than one.
+    pub fn find<'a>(&'a self, prefix: &str) -> impl Iterator<Item = &'a CommandDesc> + 'a {
+        let words: Vec<String> = prefix.split_whitespace().map(|w| w.to_string()).collect();
+        self.commands.iter().filter(move |c| c.prefix == words)
+    }
+
+    /// Check a command against the signature for its prefix
+    pub fn validate(&self, cmd: &MonCommand) -> RadosResult<()> {
+        let prefix = cmd
+            .prefix()
+            .ok_or_else(|| RadosError::new("Command has no prefix".to_string()))?;
+        let mut first_error = None;
+        for desc in self.find(prefix) {
+            match desc.validate(cmd) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error.unwrap_or_else(|| RadosError::new(format!("Unknown command {}", prefix))))
+    }
+}
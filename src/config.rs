@@ -0,0 +1,597 @@
+//! Read and write ceph.conf and keyring files without librados.
+//!
+//! Option names are normalized the way Ceph does it, so `mon host`,
+//! `mon_host` and ` mon  host ` are the same option.  Values are looked up
+//! in the entity's own section, then its type's, then `[global]`, and
+//! metavariables such as `$cluster` and `$id` are expanded on the way out.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{RadosError, RadosResult};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONF: &str = r#"
+# A comment
+[global]
+    fsid = 5a9d2b62-8d8e-4b4e-9a6c-2cf3f4ad0b5d
+    mon host = 10.0.0.1,10.0.0.2   ; trailing comment
+    keyring = /etc/ceph/$cluster.$name.keyring
+    log file = "/var/log/ceph/$cluster-$name.log"
+
+[client]
+    rados_osd_op_timeout = 30
+    admin socket = $run_dir/$cluster-$name.asok
+    run dir = /run/ceph
+
+[client.backup]
+    rados osd op timeout = 60
+    description = "a \"quoted\" # value"
+    long value = one \
+        two
+"#;
+
+    #[test]
+    fn it_parses_sections_and_normalizes_names() {
+        let conf = CephConf::parse(CONF).unwrap();
+        assert_eq!(
+            conf.section_names().collect::<Vec<_>>(),
+            vec!["global", "client", "client.backup"]
+        );
+        assert_eq!(
+            conf.get_raw("client.admin", "mon_host"),
+            Some("10.0.0.1,10.0.0.2")
+        );
+        assert_eq!(
+            conf.get_raw("client.admin", "rados-osd-op-timeout"),
+            Some("30")
+        );
+        assert_eq!(
+            conf.get_raw("client.backup", "rados osd op timeout"),
+            Some("60")
+        );
+        assert_eq!(conf.get_raw("osd.1", "rados_osd_op_timeout"), None);
+        assert_eq!(
+            conf.get_raw("client.backup", "description"),
+            Some("a \"quoted\" # value")
+        );
+        assert_eq!(conf.get_raw("client.backup", "long_value"), Some("one two"));
+    }
+
+    #[test]
+    fn it_expands_metavariables() {
+        let conf = CephConf::parse(CONF).unwrap();
+        assert_eq!(
+            conf.get("ceph", "client.admin", "keyring").as_deref(),
+            Some("/etc/ceph/ceph.client.admin.keyring")
+        );
+        assert_eq!(
+            conf.get("backup", "client.backup", "log_file").as_deref(),
+            Some("/var/log/ceph/backup-client.backup.log")
+        );
+        // Other options can be referenced too
+        assert_eq!(
+            conf.get("ceph", "client.admin", "admin_socket").as_deref(),
+            Some("/run/ceph/ceph-client.admin.asok")
+        );
+        let options = conf.options_for("ceph", "client.backup");
+        assert_eq!(options["rados_osd_op_timeout"], "60");
+        assert_eq!(options["mon_host"], "10.0.0.1,10.0.0.2");
+    }
+
+    #[test]
+    fn it_merges_overrides_and_writes_back() {
+        let mut conf = CephConf::parse(CONF).unwrap();
+        let site =
+            CephConf::parse("[global]\nmon_host = 10.1.0.1\n[osd]\nosd_memory_target = 4G\n")
+                .unwrap();
+        conf.merge(site);
+        assert_eq!(conf.get_raw("client.admin", "mon host"), Some("10.1.0.1"));
+        assert_eq!(conf.get_raw("osd.3", "osd memory target"), Some("4G"));
+
+        let reparsed = CephConf::parse(&conf.to_string()).unwrap();
+        assert_eq!(reparsed, conf);
+        assert!(CephConf::parse("key = value\n").is_err());
+        assert!(CephConf::parse("[global\n").is_err());
+    }
+
+    #[test]
+    fn it_round_trips_keyrings() {
+        let text = "[client.admin]\n\tkey = AQBS2hFjAAAAABAAp7eE4pVxE3T9UW8uE6N4Fg==\n\tcaps mds = \"allow *\"\n\tcaps mon = \"allow *\"\n\n[client.backup]\n\tkey = AQCx3hFjAAAAABAAm1lT0c1OvVq6yV0sY1Hj3w==\n\tcaps_osd = \"allow rw pool=backup\"\n";
+        let keyring = Keyring::parse(text).unwrap();
+        assert_eq!(
+            keyring.key("client.admin"),
+            Some("AQBS2hFjAAAAABAAp7eE4pVxE3T9UW8uE6N4Fg==")
+        );
+        let backup = keyring.entity("client.backup").unwrap();
+        assert_eq!(backup.caps["osd"], "allow rw pool=backup");
+
+        let mut keyring = keyring;
+        keyring.insert(
+            "client.new",
+            KeyringEntry::new("AQDy3hFjAAAAABAAd3Qm4R3lZ0d0sS2M9v1Q2w==")
+                .with_caps("mon", "allow r"),
+        );
+        let written = keyring.to_string();
+        assert!(written.starts_with(&text[..text.find("\n\n").unwrap()]));
+        assert_eq!(Keyring::parse(&written).unwrap(), keyring);
+        assert!(Keyring::parse("[client.x]\ncaps mon = allow r\n").is_err());
+    }
+}
+
+/// Ceph treats runs of spaces and underscores in option names the same,
+/// and the command line spells them with dashes
+pub fn normalize_name(name: &str) -> String {
+    name.split(|c: char| c.is_whitespace() || c == '_' || c == '-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// One `[name]` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub name: String,
+    /// Normalized option names and raw values, in file order
+    pub entries: Vec<(String, String)>,
+}
+
+impl Section {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_name(key);
+        self.entries
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let key = normalize_name(key);
+        match self.entries.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value.to_string(),
+            None => self.entries.push((key, value.to_string())),
+        }
+    }
+}
+
+/// A parsed ceph.conf
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CephConf {
+    sections: Vec<Section>,
+}
+
+impl CephConf {
+    pub fn parse(text: &str) -> RadosResult<CephConf> {
+        let mut conf = CephConf::default();
+        for (line_no, section, key, value) in parse_ini(text)? {
+            match (section, key) {
+                (Some(name), None) => {
+                    if conf.section(&name).is_none() {
+                        conf.sections.push(Section {
+                            name,
+                            entries: Vec::new(),
+                        });
+                    }
+                }
+                (Some(name), Some(key)) => conf.set(&name, &key, &value),
+                (None, _) => {
+                    return Err(RadosError::new(format!(
+                        "Line {}: option outside of a section",
+                        line_no
+                    )))
+                }
+            }
+        }
+        Ok(conf)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> RadosResult<CephConf> {
+        CephConf::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Read several files, each overriding the ones before it, e.g. a
+    /// packaged default followed by a site specific file
+    pub fn from_files<P: AsRef<Path>>(paths: &[P]) -> RadosResult<CephConf> {
+        let mut conf = CephConf::default();
+        for path in paths {
+            conf.merge(CephConf::from_file(path)?);
+        }
+        Ok(conf)
+    }
+
+    /// Apply the sections and options of other over this one
+    pub fn merge(&mut self, other: CephConf) {
+        for section in other.sections {
+            for (key, value) in &section.entries {
+                self.set(&section.name, key, value);
+            }
+            if self.section(&section.name).is_none() {
+                self.sections.push(Section {
+                    name: section.name,
+                    entries: Vec::new(),
+                });
+            }
+        }
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    pub fn section_names(&self) -> impl Iterator<Item = &str> {
+        self.sections.iter().map(|s| s.name.as_str())
+    }
+
+    /// Set an option, adding the section if needed
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        match self.sections.iter_mut().find(|s| s.name == section) {
+            Some(s) => s.set(key, value),
+            None => {
+                let mut s = Section {
+                    name: section.to_string(),
+                    entries: Vec::new(),
+                };
+                s.set(key, value);
+                self.sections.push(s);
+            }
+        }
+    }
+
+    /// The sections an entity such as `client.admin` reads, most specific
+    /// first
+    fn search_path(entity: &str) -> Vec<&str> {
+        let mut path = vec![entity];
+        if let Some(dot) = entity.find('.') {
+            path.push(&entity[..dot]);
+        }
+        path.push("global");
+        path
+    }
+
+    /// The value for an entity as written in the file
+    pub fn get_raw(&self, entity: &str, key: &str) -> Option<&str> {
+        CephConf::search_path(entity)
+            .into_iter()
+            .filter_map(|name| self.section(name))
+            .find_map(|s| s.get(key))
+    }
+
+    /// The value for an entity with metavariables expanded
+    pub fn get(&self, cluster: &str, entity: &str, key: &str) -> Option<String> {
+        let value = self.get_raw(entity, key)?;
+        Some(self.expand(cluster, entity, value))
+    }
+
+    /// Every option an entity sees, expanded, e.g. to pass to
+    /// `RadosBuilder::with_conf`
+    pub fn options_for(&self, cluster: &str, entity: &str) -> BTreeMap<String, String> {
+        let mut options = BTreeMap::new();
+        for name in CephConf::search_path(entity).into_iter().rev() {
+            if let Some(section) = self.section(name) {
+                for (key, value) in &section.entries {
+                    options.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        for value in options.values_mut() {
+            *value = self.expand(cluster, entity, value);
+        }
+        options
+    }
+
+    /// Expand `$cluster`, `$type`, `$id`, `$name`, `$host`, `$pid` and
+    /// `$home`, in either `$var` or `${var}` form.  Any other variable is
+    /// looked up as an option.
+    pub fn expand(&self, cluster: &str, entity: &str, value: &str) -> String {
+        self.expand_inner(cluster, entity, value, &mut Vec::new())
+    }
+
+    fn expand_inner(
+        &self,
+        cluster: &str,
+        entity: &str,
+        value: &str,
+        seen: &mut Vec<String>,
+    ) -> String {
+        let (entity_type, id) = match entity.find('.') {
+            Some(dot) => (&entity[..dot], &entity[dot + 1..]),
+            None => (entity, ""),
+        };
+        let mut out = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(pos) = rest.find('$') {
+            out.push_str(&rest[..pos]);
+            let after = &rest[pos + 1..];
+            let (var, consumed) = if let Some(braced) = after.strip_prefix('{') {
+                match braced.find('}') {
+                    Some(end) => (&braced[..end], end + 2),
+                    None => ("", 0),
+                }
+            } else {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            };
+            let replacement = match var {
+                "" => None,
+                "cluster" => Some(cluster.to_string()),
+                "type" => Some(entity_type.to_string()),
+                "id" => Some(id.to_string()),
+                "name" => Some(entity.to_string()),
+                "host" => Some(short_hostname()),
+                "pid" => Some(std::process::id().to_string()),
+                "home" => std::env::var("HOME").ok(),
+                _ => {
+                    let key = normalize_name(var);
+                    if seen.contains(&key) {
+                        None
+                    } else {
+                        self.get_raw(entity, &key).map(|v| {
+                            seen.push(key);
+                            let expanded = self.expand_inner(cluster, entity, v, seen);
+                            seen.pop();
+                            expanded
+                        })
+                    }
+                }
+            };
+            match replacement {
+                Some(replacement) => {
+                    out.push_str(&replacement);
+                    rest = &after[consumed..];
+                }
+                None => {
+                    out.push('$');
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+impl fmt::Display for CephConf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, section) in self.sections.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "[{}]", section.name)?;
+            for (key, value) in &section.entries {
+                writeln!(f, "\t{} = {}", key.replace('_', " "), quote(value))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A keyring entry, the secret and capabilities of one entity
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyringEntry {
+    /// base64 secret
+    pub key: String,
+    /// Capabilities by service, e.g. "mon" => "allow r"
+    pub caps: BTreeMap<String, String>,
+}
+
+impl KeyringEntry {
+    pub fn new(key: &str) -> KeyringEntry {
+        KeyringEntry {
+            key: key.to_string(),
+            caps: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_caps(mut self, service: &str, caps: &str) -> KeyringEntry {
+        self.caps.insert(service.to_string(), caps.to_string());
+        self
+    }
+}
+
+/// A keyring file such as /etc/ceph/ceph.client.admin.keyring
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Keyring {
+    pub entities: BTreeMap<String, KeyringEntry>,
+}
+
+impl Keyring {
+    pub fn parse(text: &str) -> RadosResult<Keyring> {
+        let conf = CephConf::parse(text)?;
+        let mut keyring = Keyring::default();
+        for section in conf.sections {
+            let name = section.name;
+            let mut key = None;
+            let mut caps = BTreeMap::new();
+            for (option, value) in section.entries {
+                let mut words = option.split('_');
+                match (words.next(), words.next()) {
+                    (Some("key"), None) => key = Some(value),
+                    (Some("caps"), Some(service)) => {
+                        caps.insert(service.to_string(), value);
+                    }
+                    // auid and other old fields
+                    _ => {}
+                }
+            }
+            let key =
+                key.ok_or_else(|| RadosError::new(format!("Keyring entry {} has no key", name)))?;
+            keyring.entities.insert(name, KeyringEntry { key, caps });
+        }
+        Ok(keyring)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> RadosResult<Keyring> {
+        Keyring::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Write the keyring, readable only by the owner on Unix
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> RadosResult<()> {
+        use std::io::Write;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        file.write_all(self.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    pub fn entity(&self, name: &str) -> Option<&KeyringEntry> {
+        self.entities.get(name)
+    }
+
+    /// The secret for an entity, e.g. to pass to `RadosBuilder::with_key`
+    pub fn key(&self, name: &str) -> Option<&str> {
+        self.entities.get(name).map(|e| e.key.as_str())
+    }
+
+    pub fn insert(&mut self, name: &str, entry: KeyringEntry) {
+        self.entities.insert(name.to_string(), entry);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<KeyringEntry> {
+        self.entities.remove(name)
+    }
+}
+
+impl fmt::Display for Keyring {
+    /// The layout `ceph auth get` prints
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (name, entry)) in self.entities.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "[{}]", name)?;
+            writeln!(f, "\tkey = {}", entry.key)?;
+            for (service, caps) in &entry.caps {
+                writeln!(f, "\tcaps {} = \"{}\"", service, escape(caps))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Quote values that wouldn't survive being read back bare
+fn quote(value: &str) -> String {
+    let needs_quotes =
+        value.is_empty() || value.trim() != value || value.contains(['#', ';', '"', '\\']);
+    if needs_quotes {
+        format!("\"{}\"", escape(value))
+    } else {
+        value.to_string()
+    }
+}
+
+fn short_hostname() -> String {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ret != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    let host = String::from_utf8_lossy(&buf[..len]).into_owned();
+    match host.find('.') {
+        Some(dot) => host[..dot].to_string(),
+        None => host,
+    }
+}
+
+/// A section header or an option, with the line it started on
+type IniItem = (usize, Option<String>, Option<String>, String);
+
+/// Split the file into section headers and options.  Handles comments,
+/// backslash continuations, quoted values and escapes.
+fn parse_ini(text: &str) -> RadosResult<Vec<IniItem>> {
+    let mut items = Vec::new();
+    let mut section: Option<String> = None;
+    let mut lines = text.lines().enumerate();
+    while let Some((i, line)) = lines.next() {
+        let line_no = i + 1;
+        let mut line = line.to_string();
+        // Join continuation lines
+        while line.ends_with('\\') && !line.ends_with("\\\\") {
+            line.pop();
+            match lines.next() {
+                Some((_, next)) => line.push_str(next.trim_start()),
+                None => break,
+            }
+        }
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = trimmed.strip_prefix('[') {
+            let end = header.find(']').ok_or_else(|| {
+                RadosError::new(format!("Line {}: unterminated section header", line_no))
+            })?;
+            let name = header[..end].trim().to_string();
+            if name.is_empty() {
+                return Err(RadosError::new(format!(
+                    "Line {}: empty section name",
+                    line_no
+                )));
+            }
+            section = Some(name.clone());
+            items.push((line_no, Some(name), None, String::new()));
+            continue;
+        }
+        let eq = trimmed
+            .find('=')
+            .ok_or_else(|| RadosError::new(format!("Line {}: expected key = value", line_no)))?;
+        let key = normalize_name(&trimmed[..eq]);
+        if key.is_empty() {
+            return Err(RadosError::new(format!(
+                "Line {}: empty option name",
+                line_no
+            )));
+        }
+        let value = parse_value(&trimmed[eq + 1..])
+            .map_err(|e| RadosError::new(format!("Line {}: {}", line_no, e)))?;
+        items.push((line_no, section.clone(), Some(key), value));
+    }
+    Ok(items)
+}
+
+fn parse_value(raw: &str) -> Result<String, &'static str> {
+    let raw = raw.trim_start();
+    let mut value = String::new();
+    let mut chars = raw.chars();
+    if let Some(quoted) = raw.strip_prefix('"') {
+        chars = quoted.chars();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => value.extend(chars.next()),
+                Some(c) => value.push(c),
+                None => return Err("unterminated quote"),
+            }
+        }
+        let rest = chars.as_str().trim_start();
+        if !(rest.is_empty() || rest.starts_with('#') || rest.starts_with(';')) {
+            return Err("text after quoted value");
+        }
+        return Ok(value);
+    }
+    while let Some(c) = chars.next() {
+        match c {
+            '#' | ';' => break,
+            '\\' => value.extend(chars.next()),
+            c => value.push(c),
+        }
+    }
+    Ok(value.trim_end().to_string())
+}
@@ -0,0 +1,309 @@
+//! Copy or move an object, including its xattrs and omap, to another name,
+//! namespace or pool.
+//!
+//! The copy is done on the OSDs with a copy-from write operation when the
+//! cluster supports it.  Within one pool and namespace a clone_range is tried
+//! next, and if neither works the object is streamed through the client.
+
+use std::time::UNIX_EPOCH;
+
+use libc::time_t;
+
+use crate::ceph::{IoCtx, WriteOperation};
+use crate::error::{RadosError, RadosResult};
+use crate::transfer::{chunks, DEFAULT_CHUNK_SIZE};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(pool_id: i64, namespace: &str, locator_key: &str) -> Target {
+        Target {
+            pool_id,
+            namespace: namespace.to_string(),
+            locator_key: locator_key.to_string(),
+        }
+    }
+
+    #[test]
+    fn it_rejects_copying_an_object_onto_itself() {
+        // As if from two handles opened separately on the same pool
+        let (src, dst) = (target(3, "ns", ""), target(3, "ns", ""));
+        assert!(check_not_self_copy(&src, "obj", &dst, "obj").is_err());
+        assert!(check_not_self_copy(&src, "obj", &dst, "obj2").is_ok());
+        assert!(check_not_self_copy(&src, "obj", &target(4, "ns", ""), "obj").is_ok());
+        assert!(check_not_self_copy(&src, "obj", &target(3, "", ""), "obj").is_ok());
+        assert!(check_not_self_copy(&src, "obj", &target(3, "ns", "key"), "obj").is_ok());
+    }
+}
+
+/// Options for `IoCtx::copy_object`
+#[derive(Debug, Clone)]
+pub struct CopyOptions {
+    preserve_mtime: bool,
+    delete_source: bool,
+    server_side: bool,
+    chunk_size: usize,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        CopyOptions {
+            preserve_mtime: false,
+            delete_source: false,
+            server_side: true,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+impl CopyOptions {
+    pub fn new() -> CopyOptions {
+        CopyOptions::default()
+    }
+
+    /// Give the copy the source's modification time instead of the current
+    /// time
+    pub fn with_preserve_mtime(mut self, preserve_mtime: bool) -> CopyOptions {
+        self.preserve_mtime = preserve_mtime;
+        self
+    }
+
+    /// Remove the source once the copy has succeeded, turning the copy into
+    /// a move
+    pub fn with_delete_source(mut self, delete_source: bool) -> CopyOptions {
+        self.delete_source = delete_source;
+        self
+    }
+
+    /// Try copy-from and clone_range before streaming.  Disable to always
+    /// stream the object through the client.
+    pub fn with_server_side(mut self, server_side: bool) -> CopyOptions {
+        self.server_side = server_side;
+        self
+    }
+
+    /// Size of each read and write when streaming
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> CopyOptions {
+        self.chunk_size = chunk_size;
+        self
+    }
+}
+
+/// How `IoCtx::copy_object` copied the object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyMethod {
+    /// A copy-from operation on the destination OSD
+    CopyFrom,
+    /// A clone_range within the same placement group
+    CloneRange,
+    /// Read by the client and written back
+    Streaming,
+}
+
+/// Errors that mean a server side copy isn't possible, as opposed to the
+/// copy itself failing
+fn unsupported(err: &RadosError) -> bool {
+    matches!(
+        err,
+        RadosError::ApiError(nix::errno::Errno::EOPNOTSUPP)
+            | RadosError::ApiError(nix::errno::Errno::ENOSYS)
+            | RadosError::ApiError(nix::errno::Errno::EXDEV)
+    )
+}
+
+/// Where an io context puts objects: the same pool, namespace and locator
+/// key mean the same objects, even through separate handles
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Target {
+    pool_id: i64,
+    namespace: String,
+    locator_key: String,
+}
+
+impl Target {
+    fn of(ioctx: &IoCtx) -> RadosResult<Target> {
+        Ok(Target {
+            pool_id: ioctx.rados_object_get_id()?,
+            namespace: ioctx.rados_get_namespace()?,
+            locator_key: ioctx.rados_get_locator_key(),
+        })
+    }
+}
+
+fn check_not_self_copy(
+    src: &Target,
+    src_object_name: &str,
+    dst: &Target,
+    dst_object_name: &str,
+) -> RadosResult<()> {
+    if src == dst && src_object_name == dst_object_name {
+        return Err(RadosError::new(format!(
+            "Cannot copy {} onto itself",
+            src_object_name
+        )));
+    }
+    Ok(())
+}
+
+fn remove_if_exists(ioctx: &IoCtx, object_name: &str) -> RadosResult<()> {
+    match ioctx.rados_object_remove(object_name) {
+        Err(RadosError::ApiError(nix::errno::Errno::ENOENT)) => Ok(()),
+        other => other,
+    }
+}
+
+fn copy_from(
+    src: &IoCtx,
+    src_object_name: &str,
+    dst: &IoCtx,
+    dst_object_name: &str,
+    mtime: time_t,
+) -> RadosResult<()> {
+    // Pin the copy to the version we just stat'd
+    let src_version = src.rados_get_object_last_version()?;
+    let mut op = WriteOperation::new(dst_object_name);
+    op.mtime = mtime;
+    op.copy_from(src, src_object_name, src_version)?;
+    dst.rados_commit_write_operations(&mut op)
+}
+
+/// Write the xattrs, omap and mtime of the source onto the destination along
+/// with the final step of the data copy
+fn finish_copy(
+    src: &IoCtx,
+    src_object_name: &str,
+    dst: &IoCtx,
+    mut op: WriteOperation,
+) -> RadosResult<()> {
+    for (name, value) in src.rados_object_getxattrs(src_object_name)? {
+        op.setxattr(&name, &value)?;
+    }
+    let omap = src.rados_object_omap_get_all(src_object_name)?;
+    if !omap.is_empty() {
+        op.omap_set(&omap)?;
+    }
+    dst.rados_commit_write_operations(&mut op)
+}
+
+fn clone_range(
+    ioctx: &IoCtx,
+    src_object_name: &str,
+    dst_object_name: &str,
+    size: u64,
+    mtime: time_t,
+) -> RadosResult<()> {
+    remove_if_exists(ioctx, dst_object_name)?;
+    ioctx.rados_object_clone_range(dst_object_name, 0, src_object_name, 0, size as usize)?;
+    let mut op = WriteOperation::new(dst_object_name);
+    op.mtime = mtime;
+    op.create(false);
+    op.truncate(size);
+    finish_copy(ioctx, src_object_name, ioctx, op)
+}
+
+fn stream(
+    src: &IoCtx,
+    src_object_name: &str,
+    dst: &IoCtx,
+    dst_object_name: &str,
+    size: u64,
+    mtime: time_t,
+    chunk_size: usize,
+) -> RadosResult<()> {
+    remove_if_exists(dst, dst_object_name)?;
+    let mut op = WriteOperation::new(dst_object_name);
+    op.mtime = mtime;
+    op.create(false);
+
+    let mut pieces = chunks(0, size, chunk_size);
+    // The last chunk goes out with the xattrs and omap so the mtime is set
+    // by the final write
+    let last = pieces.pop();
+    for (offset, len) in pieces {
+        let mut buffer: Vec<u8> = Vec::with_capacity(len);
+        src.rados_object_read(src_object_name, &mut buffer, offset)?;
+        if buffer.len() != len {
+            return Err(RadosError::new(format!(
+                "{} changed while it was being copied",
+                src_object_name
+            )));
+        }
+        dst.rados_object_write(dst_object_name, &buffer, offset)?;
+    }
+    if let Some((offset, len)) = last {
+        let mut buffer: Vec<u8> = Vec::with_capacity(len);
+        src.rados_object_read(src_object_name, &mut buffer, offset)?;
+        if buffer.len() != len {
+            return Err(RadosError::new(format!(
+                "{} changed while it was being copied",
+                src_object_name
+            )));
+        }
+        op.write(&buffer, offset);
+    }
+    finish_copy(src, src_object_name, dst, op)
+}
+
+pub(crate) fn copy_object(
+    src: &IoCtx,
+    src_object_name: &str,
+    dst: &IoCtx,
+    dst_object_name: &str,
+    opts: &CopyOptions,
+) -> RadosResult<CopyMethod> {
+    let (src_target, dst_target) = (Target::of(src)?, Target::of(dst)?);
+    check_not_self_copy(&src_target, src_object_name, &dst_target, dst_object_name)?;
+    let same_target = src_target == dst_target;
+    if opts.chunk_size == 0 {
+        return Err(RadosError::new(
+            "chunk_size must be greater than 0".to_string(),
+        ));
+    }
+
+    let (size, modified) = src.rados_object_stat(src_object_name)?;
+    let mtime: time_t = if opts.preserve_mtime {
+        modified
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as time_t)
+            .unwrap_or(0)
+    } else {
+        0
+    };
+
+    let mut method = CopyMethod::Streaming;
+    if opts.server_side {
+        match copy_from(src, src_object_name, dst, dst_object_name, mtime) {
+            Ok(()) => method = CopyMethod::CopyFrom,
+            Err(ref e) if unsupported(e) => {
+                debug!("copy-from {} unsupported: {:?}", src_object_name, e);
+                if same_target {
+                    match clone_range(src, src_object_name, dst_object_name, size, mtime) {
+                        Ok(()) => method = CopyMethod::CloneRange,
+                        Err(ref e) if unsupported(e) => {
+                            debug!("clone_range {} unsupported: {:?}", src_object_name, e);
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if method == CopyMethod::Streaming {
+        stream(
+            src,
+            src_object_name,
+            dst,
+            dst_object_name,
+            size,
+            mtime,
+            opts.chunk_size,
+        )?;
+    }
+
+    if opts.delete_source {
+        src.rados_object_remove(src_object_name)?;
+    }
+    Ok(method)
+}
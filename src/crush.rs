@@ -0,0 +1,2735 @@
+//! A model of a CRUSH map and a port of Ceph's CRUSH mapper, so objects can
+//! be placed on OSDs without asking the cluster.
+//!
+//! The map is built from the JSON printed by `osd crush dump`.  Mapping
+//! follows `crush_do_rule` in Ceph's mapper.c step for step, including the
+//! legacy bucket algorithms and tunables, so the results match the OSDs'.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use nom::error::{Error, ErrorKind};
+
+use crate::crush_ln_table::{LL_TBL, RH_LH_TBL};
+use crate::denc::{parse_error, Decode, Encode, IResult};
+use crate::error::{RadosError, RadosResult};
+
+/// An empty slot in the result of an indep (erasure coded) rule
+pub const CRUSH_ITEM_NONE: i32 = 0x7fff_ffff;
+const CRUSH_ITEM_UNDEF: i32 = 0x7fff_fffe;
+
+/// The choose_args used when a pool has none of its own
+pub const DEFAULT_CHOOSE_ARGS: i64 = -1;
+
+/// Weight of a fully in OSD, 1.0 in 16.16 fixed point
+pub const WEIGHT_ONE: u32 = 0x10000;
+
+const CRUSH_HASH_SEED: u32 = 1_315_423_911;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn straw2_host(id: i32, name: &str, osds: &[i32]) -> Bucket {
+        Bucket {
+            id,
+            name: name.into(),
+            type_id: 1,
+            alg: BucketAlg::Straw2,
+            hash: 0,
+            items: osds.to_vec(),
+            weights: vec![WEIGHT_ONE; osds.len()],
+            derived: Vec::new(),
+        }
+    }
+
+    fn three_hosts() -> CrushMap {
+        let mut map = CrushMap::default();
+        map.types.insert(0, "osd".into());
+        map.types.insert(1, "host".into());
+        map.types.insert(10, "root".into());
+        for osd in 0..6 {
+            map.devices.insert(
+                osd,
+                Device {
+                    name: format!("osd.{}", osd),
+                    class: None,
+                },
+            );
+        }
+        map.insert_bucket(straw2_host(-2, "a", &[0, 1]));
+        map.insert_bucket(straw2_host(-3, "b", &[2, 3]));
+        map.insert_bucket(straw2_host(-4, "c", &[4, 5]));
+        let mut root = straw2_host(-1, "default", &[-2, -3, -4]);
+        root.type_id = 10;
+        root.weights = vec![2 * WEIGHT_ONE; 3];
+        map.insert_bucket(root);
+        map.rules.push(Rule {
+            id: 0,
+            name: "replicated_rule".into(),
+            ruleset: 0,
+            rule_type: 1,
+            min_size: 1,
+            max_size: 10,
+            steps: vec![
+                RuleStep::Take { item: -1 },
+                RuleStep::ChooseLeafFirstN { num: 0, type_id: 1 },
+                RuleStep::Emit,
+            ],
+        });
+        map
+    }
+
+    #[test]
+    fn it_computes_fixed_point_logs() {
+        // crush_ln(x) is 2^44 * log2(x + 1)
+        assert_eq!(crush_ln(0), 0);
+        assert_eq!(crush_ln(1), 1 << 44);
+        assert_eq!(crush_ln(0xffff), 16 << 44);
+        let ln = crush_ln(999) as f64 / (1u64 << 44) as f64;
+        assert!((ln - 1000f64.log2()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn it_builds_tree_nodes() {
+        // Three items need a tree of depth 3 with the root at node 4
+        let weights = calc_tree_node_weights(&[1, 2, 4]);
+        assert_eq!(weights.len(), 8);
+        assert_eq!(weights[1], 1);
+        assert_eq!(weights[3], 2);
+        assert_eq!(weights[5], 4);
+        assert_eq!(weights[2], 3);
+        assert_eq!(weights[4], 7);
+    }
+
+    #[test]
+    fn it_places_replicas_on_distinct_hosts() {
+        let map = three_hosts();
+        let weights = vec![WEIGHT_ONE; 6];
+        for x in 0..200 {
+            let osds = map.do_rule(0, x, 3, &weights, DEFAULT_CHOOSE_ARGS);
+            assert_eq!(osds.len(), 3);
+            let mut hosts: Vec<i32> = osds.iter().map(|o| o / 2).collect();
+            hosts.sort_unstable();
+            hosts.dedup();
+            assert_eq!(hosts.len(), 3, "{:?}", osds);
+        }
+    }
+
+    #[test]
+    fn it_skips_out_osds() {
+        let map = three_hosts();
+        let mut weights = vec![WEIGHT_ONE; 6];
+        weights[0] = 0;
+        weights[1] = 0;
+        for x in 0..200 {
+            let osds = map.do_rule(0, x, 3, &weights, DEFAULT_CHOOSE_ARGS);
+            assert_eq!(osds.len(), 2);
+            assert!(!osds.contains(&0) && !osds.contains(&1));
+        }
+    }
+
+    #[test]
+    fn it_edits_the_hierarchy() {
+        let mut map = three_hosts();
+        map.types.insert(3, "rack".into());
+        let rack = map.add_bucket("rack1", "rack").unwrap();
+        assert_eq!(rack, -5);
+        assert!(map.add_bucket("rack1", "rack").is_err());
+        assert!(map.add_bucket("rack2", "osd").is_err());
+
+        map.link(rack, -1, 0).unwrap();
+        map.move_bucket(-2, rack).unwrap();
+        assert_eq!(map.parents(-2), vec![rack]);
+        assert_eq!(map.bucket(rack).unwrap().weight(), 2 * WEIGHT_ONE);
+        assert_eq!(map.bucket(-1).unwrap().weight(), 6 * WEIGHT_ONE);
+        assert!(map.move_bucket(-1, rack).is_err());
+
+        map.set_item_weight(0, WEIGHT_ONE / 2).unwrap();
+        assert_eq!(map.bucket(rack).unwrap().weight(), 3 * WEIGHT_ONE / 2);
+        assert_eq!(map.bucket(-1).unwrap().weight(), 11 * WEIGHT_ONE / 2);
+
+        assert!(map.remove_bucket(rack).is_err());
+        map.unlink(-2, rack).unwrap();
+        map.remove_bucket(rack).unwrap();
+        assert_eq!(map.item_id("rack1"), None);
+        assert_eq!(map.bucket(-1).unwrap().weight(), 4 * WEIGHT_ONE);
+    }
+
+    #[test]
+    fn it_builds_class_trees() {
+        let mut map = three_hosts();
+        for osd in 0..6 {
+            let class = if osd < 4 { "hdd" } else { "ssd" };
+            map.add_device(osd, &format!("osd.{}", osd), Some(class))
+                .unwrap();
+        }
+        let hdd = map.class_id("hdd").unwrap();
+        let hdd_root = map.class_buckets[&-1][&hdd];
+        assert_eq!(map.bucket_by_name("default~hdd").unwrap().id, hdd_root);
+        assert_eq!(map.bucket(hdd_root).unwrap().weight(), 4 * WEIGHT_ONE);
+        assert!(map.is_shadow(hdd_root));
+        assert!(map.bucket_by_name("c~hdd").unwrap().items.is_empty());
+
+        let rule = map
+            .add_simple_rule("fast", "default", "host", Some("ssd"), RULE_TYPE_REPLICATED)
+            .unwrap();
+        assert_eq!(rule, 1);
+        let weights = vec![WEIGHT_ONE; 6];
+        for x in 0..50 {
+            let osds = map.do_rule(rule, x, 3, &weights, DEFAULT_CHOOSE_ARGS);
+            assert_eq!(osds.len(), 1);
+            assert!(osds[0] >= 4);
+        }
+
+        // Shadow buckets keep their ids when the tree is rebuilt
+        map.set_item_weight(0, 2 * WEIGHT_ONE).unwrap();
+        assert_eq!(map.class_buckets[&-1][&hdd], hdd_root);
+        assert_eq!(map.bucket(hdd_root).unwrap().weight(), 5 * WEIGHT_ONE);
+    }
+
+    #[test]
+    fn it_round_trips_binary_maps() {
+        let mut map = three_hosts();
+        map.add_device(0, "osd.0", Some("ssd")).unwrap();
+        map.add_simple_rule("ec", "default", "host", None, RULE_TYPE_ERASURE)
+            .unwrap();
+        let bytes = map.encode();
+        let decoded = CrushMap::decode(&bytes).unwrap();
+        assert_eq!(decoded.encode(), bytes);
+        assert_eq!(decoded.rule_by_name("ec").unwrap().min_size, 3);
+        assert_eq!(decoded.devices[&0].class.as_deref(), Some("ssd"));
+        assert!(CrushMap::decode(&bytes[..bytes.len() - 1]).is_err());
+
+        // Straws and tree nodes from an old map match what's computed here
+        let jewel = CrushMap::decode(include_bytes!("../tests/crushmap-jewel")).unwrap();
+        for bucket in jewel.buckets() {
+            let mut recomputed = bucket.clone();
+            recomputed.calc_derived(jewel.tunables.straw_calc_version as u32);
+            assert_eq!(&recomputed, bucket);
+        }
+    }
+
+    #[test]
+    fn it_decodes_bucket_algs() {
+        for alg in &[
+            BucketAlg::Uniform,
+            BucketAlg::List,
+            BucketAlg::Tree,
+            BucketAlg::Straw,
+            BucketAlg::Straw2,
+        ] {
+            assert_eq!(BucketAlg::from_raw(alg.as_raw()), Some(*alg));
+            assert_eq!(alg.to_string().parse::<BucketAlg>().unwrap(), *alg);
+        }
+    }
+}
+
+/// The robert jenkins mix used by every CRUSH hash
+pub(crate) fn hashmix(a: &mut u32, b: &mut u32, c: &mut u32) {
+    *a = a.wrapping_sub(*b).wrapping_sub(*c) ^ (*c >> 13);
+    *b = b.wrapping_sub(*c).wrapping_sub(*a) ^ (*a << 8);
+    *c = c.wrapping_sub(*a).wrapping_sub(*b) ^ (*b >> 13);
+    *a = a.wrapping_sub(*b).wrapping_sub(*c) ^ (*c >> 12);
+    *b = b.wrapping_sub(*c).wrapping_sub(*a) ^ (*a << 16);
+    *c = c.wrapping_sub(*a).wrapping_sub(*b) ^ (*b >> 5);
+    *a = a.wrapping_sub(*b).wrapping_sub(*c) ^ (*c >> 3);
+    *b = b.wrapping_sub(*c).wrapping_sub(*a) ^ (*a << 10);
+    *c = c.wrapping_sub(*a).wrapping_sub(*b) ^ (*b >> 15);
+}
+
+/// crush_hash32_2 with the rjenkins1 hash
+pub fn hash32_2(mut a: u32, mut b: u32) -> u32 {
+    let mut hash = CRUSH_HASH_SEED ^ a ^ b;
+    let (mut x, mut y) = (231_232, 1232);
+    hashmix(&mut a, &mut b, &mut hash);
+    hashmix(&mut x, &mut a, &mut hash);
+    hashmix(&mut b, &mut y, &mut hash);
+    hash
+}
+
+/// crush_hash32_3 with the rjenkins1 hash
+pub fn hash32_3(mut a: u32, mut b: u32, mut c: u32) -> u32 {
+    let mut hash = CRUSH_HASH_SEED ^ a ^ b ^ c;
+    let (mut x, mut y) = (231_232, 1232);
+    hashmix(&mut a, &mut b, &mut hash);
+    hashmix(&mut c, &mut x, &mut hash);
+    hashmix(&mut y, &mut a, &mut hash);
+    hashmix(&mut b, &mut x, &mut hash);
+    hashmix(&mut y, &mut c, &mut hash);
+    hash
+}
+
+/// crush_hash32_4 with the rjenkins1 hash
+pub fn hash32_4(mut a: u32, mut b: u32, mut c: u32, mut d: u32) -> u32 {
+    let mut hash = CRUSH_HASH_SEED ^ a ^ b ^ c ^ d;
+    let (mut x, mut y) = (231_232, 1232);
+    hashmix(&mut a, &mut b, &mut hash);
+    hashmix(&mut c, &mut d, &mut hash);
+    hashmix(&mut a, &mut x, &mut hash);
+    hashmix(&mut y, &mut b, &mut hash);
+    hashmix(&mut c, &mut x, &mut hash);
+    hashmix(&mut y, &mut d, &mut hash);
+    hash
+}
+
+/// 2^44 * log2(xin + 1) in fixed point, for xin up to 0xffff
+fn crush_ln(xin: u32) -> u64 {
+    let mut x = xin + 1;
+    let mut iexpon: u64 = 15;
+
+    // Normalize x into [2^15, 2^16]
+    if x & 0x18000 == 0 {
+        let bits = (x & 0x1ffff).leading_zeros() - 16;
+        x <<= bits;
+        iexpon = 15 - bits as u64;
+    }
+
+    let index1 = ((x >> 8) << 1) as usize;
+    // RH ~ 2^56 / index1 and LH ~ 2^48 * log2(index1 / 256)
+    let rh = RH_LH_TBL[index1 - 256];
+    let lh = RH_LH_TBL[index1 + 1 - 256];
+
+    // x * RH ~ 2^48 * (2^15 + xf), which the LL table finishes off
+    let xl64 = (x as u64 * rh) >> 48;
+    let ll = LL_TBL[(xl64 & 0xff) as usize];
+    (iexpon << 44) + ((lh + ll) >> 4)
+}
+
+/// How a bucket picks among its items
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BucketAlg {
+    Uniform,
+    List,
+    Tree,
+    Straw,
+    Straw2,
+}
+
+impl BucketAlg {
+    pub fn from_raw(alg: u8) -> Option<BucketAlg> {
+        match alg {
+            1 => Some(BucketAlg::Uniform),
+            2 => Some(BucketAlg::List),
+            3 => Some(BucketAlg::Tree),
+            4 => Some(BucketAlg::Straw),
+            5 => Some(BucketAlg::Straw2),
+            _ => None,
+        }
+    }
+
+    pub fn as_raw(self) -> u8 {
+        match self {
+            BucketAlg::Uniform => 1,
+            BucketAlg::List => 2,
+            BucketAlg::Tree => 3,
+            BucketAlg::Straw => 4,
+            BucketAlg::Straw2 => 5,
+        }
+    }
+}
+
+impl std::fmt::Display for BucketAlg {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            BucketAlg::Uniform => "uniform",
+            BucketAlg::List => "list",
+            BucketAlg::Tree => "tree",
+            BucketAlg::Straw => "straw",
+            BucketAlg::Straw2 => "straw2",
+        };
+        f.write_str(name)
+    }
+}
+
+impl std::str::FromStr for BucketAlg {
+    type Err = RadosError;
+
+    fn from_str(s: &str) -> RadosResult<BucketAlg> {
+        match s {
+            "uniform" => Ok(BucketAlg::Uniform),
+            "list" => Ok(BucketAlg::List),
+            "tree" => Ok(BucketAlg::Tree),
+            "straw" => Ok(BucketAlg::Straw),
+            "straw2" => Ok(BucketAlg::Straw2),
+            _ => Err(RadosError::new(format!("Unknown bucket alg {}", s))),
+        }
+    }
+}
+
+/// A bucket in the hierarchy.  Ids are negative, devices are >= 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    pub id: i32,
+    pub name: String,
+    pub type_id: i32,
+    pub alg: BucketAlg,
+    /// Always 0 (rjenkins1) in practice
+    pub hash: u8,
+    pub items: Vec<i32>,
+    /// 16.16 fixed point weight of each item
+    pub weights: Vec<u32>,
+    /// Per algorithm data derived from the weights: list sum weights, tree
+    /// node weights or straw lengths
+    pub(crate) derived: Vec<u32>,
+}
+
+impl Bucket {
+    /// Total 16.16 weight of the bucket
+    pub fn weight(&self) -> u32 {
+        self.weights
+            .iter()
+            .fold(0u32, |sum, w| sum.wrapping_add(*w))
+    }
+
+    /// Recompute the data the mapper needs after items or weights change
+    pub(crate) fn calc_derived(&mut self, straw_calc_version: u32) {
+        self.derived = match self.alg {
+            BucketAlg::Uniform | BucketAlg::Straw2 => Vec::new(),
+            BucketAlg::List => calc_sum_weights(&self.weights),
+            BucketAlg::Tree => calc_tree_node_weights(&self.weights),
+            BucketAlg::Straw => calc_straws(&self.weights, straw_calc_version),
+        };
+    }
+}
+
+/// Running totals of the weights, highest index last
+fn calc_sum_weights(weights: &[u32]) -> Vec<u32> {
+    weights
+        .iter()
+        .scan(0u32, |sum, w| {
+            *sum = sum.wrapping_add(*w);
+            Some(*sum)
+        })
+        .collect()
+}
+
+fn tree_height(n: usize) -> u32 {
+    n.trailing_zeros()
+}
+
+fn tree_parent(n: usize) -> usize {
+    let h = tree_height(n);
+    if n & (1 << (h + 1)) != 0 {
+        n - (1 << h)
+    } else {
+        n + (1 << h)
+    }
+}
+
+fn tree_depth(size: usize) -> u32 {
+    if size == 0 {
+        return 0;
+    }
+    let mut depth = 1;
+    let mut t = size - 1;
+    while t != 0 {
+        t >>= 1;
+        depth += 1;
+    }
+    depth
+}
+
+/// Item i sits at node 2i+1; every other node holds the sum of its subtree
+fn calc_tree_node_weights(weights: &[u32]) -> Vec<u32> {
+    let depth = tree_depth(weights.len());
+    let mut nodes = vec![0u32; 1 << depth];
+    for (i, weight) in weights.iter().enumerate() {
+        let mut node = ((i + 1) << 1) - 1;
+        nodes[node] = *weight;
+        for _ in 1..depth {
+            node = tree_parent(node);
+            nodes[node] = nodes[node].wrapping_add(*weight);
+        }
+    }
+    nodes
+}
+
+/// Straw lengths for a straw bucket, as crush_calc_straw computes them
+fn calc_straws(weights: &[u32], straw_calc_version: u32) -> Vec<u32> {
+    let size = weights.len();
+    let mut straws = vec![0u32; size];
+
+    // Item indices sorted by weight, stable so ties keep insertion order
+    let mut reverse: Vec<usize> = (0..size).collect();
+    reverse.sort_by_key(|i| weights[*i]);
+
+    let mut numleft = size as f64;
+    let mut straw = 1.0f64;
+    let mut wbelow = 0.0f64;
+    let mut lastw = 0.0f64;
+
+    let mut i = 0;
+    while i < size {
+        if straw_calc_version == 0 {
+            // Zero weight items get a zero straw
+            if weights[reverse[i]] == 0 {
+                straws[reverse[i]] = 0;
+                i += 1;
+                continue;
+            }
+
+            // Same weight as the previous item means the same straw
+            straws[reverse[i]] = (straw * 65536.0) as u32;
+            i += 1;
+            if i == size {
+                break;
+            }
+            if weights[reverse[i]] == weights[reverse[i - 1]] {
+                continue;
+            }
+
+            wbelow += (weights[reverse[i - 1]] as f64 - lastw) * numleft;
+            let mut j = i;
+            while j < size {
+                if weights[reverse[j]] == weights[reverse[i]] {
+                    numleft -= 1.0;
+                } else {
+                    break;
+                }
+                j += 1;
+            }
+            let wnext = numleft * (weights[reverse[i]] - weights[reverse[i - 1]]) as f64;
+            let pbelow = wbelow / (wbelow + wnext);
+            straw *= (1.0 / pbelow).powf(1.0 / numleft);
+            lastw = weights[reverse[i - 1]] as f64;
+        } else {
+            if weights[reverse[i]] == 0 {
+                straws[reverse[i]] = 0;
+                i += 1;
+                numleft -= 1.0;
+                continue;
+            }
+
+            straws[reverse[i]] = (straw * 65536.0) as u32;
+            i += 1;
+            if i == size {
+                break;
+            }
+
+            wbelow += (weights[reverse[i - 1]] as f64 - lastw) * numleft;
+            numleft -= 1.0;
+            let wnext = numleft * (weights[reverse[i]] - weights[reverse[i - 1]]) as f64;
+            let pbelow = wbelow / (wbelow + wnext);
+            straw *= (1.0 / pbelow).powf(1.0 / numleft);
+            lastw = weights[reverse[i - 1]] as f64;
+        }
+    }
+    straws
+}
+
+/// One step of a rule.  `num` counts from the pool size when it's <= 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleStep {
+    Noop,
+    Take {
+        item: i32,
+    },
+    ChooseFirstN {
+        num: i32,
+        type_id: i32,
+    },
+    ChooseIndep {
+        num: i32,
+        type_id: i32,
+    },
+    Emit,
+    ChooseLeafFirstN {
+        num: i32,
+        type_id: i32,
+    },
+    ChooseLeafIndep {
+        num: i32,
+        type_id: i32,
+    },
+    SetChooseTries(i32),
+    SetChooseLeafTries(i32),
+    SetChooseLocalTries(i32),
+    SetChooseLocalFallbackTries(i32),
+    SetChooseLeafVaryR(i32),
+    SetChooseLeafStable(i32),
+    /// An op this crate doesn't map with, such as Squid's MSR steps.  Kept
+    /// so the map encodes back unchanged.
+    Unknown {
+        op: u32,
+        arg1: i32,
+        arg2: i32,
+    },
+}
+
+impl RuleStep {
+    /// Build a step from its op code and arguments
+    pub fn from_raw(op: u32, arg1: i32, arg2: i32) -> RuleStep {
+        match op {
+            0 => RuleStep::Noop,
+            1 => RuleStep::Take { item: arg1 },
+            2 => RuleStep::ChooseFirstN {
+                num: arg1,
+                type_id: arg2,
+            },
+            3 => RuleStep::ChooseIndep {
+                num: arg1,
+                type_id: arg2,
+            },
+            4 => RuleStep::Emit,
+            6 => RuleStep::ChooseLeafFirstN {
+                num: arg1,
+                type_id: arg2,
+            },
+            7 => RuleStep::ChooseLeafIndep {
+                num: arg1,
+                type_id: arg2,
+            },
+            8 => RuleStep::SetChooseTries(arg1),
+            9 => RuleStep::SetChooseLeafTries(arg1),
+            10 => RuleStep::SetChooseLocalTries(arg1),
+            11 => RuleStep::SetChooseLocalFallbackTries(arg1),
+            12 => RuleStep::SetChooseLeafVaryR(arg1),
+            13 => RuleStep::SetChooseLeafStable(arg1),
+            _ => RuleStep::Unknown { op, arg1, arg2 },
+        }
+    }
+
+    /// The op code and arguments of this step
+    pub fn as_raw(&self) -> (u32, i32, i32) {
+        match *self {
+            RuleStep::Noop => (0, 0, 0),
+            RuleStep::Take { item } => (1, item, 0),
+            RuleStep::ChooseFirstN { num, type_id } => (2, num, type_id),
+            RuleStep::ChooseIndep { num, type_id } => (3, num, type_id),
+            RuleStep::Emit => (4, 0, 0),
+            RuleStep::ChooseLeafFirstN { num, type_id } => (6, num, type_id),
+            RuleStep::ChooseLeafIndep { num, type_id } => (7, num, type_id),
+            RuleStep::SetChooseTries(n) => (8, n, 0),
+            RuleStep::SetChooseLeafTries(n) => (9, n, 0),
+            RuleStep::SetChooseLocalTries(n) => (10, n, 0),
+            RuleStep::SetChooseLocalFallbackTries(n) => (11, n, 0),
+            RuleStep::SetChooseLeafVaryR(n) => (12, n, 0),
+            RuleStep::SetChooseLeafStable(n) => (13, n, 0),
+            RuleStep::Unknown { op, arg1, arg2 } => (op, arg1, arg2),
+        }
+    }
+}
+
+/// Rule type of rules for replicated pools
+pub const RULE_TYPE_REPLICATED: u8 = 1;
+/// Rule type of rules for erasure coded pools
+pub const RULE_TYPE_ERASURE: u8 = 3;
+
+/// A placement rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub id: u32,
+    pub name: String,
+    /// Pools referred to rules by ruleset before Luminous.  The same as the
+    /// id on any map made by the tools.
+    pub ruleset: u8,
+    /// RULE_TYPE_REPLICATED or RULE_TYPE_ERASURE
+    pub rule_type: u8,
+    /// Ignored since Quincy
+    pub min_size: u8,
+    /// Ignored since Quincy
+    pub max_size: u8,
+    pub steps: Vec<RuleStep>,
+}
+
+/// The tunables that change how CRUSH retries.  The defaults are the jewel
+/// (optimal) profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tunables {
+    pub choose_local_tries: u32,
+    pub choose_local_fallback_tries: u32,
+    pub choose_total_tries: u32,
+    pub chooseleaf_descend_once: u32,
+    pub chooseleaf_vary_r: u8,
+    pub chooseleaf_stable: u8,
+    pub straw_calc_version: u8,
+    pub allowed_bucket_algs: u32,
+    /// Squid and later
+    pub msr_descents: Option<u32>,
+    /// Squid and later
+    pub msr_collision_tries: Option<u32>,
+}
+
+impl Tunables {
+    /// The argonaut tunables a map has when it doesn't say otherwise
+    pub fn legacy() -> Tunables {
+        Tunables {
+            choose_local_tries: 2,
+            choose_local_fallback_tries: 5,
+            choose_total_tries: 19,
+            chooseleaf_descend_once: 0,
+            chooseleaf_vary_r: 0,
+            chooseleaf_stable: 0,
+            straw_calc_version: 0,
+            // uniform, list and straw
+            allowed_bucket_algs: 0x16,
+            msr_descents: None,
+            msr_collision_tries: None,
+        }
+    }
+}
+
+impl Default for Tunables {
+    fn default() -> Self {
+        Tunables {
+            choose_local_tries: 0,
+            choose_local_fallback_tries: 0,
+            choose_total_tries: 50,
+            chooseleaf_descend_once: 1,
+            chooseleaf_vary_r: 1,
+            chooseleaf_stable: 1,
+            straw_calc_version: 1,
+            allowed_bucket_algs: 0x36,
+            msr_descents: None,
+            msr_collision_tries: None,
+        }
+    }
+}
+
+/// A device (OSD) in the map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub name: String,
+    /// Device class, hdd, ssd or nvme usually
+    pub class: Option<String>,
+}
+
+/// Per bucket overrides a pool can use in place of the bucket's own weights
+/// and ids, as set up by the balancer in crush-compat mode
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChooseArg {
+    /// Ids to hash with in place of the item ids
+    pub ids: Option<Vec<i32>>,
+    /// One set of 16.16 weights per replica position, the last reused for
+    /// any positions past the end
+    pub weight_set: Vec<Vec<u32>>,
+}
+
+/// A CRUSH map
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CrushMap {
+    /// Indexed by -1 - id
+    buckets: Vec<Option<Bucket>>,
+    /// Size of the device array, which can run past the last device
+    max_devices: i32,
+    /// Size of the rule array, which can run past the last rule
+    max_rules: u32,
+    pub rules: Vec<Rule>,
+    pub types: BTreeMap<i32, String>,
+    pub devices: BTreeMap<i32, Device>,
+    pub tunables: Tunables,
+    /// Device class names by class id
+    pub classes: BTreeMap<i32, String>,
+    /// The per class shadow copy of each bucket: bucket id to class id to
+    /// shadow bucket id.  Shadow buckets are named `<bucket>~<class>`.
+    pub class_buckets: BTreeMap<i32, BTreeMap<i32, i32>>,
+    /// choose_args keyed by pool id or DEFAULT_CHOOSE_ARGS, then bucket id
+    pub choose_args: BTreeMap<i64, BTreeMap<i32, ChooseArg>>,
+}
+
+/// Position in the bucket array for a bucket id
+fn bucket_index(id: i32) -> Option<usize> {
+    if id < 0 {
+        Some((-1 - id as i64) as usize)
+    } else {
+        None
+    }
+}
+
+impl CrushMap {
+    /// Look up a bucket by its (negative) id
+    pub fn bucket(&self, id: i32) -> Option<&Bucket> {
+        bucket_index(id)
+            .and_then(|i| self.buckets.get(i))
+            .and_then(|b| b.as_ref())
+    }
+
+    /// Look up a bucket by name
+    pub fn bucket_by_name(&self, name: &str) -> Option<&Bucket> {
+        self.buckets().find(|b| b.name == name)
+    }
+
+    /// All buckets, highest id first
+    pub fn buckets(&self) -> impl Iterator<Item = &Bucket> {
+        self.buckets.iter().filter_map(|b| b.as_ref())
+    }
+
+    /// Look up a rule by id
+    pub fn rule(&self, id: u32) -> Option<&Rule> {
+        self.rules.iter().find(|r| r.id == id)
+    }
+
+    /// Look up a rule by name
+    pub fn rule_by_name(&self, name: &str) -> Option<&Rule> {
+        self.rules.iter().find(|r| r.name == name)
+    }
+
+    /// Look up a type id by name
+    pub fn type_id(&self, name: &str) -> Option<i32> {
+        self.types
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(id, _)| *id)
+    }
+
+    /// One more than the highest device id
+    pub fn max_devices(&self) -> i32 {
+        self.devices
+            .keys()
+            .next_back()
+            .map_or(0, |id| id + 1)
+            .max(self.max_devices)
+    }
+
+    /// Insert or replace a bucket, computing its derived data
+    pub(crate) fn insert_bucket(&mut self, mut bucket: Bucket) {
+        bucket.calc_derived(self.tunables.straw_calc_version as u32);
+        let index = bucket_index(bucket.id).expect("bucket ids are negative");
+        // Grow the way crush_add_bucket does so encoded maps match Ceph's
+        let mut len = self.buckets.len();
+        while len <= index {
+            len = if len == 0 { 8 } else { len * 2 };
+        }
+        self.buckets.resize(len, None);
+        self.buckets[index] = Some(bucket);
+    }
+
+    /// Parse the JSON printed by `osd crush dump`
+    pub fn from_json(json: &str) -> RadosResult<CrushMap> {
+        let dump: CrushDump = serde_json::from_str(json)?;
+        dump.into_map()
+    }
+
+    /// Run a rule for input x, returning up to result_max items.  weights are
+    /// the 16.16 reweight values of each OSD, 0 meaning out.  choose_args
+    /// picks the weight set to use, falling back to the default one.
+    pub fn do_rule(
+        &self,
+        rule_id: u32,
+        x: i32,
+        result_max: usize,
+        weights: &[u32],
+        choose_args: i64,
+    ) -> Vec<i32> {
+        let rule = match self.rule(rule_id) {
+            Some(rule) => rule,
+            None => return Vec::new(),
+        };
+        let choose_args = self
+            .choose_args
+            .get(&choose_args)
+            .or_else(|| self.choose_args.get(&DEFAULT_CHOOSE_ARGS));
+        let mut mapper = Mapper {
+            map: self,
+            weights,
+            choose_args,
+            work: self
+                .buckets
+                .iter()
+                .map(|b| PermWork {
+                    perm_x: 0,
+                    perm_n: 0,
+                    perm: vec![0; b.as_ref().map_or(0, |b| b.items.len())],
+                })
+                .collect(),
+        };
+        mapper.do_rule(rule, x, result_max)
+    }
+}
+
+/// Ceph's is_valid_crush_name
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+impl CrushMap {
+    /// Look up a device class id by name
+    pub fn class_id(&self, name: &str) -> Option<i32> {
+        self.classes
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(id, _)| *id)
+    }
+
+    /// Look up a bucket or device id by name
+    pub fn item_id(&self, name: &str) -> Option<i32> {
+        self.bucket_by_name(name).map(|b| b.id).or_else(|| {
+            self.devices
+                .iter()
+                .find(|(_, d)| d.name == name)
+                .map(|(id, _)| *id)
+        })
+    }
+
+    /// The name of a bucket or device
+    pub fn item_name(&self, id: i32) -> Option<&str> {
+        if id < 0 {
+            self.bucket(id).map(|b| b.name.as_str())
+        } else {
+            self.devices.get(&id).map(|d| d.name.as_str())
+        }
+    }
+
+    /// Whether a bucket is the per class shadow copy of another
+    pub fn is_shadow(&self, id: i32) -> bool {
+        self.bucket(id).is_some_and(|b| b.name.contains('~'))
+    }
+
+    /// The buckets an item is linked under, leaving out shadow buckets
+    pub fn parents(&self, item: i32) -> Vec<i32> {
+        self.buckets()
+            .filter(|b| !b.name.contains('~') && b.items.contains(&item))
+            .map(|b| b.id)
+            .collect()
+    }
+
+    /// Whether item is somewhere below ancestor
+    fn is_below(&self, item: i32, ancestor: i32) -> bool {
+        self.bucket(ancestor).is_some_and(|b| {
+            b.items
+                .iter()
+                .any(|i| *i == item || (*i < 0 && self.is_below(item, *i)))
+        })
+    }
+
+    fn bucket_mut(&mut self, id: i32) -> Option<&mut Bucket> {
+        bucket_index(id)
+            .and_then(move |i| self.buckets.get_mut(i))
+            .and_then(|b| b.as_mut())
+    }
+
+    fn editable_bucket(&self, id: i32) -> RadosResult<&Bucket> {
+        match self.bucket(id) {
+            Some(b) if !b.name.contains('~') => Ok(b),
+            Some(b) => Err(RadosError::new(format!(
+                "{} is a device class shadow bucket",
+                b.name
+            ))),
+            None => Err(RadosError::new(format!("Bucket {} does not exist", id))),
+        }
+    }
+
+    /// Update a bucket's weight in each of its parents, all the way up
+    fn adjust_ancestors(&mut self, id: i32) {
+        let weight = match self.bucket(id) {
+            Some(b) => b.weight(),
+            None => return,
+        };
+        let straw_calc_version = self.tunables.straw_calc_version as u32;
+        for parent in self.parents(id) {
+            if let Some(b) = self.bucket_mut(parent) {
+                for (item, w) in b.items.iter().zip(b.weights.iter_mut()) {
+                    if *item == id {
+                        *w = weight;
+                    }
+                }
+                b.calc_derived(straw_calc_version);
+            }
+            self.adjust_ancestors(parent);
+        }
+    }
+
+    fn link_item(&mut self, item: i32, parent: i32, weight: u32) -> RadosResult<()> {
+        let bucket = self.editable_bucket(parent)?;
+        if bucket.items.contains(&item) {
+            return Err(RadosError::new(format!(
+                "{} is already in {}",
+                item, bucket.name
+            )));
+        }
+        if bucket.alg == BucketAlg::Uniform && bucket.weights.first().is_some_and(|w| *w != weight)
+        {
+            return Err(RadosError::new(format!(
+                "Items in uniform bucket {} must all have the same weight",
+                bucket.name
+            )));
+        }
+        if item < 0 {
+            self.editable_bucket(item)?;
+            if item == parent || self.is_below(parent, item) {
+                return Err(RadosError::new(format!(
+                    "Linking {} under {} would make a loop",
+                    item, parent
+                )));
+            }
+        } else if !self.devices.contains_key(&item) {
+            return Err(RadosError::new(format!("Device {} does not exist", item)));
+        }
+
+        let straw_calc_version = self.tunables.straw_calc_version as u32;
+        if let Some(b) = self.bucket_mut(parent) {
+            b.items.push(item);
+            b.weights.push(weight);
+            b.calc_derived(straw_calc_version);
+        }
+        for args in self.choose_args.values_mut() {
+            if let Some(arg) = args.get_mut(&parent) {
+                for weights in arg.weight_set.iter_mut() {
+                    weights.push(weight);
+                }
+                if let Some(ids) = arg.ids.as_mut() {
+                    ids.push(item);
+                }
+            }
+        }
+        self.adjust_ancestors(parent);
+        Ok(())
+    }
+
+    fn unlink_item(&mut self, item: i32, parent: i32) -> RadosResult<()> {
+        let bucket = self.editable_bucket(parent)?;
+        let pos = bucket
+            .items
+            .iter()
+            .position(|i| *i == item)
+            .ok_or_else(|| RadosError::new(format!("{} is not in {}", item, bucket.name)))?;
+
+        let straw_calc_version = self.tunables.straw_calc_version as u32;
+        if let Some(b) = self.bucket_mut(parent) {
+            b.items.remove(pos);
+            b.weights.remove(pos);
+            b.calc_derived(straw_calc_version);
+        }
+        for args in self.choose_args.values_mut() {
+            if let Some(arg) = args.get_mut(&parent) {
+                for weights in arg.weight_set.iter_mut() {
+                    if pos < weights.len() {
+                        weights.remove(pos);
+                    }
+                }
+                if let Some(ids) = arg.ids.as_mut() {
+                    if pos < ids.len() {
+                        ids.remove(pos);
+                    }
+                }
+            }
+        }
+        self.adjust_ancestors(parent);
+        Ok(())
+    }
+
+    /// Add a device, or update the name and class of an existing one.  It
+    /// still has to be linked into a bucket to get any data.
+    pub fn add_device(&mut self, id: i32, name: &str, class: Option<&str>) -> RadosResult<()> {
+        if id < 0 || !valid_name(name) {
+            return Err(RadosError::new(format!("Invalid device {} {}", id, name)));
+        }
+        if self.item_id(name).is_some_and(|other| other != id) {
+            return Err(RadosError::new(format!("{} already exists", name)));
+        }
+        if let Some(class) = class {
+            if !valid_name(class) {
+                return Err(RadosError::new(format!("Invalid device class {}", class)));
+            }
+            if self.class_id(class).is_none() {
+                let class_id = self.classes.keys().next_back().map_or(0, |id| id + 1);
+                self.classes.insert(class_id, class.to_string());
+            }
+        }
+        self.devices.insert(
+            id,
+            Device {
+                name: name.to_string(),
+                class: class.map(|c| c.to_string()),
+            },
+        );
+        self.rebuild_class_trees();
+        Ok(())
+    }
+
+    /// Create an empty straw2 bucket, not linked under anything yet, and
+    /// return its id
+    pub fn add_bucket(&mut self, name: &str, type_name: &str) -> RadosResult<i32> {
+        if !valid_name(name) {
+            return Err(RadosError::new(format!("Invalid bucket name {}", name)));
+        }
+        if self.item_id(name).is_some() {
+            return Err(RadosError::new(format!("{} already exists", name)));
+        }
+        let type_id = match self.type_id(type_name) {
+            Some(0) | None => {
+                return Err(RadosError::new(format!(
+                    "{} is not a bucket type",
+                    type_name
+                )))
+            }
+            Some(type_id) => type_id,
+        };
+        let index = self
+            .buckets
+            .iter()
+            .position(|b| b.is_none())
+            .unwrap_or(self.buckets.len());
+        let id = -1 - index as i32;
+        self.insert_bucket(Bucket {
+            id,
+            name: name.to_string(),
+            type_id,
+            alg: BucketAlg::Straw2,
+            hash: 0,
+            items: Vec::new(),
+            weights: Vec::new(),
+            derived: Vec::new(),
+        });
+        self.rebuild_class_trees();
+        Ok(id)
+    }
+
+    /// Remove an empty bucket that no rule uses
+    pub fn remove_bucket(&mut self, id: i32) -> RadosResult<()> {
+        let bucket = self.editable_bucket(id)?;
+        if !bucket.items.is_empty() {
+            return Err(RadosError::new(format!("{} is not empty", bucket.name)));
+        }
+        let shadows: Vec<i32> = self
+            .class_buckets
+            .get(&id)
+            .map(|m| m.values().copied().collect())
+            .unwrap_or_default();
+        let in_use = self.rules.iter().any(|r| {
+            r.steps.iter().any(|s| match s {
+                RuleStep::Take { item } => *item == id || shadows.contains(item),
+                _ => false,
+            })
+        });
+        if in_use {
+            return Err(RadosError::new(format!(
+                "{} is used by a rule",
+                bucket.name
+            )));
+        }
+        for parent in self.parents(id) {
+            self.unlink_item(id, parent)?;
+        }
+        if let Some(index) = bucket_index(id) {
+            self.buckets[index] = None;
+        }
+        for args in self.choose_args.values_mut() {
+            args.remove(&id);
+        }
+        self.class_buckets.remove(&id);
+        self.rebuild_class_trees();
+        Ok(())
+    }
+
+    /// Link a bucket or device under a bucket with a 16.16 weight.  The
+    /// weights of the buckets above are updated to match.
+    pub fn link(&mut self, item: i32, parent: i32, weight: u32) -> RadosResult<()> {
+        self.link_item(item, parent, weight)?;
+        self.rebuild_class_trees();
+        Ok(())
+    }
+
+    /// Remove an item from one bucket
+    pub fn unlink(&mut self, item: i32, parent: i32) -> RadosResult<()> {
+        self.unlink_item(item, parent)?;
+        self.rebuild_class_trees();
+        Ok(())
+    }
+
+    /// Move a bucket, with its current weight, from wherever it is to under
+    /// new_parent, as `osd crush move` does
+    pub fn move_bucket(&mut self, id: i32, new_parent: i32) -> RadosResult<()> {
+        let weight = self.editable_bucket(id)?.weight();
+        self.editable_bucket(new_parent)?;
+        if id == new_parent || self.is_below(new_parent, id) {
+            return Err(RadosError::new(format!(
+                "Moving {} under {} would make a loop",
+                id, new_parent
+            )));
+        }
+        for parent in self.parents(id) {
+            self.unlink_item(id, parent)?;
+        }
+        self.link_item(id, new_parent, weight)?;
+        self.rebuild_class_trees();
+        Ok(())
+    }
+
+    /// Set the 16.16 weight of an item everywhere it's linked, as
+    /// `osd crush reweight` does
+    pub fn set_item_weight(&mut self, item: i32, weight: u32) -> RadosResult<()> {
+        let parents = self.parents(item);
+        if parents.is_empty() {
+            return Err(RadosError::new(format!("{} is not linked anywhere", item)));
+        }
+        let straw_calc_version = self.tunables.straw_calc_version as u32;
+        for parent in parents {
+            if let Some(b) = self.bucket_mut(parent) {
+                if b.alg == BucketAlg::Uniform {
+                    for w in b.weights.iter_mut() {
+                        *w = weight;
+                    }
+                } else {
+                    for (i, w) in b.items.iter().zip(b.weights.iter_mut()) {
+                        if *i == item {
+                            *w = weight;
+                        }
+                    }
+                }
+                b.calc_derived(straw_calc_version);
+            }
+            self.adjust_ancestors(parent);
+        }
+        self.rebuild_class_trees();
+        Ok(())
+    }
+
+    /// Add a rule with the given steps and return its id
+    pub fn add_rule(
+        &mut self,
+        name: &str,
+        rule_type: u8,
+        steps: Vec<RuleStep>,
+    ) -> RadosResult<u32> {
+        if !valid_name(name) {
+            return Err(RadosError::new(format!("Invalid rule name {}", name)));
+        }
+        if self.rule_by_name(name).is_some() {
+            return Err(RadosError::new(format!("Rule {} already exists", name)));
+        }
+        let id = (0..=255u32)
+            .find(|id| self.rule(*id).is_none())
+            .ok_or_else(|| RadosError::new("No free rule ids".to_string()))?;
+        let (min_size, max_size) = if rule_type == RULE_TYPE_ERASURE {
+            (3, 20)
+        } else {
+            (1, 10)
+        };
+        self.rules.push(Rule {
+            id,
+            name: name.to_string(),
+            ruleset: id as u8,
+            rule_type,
+            min_size,
+            max_size,
+            steps,
+        });
+        self.rules.sort_by_key(|r| r.id);
+        Ok(id)
+    }
+
+    /// Add a rule that puts each replica, or each shard for
+    /// RULE_TYPE_ERASURE, in a different failure_domain under root, as
+    /// `osd crush rule create-replicated` and `create-erasure` do
+    pub fn add_simple_rule(
+        &mut self,
+        name: &str,
+        root: &str,
+        failure_domain: &str,
+        device_class: Option<&str>,
+        rule_type: u8,
+    ) -> RadosResult<u32> {
+        let mut root_id = self
+            .bucket_by_name(root)
+            .map(|b| b.id)
+            .ok_or_else(|| RadosError::new(format!("Bucket {} does not exist", root)))?;
+        let type_id = self
+            .type_id(failure_domain)
+            .ok_or_else(|| RadosError::new(format!("Unknown CRUSH type {}", failure_domain)))?;
+        if let Some(class) = device_class {
+            root_id = self
+                .class_id(class)
+                .and_then(|c| self.class_buckets.get(&root_id)?.get(&c))
+                .copied()
+                .ok_or_else(|| {
+                    RadosError::new(format!("{} has no devices of class {}", root, class))
+                })?;
+        }
+
+        let indep = rule_type == RULE_TYPE_ERASURE;
+        let mut steps = Vec::new();
+        if indep {
+            steps.push(RuleStep::SetChooseLeafTries(5));
+            steps.push(RuleStep::SetChooseTries(100));
+        }
+        steps.push(RuleStep::Take { item: root_id });
+        steps.push(match (indep, type_id) {
+            (false, 0) => RuleStep::ChooseFirstN { num: 0, type_id },
+            (false, _) => RuleStep::ChooseLeafFirstN { num: 0, type_id },
+            (true, 0) => RuleStep::ChooseIndep { num: 0, type_id },
+            (true, _) => RuleStep::ChooseLeafIndep { num: 0, type_id },
+        });
+        steps.push(RuleStep::Emit);
+        self.add_rule(name, rule_type, steps)
+    }
+
+    /// Remove a rule.  Pools still using it would stop mapping.
+    pub fn remove_rule(&mut self, id: u32) -> RadosResult<()> {
+        let pos = self
+            .rules
+            .iter()
+            .position(|r| r.id == id)
+            .ok_or_else(|| RadosError::new(format!("Rule {} does not exist", id)))?;
+        // The rule array doesn't shrink
+        self.max_rules = self.max_rules.max(id + 1);
+        self.rules.remove(pos);
+        Ok(())
+    }
+
+    /// Rebuild the per class shadow trees from the real hierarchy, keeping
+    /// the ids shadow buckets already had.  The editing methods call this,
+    /// it only needs calling after changing the fields directly.
+    pub fn rebuild_class_trees(&mut self) {
+        let old = std::mem::take(&mut self.class_buckets);
+
+        // Drop classes no device or rule uses any more
+        let dead: Vec<i32> = self
+            .classes
+            .iter()
+            .filter(|(id, name)| {
+                let used_by_device = self
+                    .devices
+                    .values()
+                    .any(|d| d.class.as_deref() == Some(name.as_str()));
+                let used_by_rule = self.rules.iter().any(|r| {
+                    r.steps.iter().any(|s| match s {
+                        RuleStep::Take { item } => old.values().any(|m| m.get(id) == Some(item)),
+                        _ => false,
+                    })
+                });
+                !used_by_device && !used_by_rule
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in dead {
+            self.classes.remove(&id);
+        }
+
+        let shadows: Vec<i32> = self
+            .buckets()
+            .filter(|b| b.name.contains('~'))
+            .map(|b| b.id)
+            .collect();
+        for id in shadows {
+            if let Some(index) = bucket_index(id) {
+                self.buckets[index] = None;
+            }
+            for args in self.choose_args.values_mut() {
+                args.remove(&id);
+            }
+        }
+
+        let used_ids: Vec<i32> = old.values().flat_map(|m| m.values().copied()).collect();
+        let mut roots: Vec<i32> = self
+            .buckets()
+            .map(|b| b.id)
+            .filter(|id| self.parents(*id).is_empty())
+            .collect();
+        roots.sort_unstable();
+        let classes: Vec<i32> = self.classes.keys().copied().collect();
+        let mut arg_weights = BTreeMap::new();
+        for root in roots {
+            for class in &classes {
+                self.clone_for_class(root, *class, &old, &used_ids, &mut arg_weights);
+            }
+        }
+    }
+
+    /// Ceph's device_class_clone: copy a bucket keeping only the devices of
+    /// one class
+    fn clone_for_class(
+        &mut self,
+        original: i32,
+        class: i32,
+        old: &BTreeMap<i32, BTreeMap<i32, i32>>,
+        used_ids: &[i32],
+        arg_weights: &mut BTreeMap<i64, BTreeMap<i32, Vec<u32>>>,
+    ) -> i32 {
+        let orig = match self.bucket(original) {
+            Some(b) => b.clone(),
+            None => return 0,
+        };
+        let class_name = self.classes.get(&class).cloned().unwrap_or_default();
+        let name = format!("{}~{}", orig.name, class_name);
+        if let Some(existing) = self.bucket_by_name(&name) {
+            return existing.id;
+        }
+
+        let mut items = Vec::new();
+        let mut weights = Vec::new();
+        let mut orig_pos = Vec::new();
+        for (i, item) in orig.items.iter().enumerate() {
+            if *item >= 0 {
+                let device_class = self.devices.get(item).and_then(|d| d.class.as_deref());
+                if device_class != Some(class_name.as_str()) {
+                    continue;
+                }
+                items.push(*item);
+                weights.push(orig.weights[i]);
+            } else {
+                let child = self.clone_for_class(*item, class, old, used_ids, arg_weights);
+                items.push(child);
+                weights.push(self.bucket(child).map_or(0, |b| b.weight()));
+            }
+            orig_pos.push(i);
+        }
+
+        let id = match old.get(&original).and_then(|m| m.get(&class)) {
+            Some(id) => *id,
+            None => {
+                // Never reuse an id a shadow bucket had before
+                let mut id = -1;
+                while self.bucket(id).is_some() || used_ids.contains(&id) {
+                    id -= 1;
+                }
+                id
+            }
+        };
+        self.insert_bucket(Bucket {
+            id,
+            name,
+            type_id: orig.type_id,
+            alg: orig.alg,
+            hash: orig.hash,
+            items: items.clone(),
+            weights,
+            derived: Vec::new(),
+        });
+        self.class_buckets
+            .entry(original)
+            .or_default()
+            .insert(class, id);
+
+        // Carry weight sets over, summing the children's as they're built
+        for (key, args) in self.choose_args.iter_mut() {
+            let arg = match args.get(&original) {
+                Some(arg) if !arg.weight_set.is_empty() => arg.clone(),
+                _ => continue,
+            };
+            let child_weights = arg_weights.entry(*key).or_default();
+            let weight_set: Vec<Vec<u32>> = arg
+                .weight_set
+                .iter()
+                .enumerate()
+                .map(|(position, set)| {
+                    items
+                        .iter()
+                        .zip(orig_pos.iter())
+                        .map(|(item, pos)| {
+                            if *item >= 0 {
+                                set.get(*pos).copied().unwrap_or(0)
+                            } else {
+                                child_weights
+                                    .get(item)
+                                    .and_then(|w| w.get(position))
+                                    .copied()
+                                    .unwrap_or(0)
+                            }
+                        })
+                        .collect()
+                })
+                .collect();
+            child_weights.insert(
+                id,
+                weight_set
+                    .iter()
+                    .map(|set| set.iter().fold(0u32, |sum, w| sum.wrapping_add(*w)))
+                    .collect(),
+            );
+            args.insert(
+                id,
+                ChooseArg {
+                    ids: None,
+                    weight_set,
+                },
+            );
+        }
+        id
+    }
+}
+
+const CRUSH_MAGIC: u32 = 0x0001_0000;
+
+impl CrushMap {
+    /// Decode the compiled map returned by `osd getcrushmap` or written by
+    /// `crushtool -c`
+    pub fn decode(bytes: &[u8]) -> RadosResult<CrushMap> {
+        decode_crush(bytes)
+            .map(|(_, map)| map)
+            .map_err(|e| parse_error(bytes, e))
+    }
+
+    /// Decode a compiled map saved to a file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> RadosResult<CrushMap> {
+        CrushMap::decode(&fs::read(path)?)
+    }
+
+    /// Encode the map for `osd setcrushmap`.  The Luminous format is always
+    /// written, whatever the map was decoded from.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        CRUSH_MAGIC.encode(&mut buf);
+        (self.buckets.len() as i32).encode(&mut buf);
+        let max_rules = self
+            .rules
+            .iter()
+            .map(|r| r.id + 1)
+            .fold(self.max_rules, u32::max);
+        max_rules.encode(&mut buf);
+        self.max_devices().encode(&mut buf);
+
+        for bucket in &self.buckets {
+            match bucket {
+                Some(bucket) => encode_bucket(bucket, &mut buf),
+                None => 0u32.encode(&mut buf),
+            }
+        }
+
+        for id in 0..max_rules {
+            let rule = match self.rule(id) {
+                Some(rule) => rule,
+                None => {
+                    0u32.encode(&mut buf);
+                    continue;
+                }
+            };
+            1u32.encode(&mut buf);
+            (rule.steps.len() as u32).encode(&mut buf);
+            buf.extend_from_slice(&[rule.ruleset, rule.rule_type, rule.min_size, rule.max_size]);
+            for step in &rule.steps {
+                let (op, arg1, arg2) = step.as_raw();
+                op.encode(&mut buf);
+                arg1.encode(&mut buf);
+                arg2.encode(&mut buf);
+            }
+        }
+
+        self.types.encode(&mut buf);
+        let mut names: BTreeMap<i32, String> = self
+            .devices
+            .iter()
+            .map(|(id, d)| (*id, d.name.clone()))
+            .collect();
+        for bucket in self.buckets() {
+            if !bucket.name.is_empty() {
+                names.insert(bucket.id, bucket.name.clone());
+            }
+        }
+        names.encode(&mut buf);
+        let rule_names: BTreeMap<i32, String> = self
+            .rules
+            .iter()
+            .map(|r| (r.id as i32, r.name.clone()))
+            .collect();
+        rule_names.encode(&mut buf);
+
+        let t = &self.tunables;
+        t.choose_local_tries.encode(&mut buf);
+        t.choose_local_fallback_tries.encode(&mut buf);
+        t.choose_total_tries.encode(&mut buf);
+        t.chooseleaf_descend_once.encode(&mut buf);
+        t.chooseleaf_vary_r.encode(&mut buf);
+        t.straw_calc_version.encode(&mut buf);
+        t.allowed_bucket_algs.encode(&mut buf);
+        t.chooseleaf_stable.encode(&mut buf);
+
+        // Device classes: the class of each device and shadow bucket, the
+        // class names and the shadow bucket table
+        let mut class_map: BTreeMap<i32, i32> = BTreeMap::new();
+        for (id, device) in &self.devices {
+            if let Some(class) = device.class.as_deref().and_then(|c| self.class_id(c)) {
+                class_map.insert(*id, class);
+            }
+        }
+        for shadows in self.class_buckets.values() {
+            for (class, shadow) in shadows {
+                class_map.insert(*shadow, *class);
+            }
+        }
+        class_map.encode(&mut buf);
+        self.classes.encode(&mut buf);
+        self.class_buckets.encode(&mut buf);
+
+        (self.choose_args.len() as u32).encode(&mut buf);
+        for (key, args) in &self.choose_args {
+            key.encode(&mut buf);
+            let args: Vec<(&i32, &ChooseArg)> = args
+                .iter()
+                .rev()
+                .filter(|(_, a)| {
+                    !a.weight_set.is_empty() || a.ids.as_ref().is_some_and(|i| !i.is_empty())
+                })
+                .collect();
+            (args.len() as u32).encode(&mut buf);
+            for (bucket_id, arg) in args {
+                (-1 - bucket_id).encode(&mut buf);
+                (arg.weight_set.len() as u32).encode(&mut buf);
+                for weights in &arg.weight_set {
+                    (weights.len() as u32).encode(&mut buf);
+                    for w in weights {
+                        w.encode(&mut buf);
+                    }
+                }
+                let ids: &[i32] = arg.ids.as_deref().unwrap_or(&[]);
+                (ids.len() as u32).encode(&mut buf);
+                for id in ids {
+                    id.encode(&mut buf);
+                }
+            }
+        }
+
+        if let (Some(descents), Some(collision_tries)) = (t.msr_descents, t.msr_collision_tries) {
+            descents.encode(&mut buf);
+            collision_tries.encode(&mut buf);
+        }
+        buf
+    }
+}
+
+fn encode_bucket(bucket: &Bucket, buf: &mut Vec<u8>) {
+    (bucket.alg.as_raw() as u32).encode(buf);
+    bucket.id.encode(buf);
+    (bucket.type_id as u16).encode(buf);
+    bucket.alg.as_raw().encode(buf);
+    bucket.hash.encode(buf);
+    bucket.weight().encode(buf);
+    (bucket.items.len() as u32).encode(buf);
+    for item in &bucket.items {
+        item.encode(buf);
+    }
+    match bucket.alg {
+        BucketAlg::Uniform => bucket.weights.first().copied().unwrap_or(0).encode(buf),
+        BucketAlg::List | BucketAlg::Straw => {
+            for (weight, derived) in bucket.weights.iter().zip(bucket.derived.iter()) {
+                weight.encode(buf);
+                derived.encode(buf);
+            }
+        }
+        BucketAlg::Tree => {
+            (bucket.derived.len() as u8).encode(buf);
+            for weight in &bucket.derived {
+                weight.encode(buf);
+            }
+        }
+        BucketAlg::Straw2 => {
+            for weight in &bucket.weights {
+                weight.encode(buf);
+            }
+        }
+    }
+}
+
+fn verify_failure(input: &[u8]) -> nom::Err<Error<&[u8]>> {
+    nom::Err::Failure(Error::new(input, ErrorKind::Verify))
+}
+
+/// n values with no length prefix
+fn decode_n<T: Decode>(mut input: &[u8], n: usize) -> IResult<&[u8], Vec<T>> {
+    let mut values = Vec::with_capacity(n.min(input.len()));
+    for _ in 0..n {
+        let (rest, value) = T::decode(input)?;
+        values.push(value);
+        input = rest;
+    }
+    Ok((input, values))
+}
+
+fn decode_bucket(input: &[u8]) -> IResult<&[u8], Option<Bucket>> {
+    let (input, alg) = u32::decode(input)?;
+    if alg == 0 {
+        return Ok((input, None));
+    }
+    let (input, id) = i32::decode(input)?;
+    let (input, type_id) = u16::decode(input)?;
+    let (input, raw_alg) = u8::decode(input)?;
+    let alg = BucketAlg::from_raw(raw_alg).ok_or_else(|| verify_failure(input))?;
+    let (input, hash) = u8::decode(input)?;
+    let (input, _weight) = u32::decode(input)?;
+    let (input, size) = u32::decode(input)?;
+    let size = size as usize;
+    let (input, items) = decode_n::<i32>(input, size)?;
+
+    let (input, weights, derived) = match alg {
+        BucketAlg::Uniform => {
+            let (input, weight) = u32::decode(input)?;
+            (input, vec![weight; size], Vec::new())
+        }
+        BucketAlg::List | BucketAlg::Straw => {
+            let (input, pairs) = decode_n::<(u32, u32)>(input, size)?;
+            let (weights, derived) = pairs.into_iter().unzip();
+            (input, weights, derived)
+        }
+        BucketAlg::Tree => {
+            let (input, num_nodes) = u8::decode(input)?;
+            let (input, nodes) = decode_n::<u32>(input, num_nodes as usize)?;
+            // Item i sits at node 2i+1
+            let weights = (0..size)
+                .map(|i| nodes.get(2 * i + 1).copied().unwrap_or(0))
+                .collect();
+            (input, weights, nodes)
+        }
+        BucketAlg::Straw2 => {
+            let (input, weights) = decode_n::<u32>(input, size)?;
+            (input, weights, Vec::new())
+        }
+    };
+    Ok((
+        input,
+        Some(Bucket {
+            id,
+            name: String::new(),
+            type_id: type_id as i32,
+            alg,
+            hash,
+            items,
+            weights,
+            derived,
+        }),
+    ))
+}
+
+/// ruleset, type, min_size, max_size and steps
+type RawRule = (u8, u8, u8, u8, Vec<RuleStep>);
+
+fn decode_rule(input: &[u8]) -> IResult<&[u8], Option<RawRule>> {
+    let (input, exists) = u32::decode(input)?;
+    if exists == 0 {
+        return Ok((input, None));
+    }
+    let (input, len) = u32::decode(input)?;
+    let (input, ruleset) = u8::decode(input)?;
+    let (input, rule_type) = u8::decode(input)?;
+    let (input, min_size) = u8::decode(input)?;
+    let (mut input, max_size) = u8::decode(input)?;
+    let mut steps = Vec::with_capacity((len as usize).min(input.len() / 12));
+    for _ in 0..len {
+        let (rest, op) = u32::decode(input)?;
+        let (rest, arg1) = i32::decode(rest)?;
+        let (rest, arg2) = i32::decode(rest)?;
+        steps.push(RuleStep::from_raw(op, arg1, arg2));
+        input = rest;
+    }
+    Ok((input, Some((ruleset, rule_type, min_size, max_size, steps))))
+}
+
+type DecodedChooseArgs = BTreeMap<i64, BTreeMap<i32, ChooseArg>>;
+
+fn decode_choose_args(input: &[u8]) -> IResult<&[u8], DecodedChooseArgs> {
+    let (mut input, count) = u32::decode(input)?;
+    let mut choose_args = BTreeMap::new();
+    for _ in 0..count {
+        let (rest, key) = i64::decode(input)?;
+        let (mut rest, size) = u32::decode(rest)?;
+        let mut args = BTreeMap::new();
+        for _ in 0..size {
+            let (r, bucket_index) = u32::decode(rest)?;
+            let (mut r, positions) = u32::decode(r)?;
+            let mut weight_set = Vec::new();
+            for _ in 0..positions {
+                let (r2, len) = u32::decode(r)?;
+                let (r2, weights) = decode_n::<u32>(r2, len as usize)?;
+                weight_set.push(weights);
+                r = r2;
+            }
+            let (r, ids_size) = u32::decode(r)?;
+            let (r, ids) = decode_n::<i32>(r, ids_size as usize)?;
+            let arg = ChooseArg {
+                ids: if ids.is_empty() { None } else { Some(ids) },
+                weight_set,
+            };
+            args.insert(-1 - bucket_index as i32, arg);
+            rest = r;
+        }
+        choose_args.insert(key, args);
+        input = rest;
+    }
+    Ok((input, choose_args))
+}
+
+fn decode_crush(input: &[u8]) -> IResult<&[u8], CrushMap> {
+    let (input, magic) = u32::decode(input)?;
+    if magic != CRUSH_MAGIC {
+        return Err(verify_failure(input));
+    }
+    let (input, max_buckets) = i32::decode(input)?;
+    let (input, max_rules) = u32::decode(input)?;
+    let (mut input, max_devices) = i32::decode(input)?;
+
+    let mut buckets = Vec::new();
+    for _ in 0..max_buckets.max(0) {
+        let (rest, bucket) = decode_bucket(input)?;
+        buckets.push(bucket);
+        input = rest;
+    }
+    let mut raw_rules = Vec::new();
+    for id in 0..max_rules {
+        let (rest, rule) = decode_rule(input)?;
+        if let Some(rule) = rule {
+            raw_rules.push((id, rule));
+        }
+        input = rest;
+    }
+
+    let (input, types) = BTreeMap::<i32, String>::decode(input)?;
+    let (input, mut names) = BTreeMap::<i32, String>::decode(input)?;
+    let (mut input, mut rule_names) = BTreeMap::<i32, String>::decode(input)?;
+
+    // Each group of tunables was added in a later release
+    let mut t = Tunables::legacy();
+    if !input.is_empty() {
+        let (rest, v) = u32::decode(input)?;
+        t.choose_local_tries = v;
+        let (rest, v) = u32::decode(rest)?;
+        t.choose_local_fallback_tries = v;
+        let (rest, v) = u32::decode(rest)?;
+        t.choose_total_tries = v;
+        input = rest;
+    }
+    if !input.is_empty() {
+        let (rest, v) = u32::decode(input)?;
+        t.chooseleaf_descend_once = v;
+        input = rest;
+    }
+    if !input.is_empty() {
+        let (rest, v) = u8::decode(input)?;
+        t.chooseleaf_vary_r = v;
+        input = rest;
+    }
+    if !input.is_empty() {
+        let (rest, v) = u8::decode(input)?;
+        t.straw_calc_version = v;
+        input = rest;
+    }
+    if !input.is_empty() {
+        let (rest, v) = u32::decode(input)?;
+        t.allowed_bucket_algs = v;
+        input = rest;
+    }
+    if !input.is_empty() {
+        let (rest, v) = u8::decode(input)?;
+        t.chooseleaf_stable = v;
+        input = rest;
+    }
+
+    let mut class_map = BTreeMap::new();
+    let mut classes = BTreeMap::new();
+    let mut class_buckets = BTreeMap::new();
+    if !input.is_empty() {
+        let (rest, v) = BTreeMap::<i32, i32>::decode(input)?;
+        class_map = v;
+        let (rest, v) = BTreeMap::<i32, String>::decode(rest)?;
+        classes = v;
+        let (rest, v) = BTreeMap::<i32, BTreeMap<i32, i32>>::decode(rest)?;
+        class_buckets = v;
+        input = rest;
+    }
+    let mut choose_args = BTreeMap::new();
+    if !input.is_empty() {
+        let (rest, v) = decode_choose_args(input)?;
+        choose_args = v;
+        input = rest;
+    }
+    if !input.is_empty() {
+        let (rest, descents) = u32::decode(input)?;
+        let (rest, collision_tries) = u32::decode(rest)?;
+        t.msr_descents = Some(descents);
+        t.msr_collision_tries = Some(collision_tries);
+        input = rest;
+    }
+
+    for bucket in buckets.iter_mut().flatten() {
+        bucket.name = names.remove(&bucket.id).unwrap_or_default();
+    }
+    let devices = names
+        .into_iter()
+        .filter(|(id, _)| *id >= 0)
+        .map(|(id, name)| {
+            let class = class_map.get(&id).and_then(|c| classes.get(c)).cloned();
+            (id, Device { name, class })
+        })
+        .collect();
+    let rules = raw_rules
+        .into_iter()
+        .map(
+            |(id, (ruleset, rule_type, min_size, max_size, steps))| Rule {
+                id,
+                name: rule_names.remove(&(id as i32)).unwrap_or_default(),
+                ruleset,
+                rule_type,
+                min_size,
+                max_size,
+                steps,
+            },
+        )
+        .collect();
+
+    Ok((
+        input,
+        CrushMap {
+            buckets,
+            max_devices,
+            max_rules,
+            rules,
+            types,
+            devices,
+            tunables: t,
+            classes,
+            class_buckets,
+            choose_args,
+        },
+    ))
+}
+
+/// Scratch state for uniform buckets
+struct PermWork {
+    perm_x: u32,
+    perm_n: u32,
+    perm: Vec<u32>,
+}
+
+struct Mapper<'a> {
+    map: &'a CrushMap,
+    weights: &'a [u32],
+    choose_args: Option<&'a BTreeMap<i32, ChooseArg>>,
+    work: Vec<PermWork>,
+}
+
+impl<'a> Mapper<'a> {
+    fn bucket(&self, id: i32) -> Option<&'a Bucket> {
+        self.map.bucket(id)
+    }
+
+    /// Pick item r of a random permutation of the bucket
+    fn perm_choose(&mut self, bucket: &Bucket, x: i32, r: i32) -> i32 {
+        let size = bucket.items.len() as u32;
+        let pr = (r as u32) % size;
+        let x = x as u32;
+        let id = bucket.id as u32;
+        let work = &mut self.work[(-1 - bucket.id) as usize];
+
+        if work.perm_x != x || work.perm_n == 0 {
+            work.perm_x = x;
+            // r = 0 is by far the most common case so don't build the
+            // whole permutation for it
+            if pr == 0 {
+                let s = hash32_3(x, id, 0) % size;
+                work.perm[0] = s;
+                work.perm_n = 0xffff;
+                return bucket.items[s as usize];
+            }
+            for (i, p) in work.perm.iter_mut().enumerate() {
+                *p = i as u32;
+            }
+            work.perm_n = 0;
+        } else if work.perm_n == 0xffff {
+            // Finish off the r = 0 case
+            for i in 1..size as usize {
+                work.perm[i] = i as u32;
+            }
+            let first = work.perm[0] as usize;
+            work.perm[first] = 0;
+            work.perm_n = 1;
+        }
+
+        while work.perm_n <= pr {
+            let p = work.perm_n;
+            if p < size - 1 {
+                let i = hash32_3(x, id, p) % (size - p);
+                if i != 0 {
+                    work.perm.swap(p as usize, (p + i) as usize);
+                }
+            }
+            work.perm_n += 1;
+        }
+        bucket.items[work.perm[pr as usize] as usize]
+    }
+
+    fn list_choose(bucket: &Bucket, x: i32, r: i32) -> i32 {
+        for i in (0..bucket.items.len()).rev() {
+            let mut w = hash32_4(x as u32, bucket.items[i] as u32, r as u32, bucket.id as u32)
+                as u64
+                & 0xffff;
+            w = (w * bucket.derived[i] as u64) >> 16;
+            if w < bucket.weights[i] as u64 {
+                return bucket.items[i];
+            }
+        }
+        bucket.items[0]
+    }
+
+    fn tree_choose(bucket: &Bucket, x: i32, r: i32) -> i32 {
+        let nodes = &bucket.derived;
+        let mut n = nodes.len() >> 1;
+        while n & 1 == 0 {
+            let w = nodes[n] as u64;
+            let t = (hash32_4(x as u32, n as u32, r as u32, bucket.id as u32) as u64 * w) >> 32;
+            let h = tree_height(n);
+            let left = n - (1 << (h - 1));
+            n = if t < nodes[left] as u64 {
+                left
+            } else {
+                n + (1 << (h - 1))
+            };
+        }
+        bucket.items[n >> 1]
+    }
+
+    fn straw_choose(bucket: &Bucket, x: i32, r: i32) -> i32 {
+        let mut high = 0;
+        let mut high_draw = 0u64;
+        for (i, item) in bucket.items.iter().enumerate() {
+            let draw = (hash32_3(x as u32, *item as u32, r as u32) & 0xffff) as u64
+                * bucket.derived[i] as u64;
+            if i == 0 || draw > high_draw {
+                high = i;
+                high_draw = draw;
+            }
+        }
+        bucket.items[high]
+    }
+
+    fn straw2_choose(&self, bucket: &Bucket, x: i32, r: i32, position: usize) -> i32 {
+        let arg = self.choose_args.and_then(|args| args.get(&bucket.id));
+        let weights = match arg.map(|a| &a.weight_set) {
+            Some(set) if !set.is_empty() => &set[position.min(set.len() - 1)],
+            _ => &bucket.weights,
+        };
+        let ids = arg.and_then(|a| a.ids.as_ref()).unwrap_or(&bucket.items);
+
+        let mut high = 0;
+        let mut high_draw = 0i64;
+        for i in 0..bucket.items.len() {
+            let weight = weights.get(i).copied().unwrap_or(0);
+            let draw = if weight != 0 {
+                let id = ids.get(i).copied().unwrap_or(bucket.items[i]);
+                let u = hash32_3(x as u32, id as u32, r as u32) & 0xffff;
+                // ln is negative so the heaviest items draw closest to 0
+                let ln = crush_ln(u) as i64 - 0x1_0000_0000_0000;
+                ln / weight as i64
+            } else {
+                i64::MIN
+            };
+            if i == 0 || draw > high_draw {
+                high = i;
+                high_draw = draw;
+            }
+        }
+        bucket.items[high]
+    }
+
+    fn bucket_choose(&mut self, bucket: &Bucket, x: i32, r: i32, position: usize) -> i32 {
+        match bucket.alg {
+            BucketAlg::Uniform => self.perm_choose(bucket, x, r),
+            BucketAlg::List => Self::list_choose(bucket, x, r),
+            BucketAlg::Tree => Self::tree_choose(bucket, x, r),
+            BucketAlg::Straw => Self::straw_choose(bucket, x, r),
+            BucketAlg::Straw2 => self.straw2_choose(bucket, x, r, position),
+        }
+    }
+
+    /// Whether a device is marked (partially) out for this input
+    fn is_out(&self, item: i32, x: i32) -> bool {
+        let weight = match self.weights.get(item as usize) {
+            Some(weight) => *weight,
+            None => return true,
+        };
+        if weight >= WEIGHT_ONE {
+            return false;
+        }
+        if weight == 0 {
+            return true;
+        }
+        (hash32_2(x as u32, item as u32) & 0xffff) >= weight
+    }
+
+    /// crush_choose_firstn: choose numrep distinct items of type_id, packed
+    /// at the front of out
+    #[allow(clippy::too_many_arguments)]
+    fn choose_firstn(
+        &mut self,
+        bucket: &'a Bucket,
+        x: i32,
+        numrep: i32,
+        type_id: i32,
+        out: &mut [i32],
+        mut outpos: usize,
+        out_size: usize,
+        tries: u32,
+        recurse_tries: u32,
+        local_retries: u32,
+        local_fallback_retries: u32,
+        recurse_to_leaf: bool,
+        vary_r: u32,
+        stable: bool,
+        mut out2: Option<&mut [i32]>,
+        parent_r: i32,
+    ) -> usize {
+        let mut count = out_size;
+        let mut rep = if stable { 0 } else { outpos as i32 };
+        while rep < numrep && count > 0 {
+            let mut ftotal: u32 = 0;
+            let mut skip_rep = false;
+            let mut item = 0;
+            loop {
+                let mut retry_descent = false;
+                let mut in_bucket = bucket;
+                let mut flocal: u32 = 0;
+                loop {
+                    let mut collide = false;
+                    let mut retry_bucket = false;
+                    let r = rep.wrapping_add(parent_r).wrapping_add(ftotal as i32);
+                    let size = in_bucket.items.len() as u32;
+                    let reject = if size == 0 {
+                        true
+                    } else {
+                        item = if local_fallback_retries > 0
+                            && flocal >= (size >> 1)
+                            && flocal > local_fallback_retries
+                        {
+                            self.perm_choose(in_bucket, x, r)
+                        } else {
+                            self.bucket_choose(in_bucket, x, r, outpos)
+                        };
+                        if item >= self.map.max_devices() {
+                            skip_rep = true;
+                            break;
+                        }
+                        let sub_bucket = if item < 0 {
+                            match self.bucket(item) {
+                                Some(b) => Some(b),
+                                None => {
+                                    skip_rep = true;
+                                    break;
+                                }
+                            }
+                        } else {
+                            None
+                        };
+                        let itemtype = sub_bucket.map_or(0, |b| b.type_id);
+
+                        if itemtype != type_id {
+                            match sub_bucket {
+                                Some(b) => {
+                                    in_bucket = b;
+                                    continue;
+                                }
+                                None => {
+                                    skip_rep = true;
+                                    break;
+                                }
+                            }
+                        }
+
+                        collide = out[..outpos].contains(&item);
+                        let mut reject = false;
+                        if !collide && recurse_to_leaf {
+                            let out2 = out2.as_deref_mut().expect("leaf output");
+                            match sub_bucket {
+                                Some(b) => {
+                                    let sub_r = if vary_r > 0 { r >> (vary_r - 1) } else { 0 };
+                                    let found = self.choose_firstn(
+                                        b,
+                                        x,
+                                        if stable { 1 } else { outpos as i32 + 1 },
+                                        0,
+                                        out2,
+                                        outpos,
+                                        count,
+                                        recurse_tries,
+                                        0,
+                                        local_retries,
+                                        local_fallback_retries,
+                                        false,
+                                        vary_r,
+                                        stable,
+                                        None,
+                                        sub_r,
+                                    );
+                                    if found <= outpos {
+                                        reject = true;
+                                    }
+                                }
+                                None => out2[outpos] = item,
+                            }
+                        }
+                        if !reject && !collide && itemtype == 0 {
+                            reject = self.is_out(item, x);
+                        }
+                        reject
+                    };
+
+                    if reject || collide {
+                        ftotal += 1;
+                        flocal += 1;
+                        if collide && flocal <= local_retries {
+                            // Retry locally a few times
+                            retry_bucket = true;
+                        } else if local_fallback_retries > 0
+                            && flocal <= size + local_fallback_retries
+                        {
+                            // Exhaustive bucket search
+                            retry_bucket = true;
+                        } else if ftotal < tries {
+                            // Then retry from the top
+                            retry_descent = true;
+                        } else {
+                            skip_rep = true;
+                        }
+                    }
+                    if !retry_bucket {
+                        break;
+                    }
+                }
+                if !retry_descent {
+                    break;
+                }
+            }
+
+            if !skip_rep {
+                out[outpos] = item;
+                outpos += 1;
+                count -= 1;
+            }
+            rep += 1;
+        }
+        outpos
+    }
+
+    /// crush_choose_indep: fill out[outpos..outpos + left] keeping each
+    /// result in its position, CRUSH_ITEM_NONE where nothing was found
+    #[allow(clippy::too_many_arguments)]
+    fn choose_indep(
+        &mut self,
+        bucket: &'a Bucket,
+        x: i32,
+        left: i32,
+        numrep: i32,
+        type_id: i32,
+        out: &mut [i32],
+        outpos: usize,
+        tries: u32,
+        recurse_tries: u32,
+        recurse_to_leaf: bool,
+        mut out2: Option<&mut [i32]>,
+        parent_r: i32,
+    ) {
+        let mut left = left;
+        let endpos = outpos + left as usize;
+        for rep in outpos..endpos {
+            out[rep] = CRUSH_ITEM_UNDEF;
+            if let Some(out2) = out2.as_deref_mut() {
+                out2[rep] = CRUSH_ITEM_UNDEF;
+            }
+        }
+
+        let mut ftotal: u32 = 0;
+        while left > 0 && ftotal < tries {
+            for rep in outpos..endpos {
+                if out[rep] != CRUSH_ITEM_UNDEF {
+                    continue;
+                }
+                let mut in_bucket = bucket;
+                loop {
+                    let size = in_bucket.items.len();
+                    // Spread retries across positions so a failure in one
+                    // doesn't shift the others
+                    let mut r = rep as i32 + parent_r;
+                    if in_bucket.alg == BucketAlg::Uniform
+                        && numrep > 0
+                        && size.is_multiple_of(numrep as usize)
+                    {
+                        r = r.wrapping_add(((numrep + 1) as u32).wrapping_mul(ftotal) as i32);
+                    } else {
+                        r = r.wrapping_add((numrep as u32).wrapping_mul(ftotal) as i32);
+                    }
+
+                    if size == 0 {
+                        break;
+                    }
+                    let item = self.bucket_choose(in_bucket, x, r, outpos);
+                    if item >= self.map.max_devices() {
+                        out[rep] = CRUSH_ITEM_NONE;
+                        if let Some(out2) = out2.as_deref_mut() {
+                            out2[rep] = CRUSH_ITEM_NONE;
+                        }
+                        left -= 1;
+                        break;
+                    }
+                    let sub_bucket = if item < 0 { self.bucket(item) } else { None };
+                    let itemtype = sub_bucket.map_or(0, |b| b.type_id);
+
+                    if itemtype != type_id {
+                        match sub_bucket {
+                            Some(b) => {
+                                in_bucket = b;
+                                continue;
+                            }
+                            None => {
+                                out[rep] = CRUSH_ITEM_NONE;
+                                if let Some(out2) = out2.as_deref_mut() {
+                                    out2[rep] = CRUSH_ITEM_NONE;
+                                }
+                                left -= 1;
+                                break;
+                            }
+                        }
+                    }
+
+                    if out[outpos..endpos].contains(&item) {
+                        break;
+                    }
+
+                    if recurse_to_leaf {
+                        let out2 = out2.as_deref_mut().expect("leaf output");
+                        match sub_bucket {
+                            Some(b) => {
+                                self.choose_indep(
+                                    b,
+                                    x,
+                                    1,
+                                    numrep,
+                                    0,
+                                    out2,
+                                    rep,
+                                    recurse_tries,
+                                    0,
+                                    false,
+                                    None,
+                                    r,
+                                );
+                                if out2[rep] == CRUSH_ITEM_NONE {
+                                    // Placed nothing, no good
+                                    break;
+                                }
+                            }
+                            None => out2[rep] = item,
+                        }
+                    }
+
+                    if itemtype == 0 && self.is_out(item, x) {
+                        break;
+                    }
+
+                    out[rep] = item;
+                    left -= 1;
+                    break;
+                }
+            }
+            ftotal += 1;
+        }
+
+        for rep in outpos..endpos {
+            if out[rep] == CRUSH_ITEM_UNDEF {
+                out[rep] = CRUSH_ITEM_NONE;
+            }
+            if let Some(out2) = out2.as_deref_mut() {
+                if out2[rep] == CRUSH_ITEM_UNDEF {
+                    out2[rep] = CRUSH_ITEM_NONE;
+                }
+            }
+        }
+    }
+
+    fn do_rule(&mut self, rule: &Rule, x: i32, result_max: usize) -> Vec<i32> {
+        let tunables = &self.map.tunables;
+        let mut result = Vec::with_capacity(result_max);
+        let mut w = vec![0i32; result_max];
+        let mut o = vec![0i32; result_max];
+        let mut c = vec![0i32; result_max];
+        let mut wsize = 0;
+
+        // One more than the number of retries
+        let mut choose_tries = tunables.choose_total_tries + 1;
+        let mut choose_leaf_tries = 0;
+        let mut choose_local_retries = tunables.choose_local_tries;
+        let mut choose_local_fallback_retries = tunables.choose_local_fallback_tries;
+        let mut vary_r = tunables.chooseleaf_vary_r as u32;
+        let mut stable = tunables.chooseleaf_stable != 0;
+
+        for step in &rule.steps {
+            let (firstn, recurse_to_leaf, num, type_id) = match *step {
+                RuleStep::Take { item } => {
+                    let valid =
+                        (item >= 0 && item < self.map.max_devices()) || self.bucket(item).is_some();
+                    if valid && result_max > 0 {
+                        w[0] = item;
+                        wsize = 1;
+                    }
+                    continue;
+                }
+                RuleStep::SetChooseTries(n) => {
+                    if n > 0 {
+                        choose_tries = n as u32;
+                    }
+                    continue;
+                }
+                RuleStep::SetChooseLeafTries(n) => {
+                    if n > 0 {
+                        choose_leaf_tries = n as u32;
+                    }
+                    continue;
+                }
+                RuleStep::SetChooseLocalTries(n) => {
+                    if n >= 0 {
+                        choose_local_retries = n as u32;
+                    }
+                    continue;
+                }
+                RuleStep::SetChooseLocalFallbackTries(n) => {
+                    if n >= 0 {
+                        choose_local_fallback_retries = n as u32;
+                    }
+                    continue;
+                }
+                RuleStep::SetChooseLeafVaryR(n) => {
+                    if n >= 0 {
+                        vary_r = n as u32;
+                    }
+                    continue;
+                }
+                RuleStep::SetChooseLeafStable(n) => {
+                    if n >= 0 {
+                        stable = n != 0;
+                    }
+                    continue;
+                }
+                RuleStep::Emit => {
+                    for item in &w[..wsize] {
+                        if result.len() < result_max {
+                            result.push(*item);
+                        }
+                    }
+                    wsize = 0;
+                    continue;
+                }
+                RuleStep::Noop | RuleStep::Unknown { .. } => continue,
+                RuleStep::ChooseFirstN { num, type_id } => (true, false, num, type_id),
+                RuleStep::ChooseLeafFirstN { num, type_id } => (true, true, num, type_id),
+                RuleStep::ChooseIndep { num, type_id } => (false, false, num, type_id),
+                RuleStep::ChooseLeafIndep { num, type_id } => (false, true, num, type_id),
+            };
+
+            if wsize == 0 {
+                continue;
+            }
+            let mut osize = 0;
+            for item in &w[..wsize] {
+                let mut numrep = num;
+                if numrep <= 0 {
+                    numrep += result_max as i32;
+                    if numrep <= 0 {
+                        continue;
+                    }
+                }
+                let bucket = match self.bucket(*item) {
+                    Some(bucket) => bucket,
+                    None => continue,
+                };
+                if firstn {
+                    let recurse_tries = if choose_leaf_tries > 0 {
+                        choose_leaf_tries
+                    } else if tunables.chooseleaf_descend_once != 0 {
+                        1
+                    } else {
+                        choose_tries
+                    };
+                    osize += self.choose_firstn(
+                        bucket,
+                        x,
+                        numrep,
+                        type_id,
+                        &mut o[osize..],
+                        0,
+                        result_max - osize,
+                        choose_tries,
+                        recurse_tries,
+                        choose_local_retries,
+                        choose_local_fallback_retries,
+                        recurse_to_leaf,
+                        vary_r,
+                        stable,
+                        Some(&mut c[osize..]),
+                        0,
+                    );
+                } else {
+                    let out_size = (numrep as usize).min(result_max - osize);
+                    self.choose_indep(
+                        bucket,
+                        x,
+                        out_size as i32,
+                        numrep,
+                        type_id,
+                        &mut o[osize..],
+                        0,
+                        choose_tries,
+                        if choose_leaf_tries > 0 {
+                            choose_leaf_tries
+                        } else {
+                            1
+                        },
+                        recurse_to_leaf,
+                        Some(&mut c[osize..]),
+                        0,
+                    );
+                    osize += out_size;
+                }
+            }
+
+            if recurse_to_leaf {
+                // Copy the final leaves over the intermediate results
+                o[..osize].copy_from_slice(&c[..osize]);
+            }
+            std::mem::swap(&mut o, &mut w);
+            wsize = osize;
+        }
+        result
+    }
+}
+
+// The subset of `osd crush dump` needed to rebuild the map
+
+#[derive(Deserialize)]
+struct CrushDump {
+    devices: Vec<DumpDevice>,
+    types: Vec<DumpType>,
+    buckets: Vec<DumpBucket>,
+    rules: Vec<DumpRule>,
+    tunables: DumpTunables,
+    #[serde(default)]
+    choose_args: BTreeMap<String, Vec<DumpChooseArg>>,
+}
+
+#[derive(Deserialize)]
+struct DumpDevice {
+    id: i32,
+    name: String,
+    class: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DumpType {
+    type_id: i32,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct DumpBucket {
+    id: i32,
+    name: String,
+    type_id: i32,
+    alg: String,
+    hash: String,
+    items: Vec<DumpItem>,
+}
+
+#[derive(Deserialize)]
+struct DumpItem {
+    id: i32,
+    weight: u32,
+}
+
+#[derive(Deserialize)]
+struct DumpRule {
+    rule_id: u32,
+    rule_name: String,
+    #[serde(default)]
+    ruleset: Option<u8>,
+    #[serde(rename = "type")]
+    rule_type: u8,
+    #[serde(default)]
+    min_size: Option<u8>,
+    #[serde(default)]
+    max_size: Option<u8>,
+    steps: Vec<DumpStep>,
+}
+
+#[derive(Deserialize)]
+struct DumpStep {
+    op: String,
+    item: Option<i32>,
+    num: Option<i32>,
+    #[serde(rename = "type")]
+    type_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DumpTunables {
+    choose_local_tries: u32,
+    choose_local_fallback_tries: u32,
+    choose_total_tries: u32,
+    chooseleaf_descend_once: u32,
+    chooseleaf_vary_r: u8,
+    chooseleaf_stable: u8,
+    straw_calc_version: u8,
+    allowed_bucket_algs: u32,
+    #[serde(default)]
+    msr_descents: Option<u32>,
+    #[serde(default)]
+    msr_collision_tries: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct DumpChooseArg {
+    bucket_id: i32,
+    ids: Option<Vec<i32>>,
+    weight_set: Option<Vec<Vec<f64>>>,
+}
+
+impl CrushDump {
+    fn into_map(self) -> RadosResult<CrushMap> {
+        let mut map = CrushMap {
+            tunables: Tunables {
+                choose_local_tries: self.tunables.choose_local_tries,
+                choose_local_fallback_tries: self.tunables.choose_local_fallback_tries,
+                choose_total_tries: self.tunables.choose_total_tries,
+                chooseleaf_descend_once: self.tunables.chooseleaf_descend_once,
+                chooseleaf_vary_r: self.tunables.chooseleaf_vary_r,
+                chooseleaf_stable: self.tunables.chooseleaf_stable,
+                straw_calc_version: self.tunables.straw_calc_version,
+                allowed_bucket_algs: self.tunables.allowed_bucket_algs,
+                msr_descents: self.tunables.msr_descents,
+                msr_collision_tries: self.tunables.msr_collision_tries,
+            },
+            ..Default::default()
+        };
+        for t in self.types {
+            map.types.insert(t.type_id, t.name);
+        }
+        for d in self.devices {
+            // The dump leaves out class ids so number them as they're seen
+            if let Some(class) = &d.class {
+                if map.class_id(class).is_none() {
+                    let id = map.classes.len() as i32;
+                    map.classes.insert(id, class.clone());
+                }
+            }
+            map.devices.insert(
+                d.id,
+                Device {
+                    name: d.name,
+                    class: d.class,
+                },
+            );
+        }
+        for b in self.buckets {
+            if b.id >= 0 {
+                return Err(RadosError::new(format!(
+                    "Bucket {} has a non-negative id {}",
+                    b.name, b.id
+                )));
+            }
+            if b.hash != "rjenkins1" && b.hash != "0" {
+                return Err(RadosError::new(format!(
+                    "Bucket {} uses unsupported hash {}",
+                    b.name, b.hash
+                )));
+            }
+            map.insert_bucket(Bucket {
+                id: b.id,
+                name: b.name,
+                type_id: b.type_id,
+                alg: b.alg.parse()?,
+                hash: 0,
+                items: b.items.iter().map(|i| i.id).collect(),
+                weights: b.items.iter().map(|i| i.weight).collect(),
+                derived: Vec::new(),
+            });
+        }
+        let mut class_buckets = BTreeMap::new();
+        for shadow in map.buckets() {
+            let mut parts = shadow.name.splitn(2, '~');
+            let (name, class) = (parts.next().unwrap_or(""), parts.next());
+            let original = map.bucket_by_name(name);
+            let class_id = class.and_then(|c| map.class_id(c));
+            if let (Some(original), Some(class_id)) = (original, class_id) {
+                class_buckets
+                    .entry(original.id)
+                    .or_insert_with(BTreeMap::new)
+                    .insert(class_id, shadow.id);
+            }
+        }
+        map.class_buckets = class_buckets;
+        for r in self.rules {
+            let mut steps = Vec::with_capacity(r.steps.len());
+            for s in r.steps {
+                steps.push(s.into_step(&map)?);
+            }
+            map.rules.push(Rule {
+                id: r.rule_id,
+                name: r.rule_name,
+                ruleset: r.ruleset.unwrap_or(r.rule_id as u8),
+                rule_type: r.rule_type,
+                min_size: r.min_size.unwrap_or(1),
+                max_size: r.max_size.unwrap_or(10),
+                steps,
+            });
+        }
+        map.max_rules = map.rules.iter().map(|r| r.id + 1).max().unwrap_or(0);
+        map.max_devices = map.max_devices();
+        for (key, args) in self.choose_args {
+            let key: i64 = key.parse()?;
+            let args = args
+                .into_iter()
+                .map(|a| {
+                    // weight_set is dumped as floats of the 16.16 weights
+                    let weight_set = a
+                        .weight_set
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|ws| {
+                            ws.into_iter()
+                                .map(|w| (w * WEIGHT_ONE as f64).round() as u32)
+                                .collect()
+                        })
+                        .collect();
+                    (
+                        a.bucket_id,
+                        ChooseArg {
+                            ids: a.ids,
+                            weight_set,
+                        },
+                    )
+                })
+                .collect();
+            map.choose_args.insert(key, args);
+        }
+        Ok(map)
+    }
+}
+
+impl DumpStep {
+    fn into_step(self, map: &CrushMap) -> RadosResult<RuleStep> {
+        let num = self.num.unwrap_or(0);
+        let type_id = || -> RadosResult<i32> {
+            let name = self.type_name.as_deref().unwrap_or("");
+            map.type_id(name)
+                .ok_or_else(|| RadosError::new(format!("Unknown CRUSH type {}", name)))
+        };
+        Ok(match self.op.as_str() {
+            "noop" => RuleStep::Noop,
+            "take" => RuleStep::Take {
+                item: self.item.unwrap_or(0),
+            },
+            "emit" => RuleStep::Emit,
+            "choose_firstn" => RuleStep::ChooseFirstN {
+                num,
+                type_id: type_id()?,
+            },
+            "choose_indep" => RuleStep::ChooseIndep {
+                num,
+                type_id: type_id()?,
+            },
+            "chooseleaf_firstn" => RuleStep::ChooseLeafFirstN {
+                num,
+                type_id: type_id()?,
+            },
+            "chooseleaf_indep" => RuleStep::ChooseLeafIndep {
+                num,
+                type_id: type_id()?,
+            },
+            "set_choose_tries" => RuleStep::SetChooseTries(num),
+            "set_chooseleaf_tries" => RuleStep::SetChooseLeafTries(num),
+            "set_choose_local_tries" => RuleStep::SetChooseLocalTries(num),
+            "set_choose_local_fallback_tries" => RuleStep::SetChooseLocalFallbackTries(num),
+            "set_chooseleaf_vary_r" => RuleStep::SetChooseLeafVaryR(num),
+            "set_chooseleaf_stable" => RuleStep::SetChooseLeafStable(num),
+            op => return Err(RadosError::new(format!("Unknown CRUSH rule op {}", op))),
+        })
+    }
+}
@@ -0,0 +1,828 @@
+//! Ceph's little-endian wire encoding, as used in bufferlists, object class
+//! payloads and binary maps.
+//!
+//! Integers are little-endian and strings and containers carry a u32 length
+//! prefix.  Most structs are wrapped in an ENCODE_START header of
+//! (struct_v: u8, struct_compat: u8, struct_len: u32) so a decoder can skip
+//! fields added by newer versions.  `denc_struct!` generates `Encode` and
+//! `Decode` for such a struct:
+//!
+//! ```rust
+//! # #[macro_use] extern crate ceph;
+//! # use ceph::denc::{from_bytes, to_bytes};
+//! denc_struct! {
+//!     #[derive(Debug, PartialEq)]
+//!     pub struct Header(2, 1) {
+//!         pub name: String,
+//!         pub size: u64,
+//!         // Only present from struct_v 2, defaulted when decoding v1
+//!         #[since(2)]
+//!         pub flags: u32,
+//!     }
+//! }
+//! # fn main() {
+//! let header = Header { name: "disk".into(), size: 4096, flags: 1 };
+//! let bytes = to_bytes(&header);
+//! assert_eq!(from_bytes::<Header>(&bytes).unwrap(), header);
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nom::bytes::complete::take;
+use nom::error::{Error, ErrorKind};
+use nom::number::complete::{
+    be_u16, be_u32, le_f32, le_f64, le_i16, le_i32, le_i64, le_i8, le_u16, le_u32, le_u64, le_u8,
+};
+pub use nom::IResult;
+use uuid::Uuid;
+
+use crate::error::{RadosError, RadosResult};
+
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
+// sizeof(struct sockaddr_in) and sizeof(struct sockaddr_in6)
+const SOCKADDR_IN_LEN: u32 = 16;
+const SOCKADDR_IN6_LEN: u32 = 28;
+// sizeof(struct ceph_sockaddr_storage) in the legacy address encoding
+const SOCKADDR_STORAGE_LEN: usize = 128;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::denc_struct;
+
+    denc_struct! {
+        #[derive(Debug, PartialEq)]
+        struct Old(1, 1) {
+            name: String,
+            size: u64,
+        }
+    }
+
+    denc_struct! {
+        #[derive(Debug, PartialEq)]
+        struct New(2, 1) {
+            name: String,
+            size: u64,
+            #[since(2)]
+            tags: Vec<String>,
+        }
+    }
+
+    #[test]
+    fn it_encodes_primitives() {
+        assert_eq!(to_bytes(&0x0102_0304u32), vec![4, 3, 2, 1]);
+        assert_eq!(to_bytes("ab"), vec![2, 0, 0, 0, b'a', b'b']);
+        assert_eq!(to_bytes(&vec![1u16, 2]), vec![2, 0, 0, 0, 1, 0, 2, 0]);
+        assert_eq!(to_bytes(&Some(true)), vec![1, 1]);
+        assert_eq!(to_bytes(&None::<u8>), vec![0]);
+    }
+
+    #[test]
+    fn it_round_trips_containers() {
+        let mut map = BTreeMap::new();
+        map.insert("a".to_string(), vec![1u64, 2, 3]);
+        map.insert("b".to_string(), vec![]);
+        let bytes = to_bytes(&map);
+        assert_eq!(
+            from_bytes::<BTreeMap<String, Vec<u64>>>(&bytes).unwrap(),
+            map
+        );
+
+        let pairs = vec![(1i32, "x".to_string()), (-1, "y".to_string())];
+        let bytes = to_bytes(&pairs);
+        assert_eq!(from_bytes::<Vec<(i32, String)>>(&bytes).unwrap(), pairs);
+    }
+
+    #[test]
+    fn it_rejects_truncated_input() {
+        let bytes = to_bytes("hello");
+        match from_bytes::<String>(&bytes[..6]) {
+            Err(RadosError::Parse(_)) => {}
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_skips_fields_from_newer_versions() {
+        let new = New {
+            name: "obj".into(),
+            size: 7,
+            tags: vec!["t".into()],
+        };
+        let mut bytes = to_bytes(&new);
+        bytes.push(0xff);
+        let (rest, old) = Old::decode(&bytes).unwrap();
+        assert_eq!(
+            old,
+            Old {
+                name: "obj".into(),
+                size: 7
+            }
+        );
+        assert_eq!(rest, &[0xff]);
+    }
+
+    #[test]
+    fn it_defaults_fields_missing_from_older_versions() {
+        let old = Old {
+            name: "obj".into(),
+            size: 7,
+        };
+        let new: New = from_bytes(&to_bytes(&old)).unwrap();
+        assert!(new.tags.is_empty());
+    }
+
+    #[test]
+    fn it_refuses_incompatible_versions() {
+        let mut bytes = to_bytes(&Old {
+            name: String::new(),
+            size: 0,
+        });
+        // struct_compat 2 means a v1 decoder can't read it
+        bytes[1] = 2;
+        assert!(from_bytes::<Old>(&bytes).is_err());
+    }
+
+    #[test]
+    fn it_round_trips_entity_addrs() {
+        let addr = EntityAddr {
+            addr_type: EntityAddrType::Msgr2,
+            nonce: 0,
+            addr: Some("10.0.0.1:3300".parse().unwrap()),
+        };
+        let bytes = to_bytes(&addr);
+        assert_eq!(
+            bytes,
+            vec![
+                1, 1, 1, 28, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 2, 0, 0x0c, 0xe4, 10, 0,
+                0, 1, 0, 0, 0, 0, 0, 0, 0, 0
+            ]
+        );
+        assert_eq!(from_bytes::<EntityAddr>(&bytes).unwrap(), addr);
+        assert_eq!(addr.to_string(), "v2:10.0.0.1:3300/0");
+
+        let v6 = EntityAddr {
+            addr_type: EntityAddrType::Legacy,
+            nonce: 42,
+            addr: Some("[fe80::1]:6789".parse().unwrap()),
+        };
+        assert_eq!(from_bytes::<EntityAddr>(&to_bytes(&v6)).unwrap(), v6);
+    }
+
+    #[test]
+    fn it_decodes_legacy_entity_addrs() {
+        let mut bytes = vec![0, 0, 0, 0, 7, 0, 0, 0];
+        let mut storage = vec![0u8; SOCKADDR_STORAGE_LEN];
+        storage[..8].copy_from_slice(&[0, 2, 0x1a, 0x85, 192, 168, 1, 10]);
+        bytes.extend(storage);
+        let addr: EntityAddr = from_bytes(&bytes).unwrap();
+        assert_eq!(addr.addr_type, EntityAddrType::Legacy);
+        assert_eq!(addr.nonce, 7);
+        assert_eq!(addr.addr, Some("192.168.1.10:6789".parse().unwrap()));
+    }
+
+    #[test]
+    fn it_decodes_addrvecs_in_every_form() {
+        let v1 = EntityAddr {
+            addr_type: EntityAddrType::Legacy,
+            nonce: 0,
+            addr: Some("10.0.0.1:6789".parse().unwrap()),
+        };
+        let v2 = EntityAddr {
+            addr_type: EntityAddrType::Msgr2,
+            ..v1.clone()
+        };
+        let addrs = EntityAddrVec(vec![v2.clone(), v1.clone()]);
+        let bytes = to_bytes(&addrs);
+        assert_eq!(bytes[0], 2);
+        assert_eq!(from_bytes::<EntityAddrVec>(&bytes).unwrap(), addrs);
+        assert_eq!(
+            from_bytes::<EntityAddrVec>(&to_bytes(&v1)).unwrap(),
+            EntityAddrVec(vec![v1])
+        );
+    }
+
+    #[test]
+    fn it_converts_utime() {
+        let time = UTime {
+            sec: 1_600_000_000,
+            nsec: 500,
+        };
+        let bytes = to_bytes(&time);
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(from_bytes::<UTime>(&bytes).unwrap(), time);
+        assert_eq!(UTime::from(SystemTime::from(time)), time);
+    }
+}
+
+/// A type with a Ceph wire encoding
+pub trait Encode {
+    /// Append the encoding of self to buf
+    fn encode(&self, buf: &mut Vec<u8>);
+}
+
+/// A type that can be parsed from its Ceph wire encoding
+pub trait Decode: Sized {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self>;
+}
+
+/// Encode a value into a new buffer
+pub fn to_bytes<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    let mut buf = Vec::new();
+    value.encode(&mut buf);
+    buf
+}
+
+/// Decode a value from the start of bytes.  Trailing bytes are ignored.
+pub fn from_bytes<T: Decode>(bytes: &[u8]) -> RadosResult<T> {
+    match T::decode(bytes) {
+        Ok((_, value)) => Ok(value),
+        Err(e) => Err(parse_error(bytes, e)),
+    }
+}
+
+/// Turn a nom error into a RadosError naming the offset it happened at
+pub(crate) fn parse_error(bytes: &[u8], err: nom::Err<Error<&[u8]>>) -> RadosError {
+    match err {
+        nom::Err::Incomplete(needed) => {
+            RadosError::Parse(format!("Input ended early.  Needed: {:?}", needed))
+        }
+        nom::Err::Error(e) | nom::Err::Failure(e) => {
+            // Every slice nom hands back points into bytes
+            let offset = (e.input.as_ptr() as usize).saturating_sub(bytes.as_ptr() as usize);
+            if e.input.is_empty() {
+                RadosError::Parse(format!("Input ended early at offset {}", offset))
+            } else {
+                RadosError::Parse(format!("{:?} failed at offset {}", e.code, offset))
+            }
+        }
+    }
+}
+
+fn failure<T>(input: &[u8], kind: ErrorKind) -> IResult<&[u8], T> {
+    Err(nom::Err::Failure(Error::new(input, kind)))
+}
+
+/// Write an ENCODE_START header for struct version `version`, readable by
+/// decoders of at least `compat`, then the body and finally the length.
+pub fn encode_versioned<F: FnOnce(&mut Vec<u8>)>(
+    buf: &mut Vec<u8>,
+    version: u8,
+    compat: u8,
+    body: F,
+) {
+    buf.push(version);
+    buf.push(compat);
+    let len_at = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    body(buf);
+    let len = (buf.len() - len_at - 4) as u32;
+    buf[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+}
+
+/// Parse an ENCODE_START header written by `encode_versioned`.  `body` is
+/// given struct_v and exactly the struct's bytes.  Anything it leaves unparsed
+/// was added by a newer version and is skipped.  Fails if the encoder says
+/// decoders older than `version` can't read it.
+pub fn decode_versioned<'a, O, F>(input: &'a [u8], version: u8, body: F) -> IResult<&'a [u8], O>
+where
+    F: FnOnce(u8, &'a [u8]) -> IResult<&'a [u8], O>,
+{
+    let (input, struct_v) = le_u8(input)?;
+    let (input, struct_compat) = le_u8(input)?;
+    if struct_compat > version {
+        return failure(input, ErrorKind::Verify);
+    }
+    let (input, struct_len) = le_u32(input)?;
+    let (rest, data) = take(struct_len)(input)?;
+    let (_, value) = body(struct_v, data)?;
+    Ok((rest, value))
+}
+
+/// Declare a struct encoded with an ENCODE_START(version, compat) header and
+/// its fields in order.  Fields marked `#[since(n)]` were added in struct
+/// version n and are set to their Default when decoding anything older.
+#[macro_export]
+macro_rules! denc_struct {
+    (@field $input:ident, $v:ident, $ty:ty) => {
+        <$ty as $crate::denc::Decode>::decode($input)
+    };
+    (@field $input:ident, $v:ident, $ty:ty, $since:literal) => {
+        if $v >= $since {
+            <$ty as $crate::denc::Decode>::decode($input)
+        } else {
+            Ok(($input, <$ty as ::std::default::Default>::default()))
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident($version:literal, $compat:literal) {
+            $( $(#[since($since:literal)])? $fvis:vis $field:ident : $ty:ty ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $( $fvis $field: $ty ),*
+        }
+
+        impl $crate::denc::Encode for $name {
+            fn encode(&self, buf: &mut Vec<u8>) {
+                $crate::denc::encode_versioned(buf, $version, $compat, |_buf| {
+                    $( $crate::denc::Encode::encode(&self.$field, _buf); )*
+                });
+            }
+        }
+
+        impl $crate::denc::Decode for $name {
+            fn decode(input: &[u8]) -> $crate::denc::IResult<&[u8], Self> {
+                $crate::denc::decode_versioned(input, $version, |_struct_v, _body| {
+                    $(
+                        let (_body, $field) =
+                            $crate::denc_struct!(@field _body, _struct_v, $ty $(, $since)?)?;
+                    )*
+                    Ok((_body, $name { $( $field ),* }))
+                })
+            }
+        }
+    };
+}
+
+macro_rules! denc_number {
+    ($($ty:ty => $parser:ident),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+                    $parser(input)
+                }
+            }
+        )*
+    };
+}
+
+denc_number!(
+    u8 => le_u8, u16 => le_u16, u32 => le_u32, u64 => le_u64,
+    i8 => le_i8, i16 => le_i16, i32 => le_i32, i64 => le_i64,
+    f32 => le_f32, f64 => le_f64
+);
+
+impl Encode for bool {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, value) = le_u8(input)?;
+        Ok((input, value != 0))
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u32).encode(buf);
+        buf.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.as_str().encode(buf);
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (rest, len) = le_u32(input)?;
+        let (rest, bytes) = take(len)(rest)?;
+        match String::from_utf8(bytes.to_vec()) {
+            Ok(s) => Ok((rest, s)),
+            Err(_) => failure(input, ErrorKind::Char),
+        }
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u32).encode(buf);
+        for item in self {
+            item.encode(buf);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.as_slice().encode(buf);
+    }
+}
+
+/// Parse a u32 count followed by that many items
+fn decode_items<T: Decode>(input: &[u8]) -> IResult<&[u8], Vec<T>> {
+    let (mut input, count) = le_u32(input)?;
+    // Don't trust the count for the allocation, every item is at least a byte
+    let mut items = Vec::with_capacity((count as usize).min(input.len()));
+    for _ in 0..count {
+        let (rest, item) = T::decode(input)?;
+        items.push(item);
+        input = rest;
+    }
+    Ok((input, items))
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        decode_items(input)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                true.encode(buf);
+                value.encode(buf);
+            }
+            None => false.encode(buf),
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, present) = bool::decode(input)?;
+        if present {
+            let (input, value) = T::decode(input)?;
+            Ok((input, Some(value)))
+        } else {
+            Ok((input, None))
+        }
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.encode(buf);
+        self.1.encode(buf);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, a) = A::decode(input)?;
+        let (input, b) = B::decode(input)?;
+        Ok((input, (a, b)))
+    }
+}
+
+impl<K: Encode, V: Encode> Encode for BTreeMap<K, V> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u32).encode(buf);
+        for (key, value) in self {
+            key.encode(buf);
+            value.encode(buf);
+        }
+    }
+}
+
+impl<K: Decode + Ord, V: Decode> Decode for BTreeMap<K, V> {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, pairs) = decode_items::<(K, V)>(input)?;
+        Ok((input, pairs.into_iter().collect()))
+    }
+}
+
+impl<K: Encode, V: Encode, S> Encode for HashMap<K, V, S> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u32).encode(buf);
+        for (key, value) in self {
+            key.encode(buf);
+            value.encode(buf);
+        }
+    }
+}
+
+impl<K: Decode + Eq + Hash, V: Decode> Decode for HashMap<K, V> {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, pairs) = decode_items::<(K, V)>(input)?;
+        Ok((input, pairs.into_iter().collect()))
+    }
+}
+
+impl<T: Encode> Encode for BTreeSet<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u32).encode(buf);
+        for item in self {
+            item.encode(buf);
+        }
+    }
+}
+
+impl<T: Decode + Ord> Decode for BTreeSet<T> {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, items) = decode_items::<T>(input)?;
+        Ok((input, items.into_iter().collect()))
+    }
+}
+
+impl<T: Encode, S> Encode for HashSet<T, S> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u32).encode(buf);
+        for item in self {
+            item.encode(buf);
+        }
+    }
+}
+
+impl<T: Decode + Eq + Hash> Decode for HashSet<T> {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, items) = decode_items::<T>(input)?;
+        Ok((input, items.into_iter().collect()))
+    }
+}
+
+/// uuid_d is encoded as its 16 raw bytes
+impl Encode for Uuid {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for Uuid {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (rest, bytes) = take(16usize)(input)?;
+        match Uuid::from_slice(bytes) {
+            Ok(uuid) => Ok((rest, uuid)),
+            Err(_) => failure(input, ErrorKind::Verify),
+        }
+    }
+}
+
+/// Ceph's utime_t: seconds and nanoseconds since the epoch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UTime {
+    pub sec: u32,
+    pub nsec: u32,
+}
+
+impl From<UTime> for SystemTime {
+    fn from(time: UTime) -> SystemTime {
+        UNIX_EPOCH + Duration::new(time.sec as u64, time.nsec)
+    }
+}
+
+impl From<SystemTime> for UTime {
+    /// Times before the epoch become the epoch
+    fn from(time: SystemTime) -> UTime {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        UTime {
+            sec: since_epoch.as_secs() as u32,
+            nsec: since_epoch.subsec_nanos(),
+        }
+    }
+}
+
+impl Encode for UTime {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.sec.encode(buf);
+        self.nsec.encode(buf);
+    }
+}
+
+impl Decode for UTime {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (input, sec) = le_u32(input)?;
+        let (input, nsec) = le_u32(input)?;
+        Ok((input, UTime { sec, nsec }))
+    }
+}
+
+/// The protocol an entity_addr_t is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntityAddrType {
+    None,
+    /// msgr v1
+    Legacy,
+    /// msgr v2
+    Msgr2,
+    Any,
+    Cidr,
+    Unknown(u32),
+}
+
+impl EntityAddrType {
+    fn from_raw(raw: u32) -> EntityAddrType {
+        match raw {
+            0 => EntityAddrType::None,
+            1 => EntityAddrType::Legacy,
+            2 => EntityAddrType::Msgr2,
+            3 => EntityAddrType::Any,
+            4 => EntityAddrType::Cidr,
+            other => EntityAddrType::Unknown(other),
+        }
+    }
+
+    pub(crate) fn as_raw(self) -> u32 {
+        match self {
+            EntityAddrType::None => 0,
+            EntityAddrType::Legacy => 1,
+            EntityAddrType::Msgr2 => 2,
+            EntityAddrType::Any => 3,
+            EntityAddrType::Cidr => 4,
+            EntityAddrType::Unknown(other) => other,
+        }
+    }
+}
+
+/// Ceph's entity_addr_t.  addr is None for a blank (AF_UNSPEC) address.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EntityAddr {
+    pub addr_type: EntityAddrType,
+    pub nonce: u32,
+    pub addr: Option<SocketAddr>,
+}
+
+impl fmt::Display for EntityAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = match self.addr_type {
+            EntityAddrType::None => "none:",
+            EntityAddrType::Legacy => "v1:",
+            EntityAddrType::Msgr2 => "v2:",
+            EntityAddrType::Any => "any:",
+            EntityAddrType::Cidr => "cidr:",
+            EntityAddrType::Unknown(_) => "",
+        };
+        match self.addr {
+            Some(addr) => write!(f, "{}{}/{}", prefix, addr, self.nonce),
+            None => write!(f, "{}-", prefix),
+        }
+    }
+}
+
+/// Parse the sockaddr bytes that follow the address family
+fn decode_sockaddr(input: &[u8], family: u16) -> IResult<&[u8], Option<SocketAddr>> {
+    match family {
+        AF_INET => {
+            let (input, port) = be_u16(input)?;
+            let (input, ip) = take(4usize)(input)?;
+            let ip = Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]);
+            Ok((input, Some(SocketAddr::V4(SocketAddrV4::new(ip, port)))))
+        }
+        AF_INET6 => {
+            let (input, port) = be_u16(input)?;
+            let (input, flowinfo) = be_u32(input)?;
+            let (input, ip) = take(16usize)(input)?;
+            let (input, scope_id) = le_u32(input)?;
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(ip);
+            let ip = Ipv6Addr::from(octets);
+            Ok((
+                input,
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    ip, port, flowinfo, scope_id,
+                ))),
+            ))
+        }
+        _ => Ok((input, None)),
+    }
+}
+
+/// The pre-Nautilus encoding: a zero u32 type, the nonce and a whole
+/// ceph_sockaddr_storage with the family in network byte order
+fn decode_legacy_addr_after_marker(input: &[u8]) -> IResult<&[u8], EntityAddr> {
+    let (input, _) = take(3usize)(input)?;
+    let (input, nonce) = le_u32(input)?;
+    let (rest, storage) = take(SOCKADDR_STORAGE_LEN)(input)?;
+    let (sockaddr, family) = be_u16(storage)?;
+    let (_, addr) = decode_sockaddr(sockaddr, family)?;
+    let addr_type = if addr.is_some() {
+        EntityAddrType::Legacy
+    } else {
+        EntityAddrType::None
+    };
+    Ok((
+        rest,
+        EntityAddr {
+            addr_type,
+            nonce,
+            addr,
+        },
+    ))
+}
+
+/// Parse the versioned body of an entity_addr_t once its marker byte of 1
+/// has been consumed
+pub(crate) fn decode_addr_after_marker(input: &[u8]) -> IResult<&[u8], EntityAddr> {
+    decode_versioned(input, 1, |_, body| {
+        let (body, addr_type) = le_u32(body)?;
+        let (body, nonce) = le_u32(body)?;
+        let (body, len) = le_u32(body)?;
+        let (body, sockaddr) = take(len)(body)?;
+        let addr = if len >= 2 {
+            let (sockaddr, family) = le_u16(sockaddr)?;
+            decode_sockaddr(sockaddr, family)?.1
+        } else {
+            None
+        };
+        Ok((
+            body,
+            EntityAddr {
+                addr_type: EntityAddrType::from_raw(addr_type),
+                nonce,
+                addr,
+            },
+        ))
+    })
+}
+
+impl Encode for EntityAddr {
+    /// Always uses the msgr2-capable encoding
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(1);
+        encode_versioned(buf, 1, 1, |buf| {
+            self.addr_type.as_raw().encode(buf);
+            self.nonce.encode(buf);
+            match self.addr {
+                Some(SocketAddr::V4(ref addr)) => {
+                    SOCKADDR_IN_LEN.encode(buf);
+                    AF_INET.encode(buf);
+                    buf.extend_from_slice(&addr.port().to_be_bytes());
+                    buf.extend_from_slice(&addr.ip().octets());
+                    buf.extend_from_slice(&[0; 8]);
+                }
+                Some(SocketAddr::V6(ref addr)) => {
+                    SOCKADDR_IN6_LEN.encode(buf);
+                    AF_INET6.encode(buf);
+                    buf.extend_from_slice(&addr.port().to_be_bytes());
+                    buf.extend_from_slice(&addr.flowinfo().to_be_bytes());
+                    buf.extend_from_slice(&addr.ip().octets());
+                    addr.scope_id().encode(buf);
+                }
+                None => 0u32.encode(buf),
+            }
+        });
+    }
+}
+
+impl Decode for EntityAddr {
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (rest, marker) = le_u8(input)?;
+        match marker {
+            0 => decode_legacy_addr_after_marker(rest),
+            1 => decode_addr_after_marker(rest),
+            _ => failure(input, ErrorKind::Tag),
+        }
+    }
+}
+
+impl EntityAddr {
+    /// The IP address, if there is one
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.addr.map(|addr| addr.ip())
+    }
+}
+
+/// Ceph's entity_addrvec_t: every address a daemon listens on
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct EntityAddrVec(pub Vec<EntityAddr>);
+
+impl Encode for EntityAddrVec {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(2);
+        self.0.encode(buf);
+    }
+}
+
+impl Decode for EntityAddrVec {
+    /// Also accepts a lone entity_addr_t in either encoding
+    fn decode(input: &[u8]) -> IResult<&[u8], Self> {
+        let (rest, marker) = le_u8(input)?;
+        match marker {
+            0 => {
+                let (rest, addr) = decode_legacy_addr_after_marker(rest)?;
+                Ok((rest, EntityAddrVec(vec![addr])))
+            }
+            1 => {
+                let (rest, addr) = decode_addr_after_marker(rest)?;
+                Ok((rest, EntityAddrVec(vec![addr])))
+            }
+            2 => {
+                let (rest, addrs) = decode_items(rest)?;
+                Ok((rest, EntityAddrVec(addrs)))
+            }
+            _ => failure(input, ErrorKind::Tag),
+        }
+    }
+}
@@ -25,6 +25,30 @@ use uuid::Error as UuidError;
 
 extern crate nix;
 
+/// Largest errno value librados returns
+const MAX_ERRNO: i32 = 4095;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_decodes_cmpext_mismatches() {
+        assert!(matches!(
+            RadosError::from(-MAX_ERRNO - 17),
+            RadosError::CompareMismatch(17)
+        ));
+        assert!(matches!(
+            RadosError::from(-MAX_ERRNO),
+            RadosError::CompareMismatch(0)
+        ));
+        assert!(matches!(
+            RadosError::from(-2),
+            RadosError::ApiError(nix::errno::Errno::ENOENT)
+        ));
+    }
+}
+
 /// Custom error handling for the library
 #[derive(Debug)]
 pub enum RadosError {
@@ -41,6 +65,9 @@ pub enum RadosError {
     /// This should be the minimum version and the current version
     MinVersion(CephVersion, CephVersion),
     Parse(String),
+    /// A compare-extent step found different data.  Holds the offset of the
+    /// first byte that didn't match.
+    CompareMismatch(u64),
 }
 
 pub type RadosResult<T> = Result<T, RadosError>;
@@ -62,6 +89,9 @@ impl fmt::Display for RadosError {
                 f.write_str("Ceph version is too low")
             }
             RadosError::Parse(ref _input) => f.write_str("An error occurred during parsing"),
+            RadosError::CompareMismatch(offset) => {
+                write!(f, "Compared data differs at offset {}", offset)
+            }
         }
     }
 }
@@ -81,6 +111,7 @@ impl StdError for RadosError {
             RadosError::SerdeError(ref e) => e.source(),
             RadosError::MinVersion(ref _min, ref _current_version) => None,
             RadosError::Parse(ref _input) => None,
+            RadosError::CompareMismatch(_) => None,
         }
     }
 }
@@ -139,6 +170,20 @@ impl From<Error> for RadosError {
 }
 impl From<i32> for RadosError {
     fn from(err: i32) -> RadosError {
+        // cmpext reports a mismatch as -MAX_ERRNO - offset, which is below
+        // every real errno
+        if err <= -MAX_ERRNO {
+            return RadosError::CompareMismatch((-(err as i64) - MAX_ERRNO as i64) as u64);
+        }
         RadosError::ApiError(nix::errno::Errno::from_raw(-err))
     }
 }
+impl From<RadosError> for Error {
+    fn from(err: RadosError) -> Error {
+        match err {
+            RadosError::IoError(e) => e,
+            RadosError::ApiError(errno) => Error::from_raw_os_error(errno as i32),
+            e => Error::other(e),
+        }
+    }
+}
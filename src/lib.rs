@@ -58,6 +58,8 @@ extern crate tracing;
 #[macro_use]
 extern crate nom;
 extern crate serde;
+#[cfg(feature = "cbor")]
+extern crate serde_cbor;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
@@ -67,20 +69,34 @@ extern crate uuid;
 pub mod admin_sockets;
 pub mod ceph;
 pub mod ceph_volume;
+pub mod checksum;
+pub mod cluster_log;
 pub mod cmd;
+pub mod codec;
+pub mod command_desc;
+pub mod config;
+pub mod copy;
+pub mod crush;
+pub mod denc;
 pub mod error;
 pub mod json;
+pub mod monmap;
+pub mod object_io;
+pub mod placement;
 pub mod rados;
 #[cfg(feature = "rados_striper")]
 pub mod rados_striper;
 pub mod status;
+pub mod supervisor;
+pub mod transfer;
 pub mod utils;
 
 mod ceph_client;
 mod ceph_version;
+mod crush_ln_table;
 mod mon_command;
 
-pub use crate::ceph_client::CephClient;
+pub use crate::ceph_client::{CephClient, HealthMuteGuard};
 pub use crate::ceph_version::CephVersion;
 pub use crate::cmd::{OsdOption, PoolOption};
 pub use crate::mon_command::MonCommand;
@@ -1,10 +1,9 @@
-use std::collections::HashMap;
-
-use serde_json;
+use serde_json::{self, Map, Value};
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
     #[test]
     fn it_builds_a_mon_command() {
@@ -18,47 +17,128 @@ mod tests {
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn it_builds_typed_arguments() {
+        let command = MonCommand::new()
+            .with_prefix("osd pool create")
+            .with("pool", "rbd")
+            .with_int("pg_num", 128)
+            .with_bool("yes_i_really_mean_it", true)
+            .with_strings("ids", &["1", "2"])
+            .with_input(b"payload".to_vec());
+
+        assert_eq!(
+            command.as_value(),
+            &json!({
+                "prefix": "osd pool create",
+                "format": "json",
+                "pool": "rbd",
+                "pg_num": 128,
+                "yes_i_really_mean_it": true,
+                "ids": ["1", "2"],
+            })
+        );
+        assert_eq!(command.prefix(), Some("osd pool create"));
+        assert_eq!(command.get("pg_num"), Some(&json!(128)));
+        assert_eq!(command.input(), b"payload");
+    }
 }
 
-pub struct MonCommand<'a> {
-    map: HashMap<&'a str, &'a str>,
+/// A mon command: its JSON arguments and an optional input payload
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonCommand {
+    args: Value,
+    input: Vec<u8>,
 }
 
-impl<'a> Default for MonCommand<'a> {
+impl Default for MonCommand {
     fn default() -> Self {
+        let mut args = Map::new();
+        args.insert("format".to_string(), Value::from("json"));
         MonCommand {
-            map: {
-                let mut map = HashMap::new();
-                map.insert("format", "json");
-                map
-            },
+            args: Value::Object(args),
+            input: Vec::new(),
         }
     }
 }
 
-impl<'a> MonCommand<'a> {
-    pub fn new() -> MonCommand<'a> {
+impl MonCommand {
+    pub fn new() -> MonCommand {
         MonCommand::default()
     }
 
-    pub fn with_format(self, format: &'a str) -> MonCommand<'a> {
+    pub fn with_format(self, format: &str) -> MonCommand {
         self.with("format", format)
     }
 
-    pub fn with_name(self, name: &'a str) -> MonCommand<'a> {
+    pub fn with_name(self, name: &str) -> MonCommand {
         self.with("name", name)
     }
 
-    pub fn with_prefix(self, prefix: &'a str) -> MonCommand<'a> {
+    pub fn with_prefix(self, prefix: &str) -> MonCommand {
         self.with("prefix", prefix)
     }
 
-    pub fn with(mut self, name: &'a str, value: &'a str) -> MonCommand<'a> {
-        self.map.insert(name, value);
+    /// A string argument
+    pub fn with(self, name: &str, value: &str) -> MonCommand {
+        self.with_value(name, Value::from(value))
+    }
+
+    /// An integer argument such as `pg_num`
+    pub fn with_int(self, name: &str, value: i64) -> MonCommand {
+        self.with_value(name, Value::from(value))
+    }
+
+    /// A float argument such as an OSD weight
+    pub fn with_float(self, name: &str, value: f64) -> MonCommand {
+        self.with_value(name, Value::from(value))
+    }
+
+    /// A boolean argument such as `yes_i_really_mean_it`
+    pub fn with_bool(self, name: &str, value: bool) -> MonCommand {
+        self.with_value(name, Value::from(value))
+    }
+
+    /// A list of strings, for arguments that take several values like `ids`
+    pub fn with_strings<S: AsRef<str>>(self, name: &str, values: &[S]) -> MonCommand {
+        let values = values.iter().map(|v| Value::from(v.as_ref())).collect();
+        self.with_value(name, Value::Array(values))
+    }
+
+    /// Any JSON value
+    pub fn with_value(mut self, name: &str, value: Value) -> MonCommand {
+        if let Value::Object(args) = &mut self.args {
+            args.insert(name.to_string(), value);
+        }
         self
     }
 
+    /// Data sent along with the command, such as the map for
+    /// `osd setcrushmap`
+    pub fn with_input(mut self, input: Vec<u8>) -> MonCommand {
+        self.input = input;
+        self
+    }
+
+    pub fn prefix(&self) -> Option<&str> {
+        self.get("prefix").and_then(Value::as_str)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.args.get(name)
+    }
+
+    /// The arguments as a JSON object
+    pub fn as_value(&self) -> &Value {
+        &self.args
+    }
+
+    pub fn input(&self) -> &[u8] {
+        &self.input
+    }
+
     pub fn as_json(&self) -> String {
-        serde_json::to_string(&self.map).unwrap()
+        self.args.to_string()
     }
 }
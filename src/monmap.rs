@@ -0,0 +1,346 @@
+//! Decoder for the binary monmap returned by `mon getmap` and written by
+//! `ceph-mon --extract-monmap` or `monmaptool`.
+//!
+//! ```rust,no_run
+//! # use ceph::monmap::MonMap;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let monmap = MonMap::from_file("/tmp/monmap")?;
+//! for mon in &monmap.mons {
+//!     println!("{} rank {} {:?}", mon.name, mon.rank, mon.v2_addr());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use nom::error::{Error, ErrorKind};
+use uuid::Uuid;
+
+use crate::denc::{
+    decode_versioned, parse_error, Decode, EntityAddr, EntityAddrType, EntityAddrVec, IResult,
+    UTime,
+};
+use crate::denc_struct;
+use crate::error::RadosResult;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::denc::{encode_versioned, Encode};
+
+    fn addr(addr_type: EntityAddrType, addr: &str) -> EntityAddr {
+        EntityAddr {
+            addr_type,
+            nonce: 0,
+            addr: Some(addr.parse().unwrap()),
+        }
+    }
+
+    fn mon_info(name: &str, ip: &str) -> MonInfo {
+        MonInfo {
+            name: name.into(),
+            public_addrs: EntityAddrVec(vec![
+                addr(EntityAddrType::Msgr2, &format!("{}:3300", ip)),
+                addr(EntityAddrType::Legacy, &format!("{}:6789", ip)),
+            ]),
+            priority: 0,
+            weight: 0,
+            crush_location: BTreeMap::new(),
+        }
+    }
+
+    fn fsid() -> Uuid {
+        "8ab0ba5c-3ab8-11ee-a5a6-525400c8e3a1".parse().unwrap()
+    }
+
+    fn encode_features(buf: &mut Vec<u8>, features: u64) {
+        encode_versioned(buf, 1, 1, |buf| features.encode(buf));
+    }
+
+    // What a Nautilus or later mon sends
+    fn monmap_v9() -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_versioned(&mut buf, 9, 6, |buf| {
+            fsid().encode(buf);
+            3u32.encode(buf);
+            UTime { sec: 200, nsec: 0 }.encode(buf);
+            UTime { sec: 100, nsec: 0 }.encode(buf);
+            encode_features(buf, 0x3f);
+            encode_features(buf, 0);
+            let mut infos = BTreeMap::new();
+            infos.insert("a".to_string(), mon_info("a", "10.0.0.2"));
+            infos.insert("b".to_string(), mon_info("b", "10.0.0.1"));
+            infos.encode(buf);
+            vec!["b".to_string(), "a".to_string()].encode(buf);
+            17u8.encode(buf);
+            // removed_ranks, election strategy, disallowed_leaders
+            Vec::<i32>::new().encode(buf);
+            1u8.encode(buf);
+            Vec::<String>::new().encode(buf);
+            // stretch mode
+            false.encode(buf);
+            "".encode(buf);
+            Vec::<String>::new().encode(buf);
+        });
+        buf
+    }
+
+    // What a Luminous mon sends: no ranks and a v1 address for each mon
+    fn monmap_v5() -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_versioned(&mut buf, 5, 3, |buf| {
+            fsid().encode(buf);
+            1u32.encode(buf);
+            let mut legacy = BTreeMap::new();
+            legacy.insert(
+                "a".to_string(),
+                addr(EntityAddrType::Legacy, "10.0.0.2:6789"),
+            );
+            legacy.insert(
+                "b".to_string(),
+                addr(EntityAddrType::Legacy, "10.0.0.1:6789"),
+            );
+            legacy.encode(buf);
+            UTime { sec: 0, nsec: 0 }.encode(buf);
+            UTime { sec: 0, nsec: 0 }.encode(buf);
+            encode_features(buf, 0);
+            encode_features(buf, 0);
+            let mut infos = BTreeMap::new();
+            for (name, addr) in legacy {
+                let mut info_buf = Vec::new();
+                encode_versioned(&mut info_buf, 2, 1, |buf| {
+                    name.encode(buf);
+                    addr.encode(buf);
+                    0u16.encode(buf);
+                });
+                infos.insert(name, info_buf);
+            }
+            (infos.len() as u32).encode(buf);
+            for (name, info) in infos {
+                name.encode(buf);
+                buf.extend(info);
+            }
+        });
+        buf
+    }
+
+    #[test]
+    fn it_decodes_a_nautilus_monmap() {
+        let monmap = MonMap::decode(&monmap_v9()).unwrap();
+        assert_eq!(monmap.fsid, fsid());
+        assert_eq!(monmap.epoch, 3);
+        assert_eq!(monmap.created.sec, 100);
+        assert_eq!(monmap.modified.sec, 200);
+        assert_eq!(monmap.min_mon_release, 17);
+        let names: Vec<&str> = monmap.mons.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["b", "a"]);
+        let b = monmap.mon("b").unwrap();
+        assert_eq!(b.rank, 0);
+        assert_eq!(b.v2_addr().unwrap().to_string(), "v2:10.0.0.1:3300/0");
+        assert_eq!(b.v1_addr().unwrap().to_string(), "v1:10.0.0.1:6789/0");
+    }
+
+    #[test]
+    fn it_decodes_a_luminous_monmap() {
+        let monmap = MonMap::decode(&monmap_v5()).unwrap();
+        assert_eq!(monmap.epoch, 1);
+        assert_eq!(monmap.min_mon_release, 0);
+        // Ranks follow address order
+        assert_eq!(monmap.mon("b").unwrap().rank, 0);
+        assert_eq!(monmap.mon("a").unwrap().rank, 1);
+        assert!(monmap.mons[0].v2_addr().is_none());
+    }
+
+    #[test]
+    fn it_rejects_truncated_monmaps() {
+        let bytes = monmap_v9();
+        assert!(MonMap::decode(&bytes[..bytes.len() / 2]).is_err());
+    }
+}
+
+denc_struct! {
+    // mon_info_t
+    struct MonInfo(5, 1) {
+        name: String,
+        public_addrs: EntityAddrVec,
+        #[since(2)]
+        priority: u16,
+        #[since(4)]
+        weight: u16,
+        #[since(5)]
+        crush_location: BTreeMap<String, String>,
+    }
+}
+
+/// A decoded monmap
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonMap {
+    pub fsid: Uuid,
+    pub epoch: u32,
+    pub created: UTime,
+    /// last_changed in Ceph's terms
+    pub modified: UTime,
+    /// The oldest release the mons may run, 0 before Nautilus
+    pub min_mon_release: u8,
+    /// Ordered by rank
+    pub mons: Vec<Mon>,
+}
+
+/// One monitor in a monmap
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mon {
+    pub name: String,
+    pub rank: usize,
+    pub addrs: Vec<EntityAddr>,
+    pub priority: u16,
+    pub weight: u16,
+    pub crush_location: BTreeMap<String, String>,
+}
+
+impl Mon {
+    /// The msgr v1 address, if the mon has one
+    pub fn v1_addr(&self) -> Option<&EntityAddr> {
+        self.addrs
+            .iter()
+            .find(|a| a.addr_type == EntityAddrType::Legacy)
+    }
+
+    /// The msgr v2 address, if the mon has one
+    pub fn v2_addr(&self) -> Option<&EntityAddr> {
+        self.addrs
+            .iter()
+            .find(|a| a.addr_type == EntityAddrType::Msgr2)
+    }
+}
+
+impl MonMap {
+    /// Decode the bytes returned by `cmd::mon_getmap`
+    pub fn decode(bytes: &[u8]) -> RadosResult<MonMap> {
+        decode_monmap(bytes)
+            .map(|(_, monmap)| monmap)
+            .map_err(|e| parse_error(bytes, e))
+    }
+
+    /// Decode a monmap saved to a file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> RadosResult<MonMap> {
+        MonMap::decode(&fs::read(path)?)
+    }
+
+    /// Look up a mon by name
+    pub fn mon(&self, name: &str) -> Option<&Mon> {
+        self.mons.iter().find(|m| m.name == name)
+    }
+}
+
+/// Skip a mon_feature_t
+fn skip_features(input: &[u8]) -> IResult<&[u8], u64> {
+    decode_versioned(input, 1, |_, body| u64::decode(body))
+}
+
+/// Before v6 ranks weren't stored and the mons were ranked by address
+fn legacy_ranks(infos: &BTreeMap<String, MonInfo>) -> Vec<String> {
+    let mut names: Vec<(Vec<u8>, &String)> = infos
+        .iter()
+        .map(|(name, info)| {
+            // entity_addr_t compares its raw bytes: type, nonce, then the
+            // sockaddr with the port ahead of the ip
+            let mut key = Vec::new();
+            if let Some(addr) = info.public_addrs.0.first() {
+                key.extend_from_slice(&addr.addr_type.as_raw().to_le_bytes());
+                key.extend_from_slice(&addr.nonce.to_le_bytes());
+                if let Some(sockaddr) = addr.addr {
+                    key.extend_from_slice(&sockaddr.port().to_be_bytes());
+                    match sockaddr.ip() {
+                        IpAddr::V4(ip) => key.extend_from_slice(&ip.octets()),
+                        IpAddr::V6(ip) => key.extend_from_slice(&ip.octets()),
+                    }
+                }
+            }
+            (key, name)
+        })
+        .collect();
+    names.sort();
+    names.into_iter().map(|(_, name)| name.clone()).collect()
+}
+
+fn decode_monmap(input: &[u8]) -> IResult<&[u8], MonMap> {
+    // Monmaps from before v3 (Bobtail) have a different header
+    if input.first().is_some_and(|v| *v < 3) {
+        return Err(nom::Err::Failure(Error::new(input, ErrorKind::Verify)));
+    }
+    decode_versioned(input, 9, |struct_v, body| {
+        let (body, fsid) = Uuid::decode(body)?;
+        let (body, epoch) = u32::decode(body)?;
+        let (body, legacy_addrs) = if struct_v < 6 {
+            BTreeMap::<String, EntityAddr>::decode(body)?
+        } else {
+            (body, BTreeMap::new())
+        };
+        let (body, modified) = UTime::decode(body)?;
+        let (mut body, created) = UTime::decode(body)?;
+        if struct_v >= 4 {
+            body = skip_features(body)?.0;
+            body = skip_features(body)?.0;
+        }
+        let (body, mut infos) = if struct_v >= 5 {
+            BTreeMap::<String, MonInfo>::decode(body)?
+        } else {
+            let infos = legacy_addrs
+                .into_iter()
+                .map(|(name, addr)| {
+                    let info = MonInfo {
+                        name: name.clone(),
+                        public_addrs: EntityAddrVec(vec![addr]),
+                        priority: 0,
+                        weight: 0,
+                        crush_location: BTreeMap::new(),
+                    };
+                    (name, info)
+                })
+                .collect();
+            (body, infos)
+        };
+        let (body, ranks) = if struct_v >= 6 {
+            Vec::<String>::decode(body)?
+        } else {
+            let ranks = legacy_ranks(&infos);
+            (body, ranks)
+        };
+        let (body, min_mon_release) = if struct_v >= 7 {
+            u8::decode(body)?
+        } else {
+            (body, 0)
+        };
+
+        let mut mons = Vec::with_capacity(ranks.len());
+        for (rank, name) in ranks.into_iter().enumerate() {
+            let info = match infos.remove(&name) {
+                Some(info) => info,
+                None => return Err(nom::Err::Failure(Error::new(body, ErrorKind::Verify))),
+            };
+            mons.push(Mon {
+                name,
+                rank,
+                addrs: info.public_addrs.0,
+                priority: info.priority,
+                weight: info.weight,
+                crush_location: info.crush_location,
+            });
+        }
+        Ok((
+            body,
+            MonMap {
+                fsid,
+                epoch,
+                created,
+                modified,
+                min_mon_release,
+                mons,
+            },
+        ))
+    })
+}
@@ -0,0 +1,223 @@
+//! A `std::io` view of a single RADOS object so it can be handed to code
+//! that expects `Read`, `Write` or `Seek` (archivers, compressors, parsers).
+//!
+//! ```rust,no_run
+//! # use ceph::ceph::connect_to_ceph;
+//! # use std::io::{Read, Write};
+//! # use std::sync::Arc;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let cluster = Arc::new(connect_to_ceph("admin", "/etc/ceph/ceph.conf")?);
+//! let ioctx = cluster.get_rados_ioctx("data")?;
+//! let mut object = ioctx.open("archive.tar").truncate(true);
+//! object.write_all(b"hello")?;
+//! object.flush()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+
+use crate::ceph::IoCtx;
+
+/// Default number of bytes fetched per read request.
+pub const DEFAULT_READ_AHEAD: usize = 1024 * 1024;
+/// Default number of bytes buffered before a write is sent to the OSD.
+pub const DEFAULT_WRITE_BUFFER: usize = 1024 * 1024;
+
+/// A buffered handle to one object in an io context.
+/// Created with `IoCtx::open`.  Buffered writes are flushed when the handle is
+/// dropped but errors are ignored there, so call `flush` to observe them.
+pub struct RadosObject<'a> {
+    ioctx: &'a IoCtx,
+    name: String,
+    pos: u64,
+    read_ahead: usize,
+    read_buf: Vec<u8>,
+    read_buf_offset: u64,
+    write_buffer: usize,
+    write_buf: Vec<u8>,
+    write_buf_offset: u64,
+    append: bool,
+    truncate_pending: bool,
+}
+
+impl<'a> RadosObject<'a> {
+    pub(crate) fn new(ioctx: &'a IoCtx, name: &str) -> RadosObject<'a> {
+        RadosObject {
+            ioctx,
+            name: name.to_string(),
+            pos: 0,
+            read_ahead: DEFAULT_READ_AHEAD,
+            read_buf: Vec::new(),
+            read_buf_offset: 0,
+            write_buffer: DEFAULT_WRITE_BUFFER,
+            write_buf: Vec::new(),
+            write_buf_offset: 0,
+            append: false,
+            truncate_pending: false,
+        }
+    }
+
+    /// Number of bytes to fetch per read.  0 reads exactly what is asked for.
+    pub fn read_ahead(mut self, bytes: usize) -> Self {
+        self.read_ahead = bytes;
+        self
+    }
+
+    /// Number of bytes to collect before writing.  A full buffer is sent by
+    /// the next write, read or flush.  0 disables buffering.
+    pub fn write_buffer(mut self, bytes: usize) -> Self {
+        self.write_buffer = bytes;
+        self
+    }
+
+    /// Every flush appends to the object instead of writing at the current
+    /// position.
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Replace the object's contents.  The first flush is sent with
+    /// write_full so the old data is dropped atomically.
+    pub fn truncate(mut self, truncate: bool) -> Self {
+        self.truncate_pending = truncate;
+        self
+    }
+
+    /// The name of the object this handle reads and writes.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn flush_write_buf(&mut self) -> Result<()> {
+        if self.write_buf.is_empty() && !self.truncate_pending {
+            return Ok(());
+        }
+        if self.truncate_pending {
+            if self.append || self.write_buf_offset == 0 || self.write_buf.is_empty() {
+                self.ioctx
+                    .rados_object_write_full(&self.name, &self.write_buf)?;
+                self.truncate_pending = false;
+                self.write_buf.clear();
+                return Ok(());
+            }
+            self.ioctx.rados_object_trunc(&self.name, 0)?;
+            self.truncate_pending = false;
+        }
+        if self.append {
+            self.ioctx
+                .rados_object_append(&self.name, &self.write_buf)?;
+        } else {
+            self.ioctx
+                .rados_object_write(&self.name, &self.write_buf, self.write_buf_offset)?;
+        }
+        self.write_buf.clear();
+        Ok(())
+    }
+
+    fn object_size(&mut self) -> Result<u64> {
+        self.flush_write_buf()?;
+        let (size, _) = self.ioctx.rados_object_stat(&self.name)?;
+        Ok(size)
+    }
+}
+
+impl<'a> Read for RadosObject<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Reads must observe anything written through this handle
+        self.flush_write_buf()?;
+
+        let buffered_end = self.read_buf_offset + self.read_buf.len() as u64;
+        if self.pos < self.read_buf_offset || self.pos >= buffered_end {
+            let len = self.read_ahead.max(buf.len());
+            let mut fill: Vec<u8> = Vec::with_capacity(len);
+            self.ioctx
+                .rados_object_read(&self.name, &mut fill, self.pos)?;
+            self.read_buf = fill;
+            self.read_buf_offset = self.pos;
+        }
+
+        let start = (self.pos - self.read_buf_offset) as usize;
+        let available = &self.read_buf[start..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<'a> Write for RadosObject<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        // Whatever was read ahead may now be stale
+        self.read_buf.clear();
+
+        // A buffer filled by the last call is sent now rather than then, so
+        // an error here means none of buf was taken
+        let buffer_full = self.write_buffer > 0 && self.write_buf.len() >= self.write_buffer;
+        if !self.write_buf.is_empty()
+            && (buffer_full
+                || (!self.append
+                    && self.pos != self.write_buf_offset + self.write_buf.len() as u64))
+        {
+            self.flush_write_buf()?;
+        }
+        if self.write_buf.is_empty() {
+            self.write_buf_offset = self.pos;
+        }
+
+        if self.write_buffer == 0 {
+            // Unbuffered: send it now, and drop it again if that fails
+            self.write_buf.extend_from_slice(buf);
+            if let Err(e) = self.flush_write_buf() {
+                self.write_buf.clear();
+                return Err(e);
+            }
+            self.pos += buf.len() as u64;
+            return Ok(buf.len());
+        }
+
+        // Only take what fits so a single huge write is split into
+        // write_buffer sized requests
+        let len = buf.len().min(self.write_buffer - self.write_buf.len());
+        self.write_buf.extend_from_slice(&buf[..len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.flush_write_buf()
+    }
+}
+
+impl<'a> Seek for RadosObject<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::Current(offset) => (self.pos, offset),
+            SeekFrom::End(offset) => (self.object_size()?, offset),
+        };
+        match base.checked_add_signed(offset) {
+            Some(new_pos) => {
+                self.pos = new_pos;
+                Ok(new_pos)
+            }
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl<'a> Drop for RadosObject<'a> {
+    fn drop(&mut self) {
+        let _ = self.flush_write_buf();
+    }
+}
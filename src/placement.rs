@@ -0,0 +1,663 @@
+//! Work out where an object lives without asking the mons.
+//!
+//! This follows the same path the OSDs do: hash the object name with the
+//! pool's hash, fold the hash into a placement group with
+//! `ceph_stable_mod`, then run the pool's CRUSH rule and apply upmaps,
+//! down OSDs, primary affinity and pg_temp.  The result is what
+//! `ceph osd map <pool> <object>` prints for the same maps.
+//!
+//! ```rust,no_run
+//! # use ceph::ceph::connect_to_ceph;
+//! # use ceph::placement::Placement;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let cluster = connect_to_ceph("admin", "/etc/ceph/ceph.conf")?;
+//! // Fetch the maps once, then map as many objects as needed
+//! let placement = Placement::fetch(&cluster)?;
+//! let p = placement.map_object("rbd", "rbd_directory")?;
+//! println!("{} -> {:?} primary {}", p.pgid, p.acting, p.acting_primary);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ceph::Rados;
+use crate::crush::{hash32_2, hashmix, CrushMap, CRUSH_ITEM_NONE, WEIGHT_ONE};
+use crate::error::{RadosError, RadosResult};
+
+/// Pool flag that mixes the pool id into the placement seed
+pub const POOL_FLAG_HASHPSPOOL: u64 = 1;
+
+/// Pool type of replicated pools
+pub const POOL_TYPE_REPLICATED: u8 = 1;
+/// Pool type of erasure coded pools
+pub const POOL_TYPE_ERASURE: u8 = 3;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_hashes_object_names() {
+        // Checked against `ceph osd map`
+        assert_eq!(StrHash::Rjenkins.hash(b"foo"), 0x7fc1_f406);
+        assert_eq!(StrHash::Rjenkins.hash(b"rbd_directory"), 0x30a9_8c1c);
+        // Longer than one 12 byte block
+        assert_eq!(
+            StrHash::Rjenkins.hash(b"rbd_header.1234567890ab"),
+            str_hash_rjenkins(b"rbd_header.1234567890ab")
+        );
+        assert_eq!(StrHash::Linux.hash(b""), 0);
+        assert_eq!(StrHash::Linux.hash(b"a"), (97 * 16 + 6) * 11);
+    }
+
+    #[test]
+    fn it_folds_hashes_into_pgs() {
+        // pg_num 12 has a mask of 15, so 13 folds down to 5
+        assert_eq!(pg_num_mask(12), 15);
+        assert_eq!(ceph_stable_mod(11, 12, 15), 11);
+        assert_eq!(ceph_stable_mod(13, 12, 15), 5);
+        assert_eq!(pg_num_mask(32), 31);
+        assert_eq!(ceph_stable_mod(0x7fc1_f406, 32, 31), 6);
+        assert_eq!(pg_num_mask(1), 0);
+    }
+
+    #[test]
+    fn it_parses_pgids() {
+        let pgid: PgId = "2.1c".parse().unwrap();
+        assert_eq!(
+            pgid,
+            PgId {
+                pool: 2,
+                seed: 0x1c
+            }
+        );
+        assert_eq!(pgid.to_string(), "2.1c");
+        assert!("2".parse::<PgId>().is_err());
+        assert!("2.xyz".parse::<PgId>().is_err());
+    }
+}
+
+/// ceph_str_hash_linux, the dcache hash
+pub fn str_hash_linux(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |hash, c| {
+        let c = *c as u32;
+        hash.wrapping_add(c << 4)
+            .wrapping_add(c >> 4)
+            .wrapping_mul(11)
+    })
+}
+
+/// ceph_str_hash_rjenkins, Bob Jenkins' lookup2 hash
+pub fn str_hash_rjenkins(data: &[u8]) -> u32 {
+    let word = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+    let mut a: u32 = 0x9e37_79b9;
+    let mut b: u32 = a;
+    let mut c: u32 = 0;
+
+    let mut chunks = data.chunks_exact(12);
+    for chunk in &mut chunks {
+        a = a.wrapping_add(word(&chunk[0..4]));
+        b = b.wrapping_add(word(&chunk[4..8]));
+        c = c.wrapping_add(word(&chunk[8..12]));
+        hashmix(&mut a, &mut b, &mut c);
+    }
+
+    // The low byte of c is reserved for the length
+    c = c.wrapping_add(data.len() as u32);
+    for (i, byte) in chunks.remainder().iter().enumerate() {
+        let byte = *byte as u32;
+        match i {
+            0..=3 => a = a.wrapping_add(byte << (8 * i)),
+            4..=7 => b = b.wrapping_add(byte << (8 * (i - 4))),
+            _ => c = c.wrapping_add(byte << (8 * (i - 7))),
+        }
+    }
+    hashmix(&mut a, &mut b, &mut c);
+    c
+}
+
+/// The object name hash a pool uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrHash {
+    Linux,
+    Rjenkins,
+}
+
+impl StrHash {
+    pub fn from_raw(hash: u8) -> Option<StrHash> {
+        match hash {
+            1 => Some(StrHash::Linux),
+            2 => Some(StrHash::Rjenkins),
+            _ => None,
+        }
+    }
+
+    pub fn hash(self, data: &[u8]) -> u32 {
+        match self {
+            StrHash::Linux => str_hash_linux(data),
+            StrHash::Rjenkins => str_hash_rjenkins(data),
+        }
+    }
+}
+
+/// Fold x into [0, b) so that growing b only moves a minimal set of values
+pub fn ceph_stable_mod(x: u32, b: u32, bmask: u32) -> u32 {
+    if x & bmask < b {
+        x & bmask
+    } else {
+        x & (bmask >> 1)
+    }
+}
+
+/// The smallest all-ones mask covering pg_num - 1
+pub fn pg_num_mask(pg_num: u32) -> u32 {
+    let bits = 32 - pg_num.saturating_sub(1).leading_zeros();
+    ((1u64 << bits) - 1) as u32
+}
+
+/// A placement group id, printed as pool.seed with the seed in hex
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PgId {
+    pub pool: i64,
+    pub seed: u32,
+}
+
+impl fmt::Display for PgId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:x}", self.pool, self.seed)
+    }
+}
+
+impl FromStr for PgId {
+    type Err = RadosError;
+
+    fn from_str(s: &str) -> RadosResult<PgId> {
+        let mut parts = s.splitn(2, '.');
+        let pool = parts.next().unwrap_or("");
+        let seed = parts
+            .next()
+            .ok_or_else(|| RadosError::new(format!("Invalid pgid {}", s)))?;
+        Ok(PgId {
+            pool: pool.parse()?,
+            seed: u32::from_str_radix(seed, 16)?,
+        })
+    }
+}
+
+impl TryFrom<String> for PgId {
+    type Error = RadosError;
+
+    fn try_from(s: String) -> RadosResult<PgId> {
+        s.parse()
+    }
+}
+
+/// The placement settings of a pool
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Pool {
+    #[serde(rename = "pool")]
+    pub id: i64,
+    #[serde(rename = "pool_name")]
+    pub name: String,
+    pub flags: u64,
+    #[serde(rename = "type")]
+    pub pool_type: u8,
+    pub size: u32,
+    /// crush_ruleset before Luminous, where it was the same as the rule id
+    /// on any map created by the tools
+    #[serde(alias = "crush_ruleset")]
+    pub crush_rule: u32,
+    pub object_hash: u8,
+    pub pg_num: u32,
+    #[serde(rename = "pg_placement_num")]
+    pub pgp_num: u32,
+}
+
+impl Pool {
+    /// Replicated pools close up gaps left by missing OSDs, erasure coded
+    /// ones keep each shard in its position
+    pub fn can_shift_osds(&self) -> bool {
+        self.pool_type == POOL_TYPE_REPLICATED
+    }
+
+    /// The placement group an object name hash falls in
+    pub fn raw_pg_to_pg(&self, seed: u32) -> u32 {
+        ceph_stable_mod(seed, self.pg_num, pg_num_mask(self.pg_num))
+    }
+
+    /// The CRUSH input for a placement group
+    pub fn pg_to_pps(&self, pgid: PgId) -> u32 {
+        let ps = ceph_stable_mod(pgid.seed, self.pgp_num, pg_num_mask(self.pgp_num));
+        if self.flags & POOL_FLAG_HASHPSPOOL != 0 {
+            hash32_2(ps, pgid.pool as u32)
+        } else {
+            ps.wrapping_add(pgid.pool as u32)
+        }
+    }
+
+    /// Hash an object name, prefixed by its namespace if it has one
+    pub fn hash_key(&self, key: &str, namespace: &str) -> RadosResult<u32> {
+        let hash = StrHash::from_raw(self.object_hash).ok_or_else(|| {
+            RadosError::new(format!(
+                "Pool {} uses unknown object hash {}",
+                self.name, self.object_hash
+            ))
+        })?;
+        if namespace.is_empty() {
+            Ok(hash.hash(key.as_bytes()))
+        } else {
+            let mut buf = Vec::with_capacity(namespace.len() + 1 + key.len());
+            buf.extend_from_slice(namespace.as_bytes());
+            buf.push(0x1f);
+            buf.extend_from_slice(key.as_bytes());
+            Ok(hash.hash(&buf))
+        }
+    }
+}
+
+/// The state of one OSD
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct OsdInfo {
+    pub osd: i32,
+    pub up: u8,
+    #[serde(rename = "in")]
+    pub is_in: u8,
+    /// Reweight between 0 (out) and 1
+    pub weight: f64,
+    pub primary_affinity: f64,
+}
+
+/// The parts of `osd dump` needed for placement
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OsdMap {
+    pub epoch: u32,
+    pub max_osd: i32,
+    pub pools: BTreeMap<i64, Pool>,
+    /// Only OSDs that exist
+    pub osds: BTreeMap<i32, OsdInfo>,
+    pub pg_upmap: BTreeMap<PgId, Vec<i32>>,
+    pub pg_upmap_items: BTreeMap<PgId, Vec<(i32, i32)>>,
+    pub pg_upmap_primaries: BTreeMap<PgId, i32>,
+    pub pg_temp: BTreeMap<PgId, Vec<i32>>,
+    pub primary_temp: BTreeMap<PgId, i32>,
+}
+
+#[derive(Deserialize)]
+struct OsdDump {
+    epoch: u32,
+    max_osd: i32,
+    pools: Vec<Pool>,
+    osds: Vec<OsdInfo>,
+    #[serde(default)]
+    pg_upmap: Vec<DumpPgOsds>,
+    #[serde(default)]
+    pg_upmap_items: Vec<DumpUpmapItems>,
+    #[serde(default)]
+    pg_upmap_primaries: Vec<DumpPgPrimary>,
+    #[serde(default)]
+    pg_temp: Vec<DumpPgOsds>,
+    #[serde(default)]
+    primary_temp: Vec<DumpPgOsd>,
+}
+
+#[derive(Deserialize)]
+struct DumpPgOsds {
+    pgid: PgId,
+    osds: Vec<i32>,
+}
+
+#[derive(Deserialize)]
+struct DumpUpmapItems {
+    pgid: PgId,
+    mappings: Vec<DumpUpmapItem>,
+}
+
+#[derive(Deserialize)]
+struct DumpUpmapItem {
+    from: i32,
+    to: i32,
+}
+
+#[derive(Deserialize)]
+struct DumpPgPrimary {
+    pgid: PgId,
+    primary_osd: i32,
+}
+
+#[derive(Deserialize)]
+struct DumpPgOsd {
+    pgid: PgId,
+    osd: i32,
+}
+
+/// Convert a dumped float weight back to 16.16 fixed point
+fn fixed_weight(weight: f64) -> u32 {
+    (weight * WEIGHT_ONE as f64).round() as u32
+}
+
+impl OsdMap {
+    /// Parse the JSON printed by `osd dump`
+    pub fn from_json(json: &str) -> RadosResult<OsdMap> {
+        let dump: OsdDump = serde_json::from_str(json)?;
+        Ok(OsdMap {
+            epoch: dump.epoch,
+            max_osd: dump.max_osd,
+            pools: dump.pools.into_iter().map(|p| (p.id, p)).collect(),
+            osds: dump.osds.into_iter().map(|o| (o.osd, o)).collect(),
+            pg_upmap: dump
+                .pg_upmap
+                .into_iter()
+                .map(|p| (p.pgid, p.osds))
+                .collect(),
+            pg_upmap_items: dump
+                .pg_upmap_items
+                .into_iter()
+                .map(|p| (p.pgid, p.mappings.iter().map(|m| (m.from, m.to)).collect()))
+                .collect(),
+            pg_upmap_primaries: dump
+                .pg_upmap_primaries
+                .into_iter()
+                .map(|p| (p.pgid, p.primary_osd))
+                .collect(),
+            pg_temp: dump.pg_temp.into_iter().map(|p| (p.pgid, p.osds)).collect(),
+            primary_temp: dump
+                .primary_temp
+                .into_iter()
+                .map(|p| (p.pgid, p.osd))
+                .collect(),
+        })
+    }
+
+    /// Look up a pool by name
+    pub fn pool_by_name(&self, name: &str) -> Option<&Pool> {
+        self.pools.values().find(|p| p.name == name)
+    }
+
+    fn exists(&self, osd: i32) -> bool {
+        osd >= 0 && osd < self.max_osd && self.osds.contains_key(&osd)
+    }
+
+    fn is_up(&self, osd: i32) -> bool {
+        self.osds.get(&osd).is_some_and(|o| o.up != 0)
+    }
+
+    /// 16.16 reweight of an OSD, 0 if it's out or doesn't exist
+    fn weight(&self, osd: i32) -> u32 {
+        self.osds.get(&osd).map_or(0, |o| fixed_weight(o.weight))
+    }
+
+    /// The reweight of every OSD, as CRUSH wants them
+    pub fn osd_weights(&self) -> Vec<u32> {
+        (0..self.max_osd).map(|osd| self.weight(osd)).collect()
+    }
+
+    /// Apply pg_upmap, pg_upmap_items and pg_upmap_primaries to a raw
+    /// CRUSH result
+    fn apply_upmap(&self, pgid: PgId, raw: &mut Vec<i32>) {
+        // An upmap onto an out OSD is ignored
+        let marked_out = |osd: i32| {
+            osd != CRUSH_ITEM_NONE && osd >= 0 && osd < self.max_osd && self.weight(osd) == 0
+        };
+
+        if let Some(osds) = self.pg_upmap.get(&pgid) {
+            if osds.iter().any(|osd| marked_out(*osd)) {
+                return;
+            }
+            *raw = osds.clone();
+        }
+
+        if let Some(items) = self.pg_upmap_items.get(&pgid) {
+            for (from, to) in items {
+                if raw.contains(to) {
+                    continue;
+                }
+                if marked_out(*to) {
+                    continue;
+                }
+                if let Some(pos) = raw.iter().position(|osd| osd == from) {
+                    raw[pos] = *to;
+                }
+            }
+        }
+
+        if let Some(primary) = self.pg_upmap_primaries.get(&pgid) {
+            let primary = *primary;
+            if primary != CRUSH_ITEM_NONE
+                && primary >= 0
+                && primary < self.max_osd
+                && self.weight(primary) != 0
+            {
+                if let Some(pos) = raw.iter().skip(1).position(|osd| *osd == primary) {
+                    raw[pos + 1] = raw[0];
+                    raw[0] = primary;
+                }
+            }
+        }
+    }
+
+    /// Move the primary away from OSDs with a reduced primary affinity
+    fn apply_primary_affinity(&self, pps: u32, pool: &Pool, osds: &mut [i32], primary: &mut i32) {
+        let affinity = |osd: i32| {
+            self.osds
+                .get(&osd)
+                .map_or(WEIGHT_ONE, |o| fixed_weight(o.primary_affinity))
+        };
+        if !osds
+            .iter()
+            .any(|osd| *osd != CRUSH_ITEM_NONE && affinity(*osd) != WEIGHT_ONE)
+        {
+            return;
+        }
+
+        // Rejected OSDs are remembered as a fallback in case every one of
+        // them is rejected
+        let mut pos = None;
+        for (i, osd) in osds.iter().enumerate() {
+            if *osd == CRUSH_ITEM_NONE {
+                continue;
+            }
+            let a = affinity(*osd);
+            if a < WEIGHT_ONE && (hash32_2(pps, *osd as u32) >> 16) >= a {
+                if pos.is_none() {
+                    pos = Some(i);
+                }
+            } else {
+                pos = Some(i);
+                break;
+            }
+        }
+        let pos = match pos {
+            Some(pos) => pos,
+            None => return,
+        };
+        *primary = osds[pos];
+        if pool.can_shift_osds() && pos > 0 {
+            osds[..=pos].rotate_right(1);
+        }
+    }
+
+    /// The first OSD that isn't a hole, or -1
+    fn pick_primary(osds: &[i32]) -> i32 {
+        osds.iter()
+            .copied()
+            .find(|osd| *osd != CRUSH_ITEM_NONE)
+            .unwrap_or(-1)
+    }
+
+    /// Map a placement group to its up and acting sets
+    pub fn pg_to_up_acting(&self, crush: &CrushMap, pgid: PgId) -> RadosResult<PgMapping> {
+        let pool = self
+            .pools
+            .get(&pgid.pool)
+            .ok_or_else(|| RadosError::new(format!("Pool {} does not exist", pgid.pool)))?;
+        let pgid = PgId {
+            pool: pgid.pool,
+            seed: pool.raw_pg_to_pg(pgid.seed),
+        };
+
+        // pg_temp overrides the acting set
+        let mut acting: Vec<i32> = Vec::new();
+        if let Some(temp) = self.pg_temp.get(&pgid) {
+            for osd in temp {
+                if !self.exists(*osd) || !self.is_up(*osd) {
+                    if !pool.can_shift_osds() {
+                        acting.push(CRUSH_ITEM_NONE);
+                    }
+                } else {
+                    acting.push(*osd);
+                }
+            }
+        }
+        let mut acting_primary = match self.primary_temp.get(&pgid) {
+            Some(osd) => *osd,
+            None => Self::pick_primary(&acting),
+        };
+
+        let pps = pool.pg_to_pps(pgid);
+        let mut raw = crush.do_rule(
+            pool.crush_rule,
+            pps as i32,
+            pool.size as usize,
+            &self.osd_weights(),
+            pgid.pool,
+        );
+        if pool.can_shift_osds() {
+            raw.retain(|osd| self.exists(*osd));
+        } else {
+            for osd in raw.iter_mut() {
+                if !self.exists(*osd) {
+                    *osd = CRUSH_ITEM_NONE;
+                }
+            }
+        }
+        self.apply_upmap(pgid, &mut raw);
+
+        let mut up: Vec<i32> = Vec::with_capacity(raw.len());
+        for osd in raw {
+            if self.exists(osd) && self.is_up(osd) {
+                up.push(osd);
+            } else if !pool.can_shift_osds() {
+                up.push(CRUSH_ITEM_NONE);
+            }
+        }
+        let mut up_primary = Self::pick_primary(&up);
+        self.apply_primary_affinity(pps, pool, &mut up, &mut up_primary);
+
+        if acting.is_empty() {
+            acting = up.clone();
+            if acting_primary == -1 {
+                acting_primary = up_primary;
+            }
+        }
+        Ok(PgMapping {
+            pgid,
+            up,
+            up_primary,
+            acting,
+            acting_primary,
+        })
+    }
+}
+
+/// The OSDs serving a placement group
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgMapping {
+    pub pgid: PgId,
+    pub up: Vec<i32>,
+    pub up_primary: i32,
+    pub acting: Vec<i32>,
+    pub acting_primary: i32,
+}
+
+/// Where an object is stored, in the shape `osd map` prints
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ObjectPlacement {
+    pub epoch: u32,
+    /// Pool name
+    pub pool: String,
+    pub pool_id: i64,
+    /// namespace/name, or just the name outside of a namespace
+    pub objname: String,
+    /// The object's hash, before it's folded down to pg_num
+    pub raw_pgid: PgId,
+    pub pgid: PgId,
+    pub up: Vec<i32>,
+    pub up_primary: i32,
+    pub acting: Vec<i32>,
+    pub acting_primary: i32,
+}
+
+/// A CRUSH map and OSD map to place objects with
+#[derive(Debug, Clone)]
+pub struct Placement {
+    pub crush: CrushMap,
+    pub osdmap: OsdMap,
+}
+
+impl Placement {
+    pub fn new(crush: CrushMap, osdmap: OsdMap) -> Placement {
+        Placement { crush, osdmap }
+    }
+
+    /// Fetch the current maps from the cluster.  The result goes stale as
+    /// soon as the OSD map changes.
+    pub fn fetch(cluster_handle: &Rados) -> RadosResult<Placement> {
+        Ok(Placement {
+            crush: crate::cmd::osd_crush_dump(cluster_handle)?,
+            osdmap: crate::cmd::osd_dump(cluster_handle)?,
+        })
+    }
+
+    /// Map an object in the default namespace
+    pub fn map_object(&self, pool: &str, object: &str) -> RadosResult<ObjectPlacement> {
+        self.map_object_in(pool, "", None, object)
+    }
+
+    /// Map an object with a namespace and, if it has one, a locator key
+    /// that's hashed in place of its name
+    pub fn map_object_in(
+        &self,
+        pool: &str,
+        namespace: &str,
+        locator: Option<&str>,
+        object: &str,
+    ) -> RadosResult<ObjectPlacement> {
+        let pool_info = self
+            .osdmap
+            .pool_by_name(pool)
+            .ok_or_else(|| RadosError::new(format!("Pool {} does not exist", pool)))?;
+        let seed = pool_info.hash_key(locator.unwrap_or(object), namespace)?;
+        let raw_pgid = PgId {
+            pool: pool_info.id,
+            seed,
+        };
+        let mapping = self.osdmap.pg_to_up_acting(&self.crush, raw_pgid)?;
+        let objname = if namespace.is_empty() {
+            object.to_string()
+        } else {
+            format!("{}/{}", namespace, object)
+        };
+        Ok(ObjectPlacement {
+            epoch: self.osdmap.epoch,
+            pool: pool.to_string(),
+            pool_id: pool_info.id,
+            objname,
+            raw_pgid,
+            pgid: mapping.pgid,
+            up: mapping.up,
+            up_primary: mapping.up_primary,
+            acting: mapping.acting,
+            acting_primary: mapping.acting_primary,
+        })
+    }
+
+    /// Map a placement group
+    pub fn map_pg(&self, pgid: PgId) -> RadosResult<PgMapping> {
+        self.osdmap.pg_to_up_acting(&self.crush, pgid)
+    }
+}
@@ -156,6 +156,11 @@ impl ::std::default::Default for Struct_rados_cluster_stat_t {
     }
 }
 
+pub type rados_checksum_type_t = ::libc::c_uint;
+pub const LIBRADOS_CHECKSUM_TYPE_XXHASH32: rados_checksum_type_t = 0;
+pub const LIBRADOS_CHECKSUM_TYPE_XXHASH64: rados_checksum_type_t = 1;
+pub const LIBRADOS_CHECKSUM_TYPE_CRC32C: rados_checksum_type_t = 2;
+
 pub type rados_write_op_t = *mut ::std::os::raw::c_void;
 
 pub type rados_read_op_t = *mut ::std::os::raw::c_void;
@@ -286,6 +291,8 @@ extern "C" {
 
     pub fn rados_get_instance_id(cluster: rados_t) -> u64;
 
+    pub fn rados_getaddrs(cluster: rados_t, addrs: *mut *mut ::libc::c_char) -> ::libc::c_int;
+
     pub fn rados_ioctx_create(
         cluster: rados_t,
         pool_name: *const ::libc::c_char,
@@ -368,6 +375,12 @@ extern "C" {
 
     pub fn rados_ioctx_set_namespace(io: rados_ioctx_t, nspace: *const ::libc::c_char) -> ();
 
+    pub fn rados_ioctx_get_namespace(
+        io: rados_ioctx_t,
+        buf: *mut ::libc::c_char,
+        maxlen: ::libc::c_uint,
+    ) -> ::libc::c_int;
+
     pub fn rados_nobjects_list_open(io: rados_ioctx_t, ctx: *mut rados_list_ctx_t)
         -> ::libc::c_int;
 
@@ -487,6 +500,15 @@ extern "C" {
         len: size_t,
     ) -> ::libc::c_int;
 
+    pub fn rados_writesame(
+        io: rados_ioctx_t,
+        oid: *const ::libc::c_char,
+        buf: *const ::libc::c_char,
+        data_len: size_t,
+        write_len: size_t,
+        off: u64,
+    ) -> ::libc::c_int;
+
     pub fn rados_clone_range(
         io: rados_ioctx_t,
         dst: *const ::libc::c_char,
@@ -511,6 +533,14 @@ extern "C" {
         off: u64,
     ) -> ::libc::c_int;
 
+    pub fn rados_cmpext(
+        io: rados_ioctx_t,
+        o: *const ::libc::c_char,
+        cmp_buf: *const ::libc::c_char,
+        cmp_len: size_t,
+        off: u64,
+    ) -> ::libc::c_int;
+
     pub fn rados_remove(io: rados_ioctx_t, oid: *const ::libc::c_char) -> ::libc::c_int;
 
     pub fn rados_trunc(io: rados_ioctx_t, oid: *const ::libc::c_char, size: u64) -> ::libc::c_int;
@@ -652,6 +682,14 @@ extern "C" {
         off: u64,
     ) -> ::libc::c_int;
     pub fn rados_aio_flush(io: rados_ioctx_t) -> ::libc::c_int;
+    pub fn rados_aio_cmpext(
+        io: rados_ioctx_t,
+        o: *const ::libc::c_char,
+        completion: rados_completion_t,
+        cmp_buf: *const ::libc::c_char,
+        cmp_len: size_t,
+        off: u64,
+    ) -> ::libc::c_int;
     pub fn rados_aio_flush_async(
         io: rados_ioctx_t,
         completion: rados_completion_t,
@@ -720,6 +758,13 @@ extern "C" {
     pub fn rados_write_op_set_flags(write_op: rados_write_op_t, flags: ::libc::c_int) -> ();
     pub fn rados_write_op_assert_exists(write_op: rados_write_op_t) -> ();
     pub fn rados_write_op_assert_version(write_op: rados_write_op_t, ver: u64) -> ();
+    pub fn rados_write_op_cmpext(
+        write_op: rados_write_op_t,
+        cmp_buf: *const ::libc::c_char,
+        cmp_len: size_t,
+        off: u64,
+        prval: *mut ::libc::c_int,
+    ) -> ();
     pub fn rados_write_op_cmpxattr(
         write_op: rados_write_op_t,
         name: *const ::libc::c_char,
@@ -758,6 +803,13 @@ extern "C" {
         buffer: *const ::libc::c_char,
         len: size_t,
     ) -> ();
+    pub fn rados_write_op_writesame(
+        write_op: rados_write_op_t,
+        buffer: *const ::libc::c_char,
+        data_len: size_t,
+        write_len: size_t,
+        offset: u64,
+    ) -> ();
     pub fn rados_write_op_append(
         write_op: rados_write_op_t,
         buffer: *const ::libc::c_char,
@@ -774,6 +826,13 @@ extern "C" {
         in_len: size_t,
         prval: *mut ::libc::c_int,
     ) -> ();
+    pub fn rados_write_op_copy_from(
+        write_op: rados_write_op_t,
+        src: *const ::libc::c_char,
+        src_io: rados_ioctx_t,
+        src_version: u64,
+        src_fadvise_flags: u32,
+    ) -> ();
     pub fn rados_write_op_omap_set(
         write_op: rados_write_op_t,
         keys: *const *const ::libc::c_char,
@@ -812,6 +871,13 @@ extern "C" {
     pub fn rados_read_op_set_flags(read_op: rados_read_op_t, flags: ::libc::c_int) -> ();
     pub fn rados_read_op_assert_exists(read_op: rados_read_op_t) -> ();
     pub fn rados_read_op_assert_version(write_op: rados_read_op_t, ver: u64) -> ();
+    pub fn rados_read_op_cmpext(
+        read_op: rados_read_op_t,
+        cmp_buf: *const ::libc::c_char,
+        cmp_len: size_t,
+        off: u64,
+        prval: *mut ::libc::c_int,
+    ) -> ();
     pub fn rados_read_op_cmpxattr(
         read_op: rados_read_op_t,
         name: *const ::libc::c_char,
@@ -867,6 +933,18 @@ extern "C" {
         used_len: *mut size_t,
         prval: *mut ::libc::c_int,
     ) -> ();
+    pub fn rados_read_op_checksum(
+        read_op: rados_read_op_t,
+        type_: rados_checksum_type_t,
+        init_value: *const ::libc::c_char,
+        init_value_len: size_t,
+        offset: u64,
+        len: size_t,
+        chunk_size: size_t,
+        pchecksum: *mut ::libc::c_char,
+        checksum_len: size_t,
+        prval: *mut ::libc::c_int,
+    ) -> ();
     pub fn rados_read_op_omap_get_vals(
         read_op: rados_read_op_t,
         start_after: *const ::libc::c_char,
@@ -1001,6 +1079,29 @@ extern "C" {
         outs: *mut *mut ::libc::c_char,
         outslen: *mut size_t,
     ) -> ::libc::c_int;
+    pub fn rados_mgr_command(
+        cluster: rados_t,
+        cmd: *mut *const ::libc::c_char,
+        cmdlen: size_t,
+        inbuf: *const ::libc::c_char,
+        inbuflen: size_t,
+        outbuf: *mut *mut ::libc::c_char,
+        outbuflen: *mut size_t,
+        outs: *mut *mut ::libc::c_char,
+        outslen: *mut size_t,
+    ) -> ::libc::c_int;
+    pub fn rados_mgr_command_target(
+        cluster: rados_t,
+        name: *const ::libc::c_char,
+        cmd: *mut *const ::libc::c_char,
+        cmdlen: size_t,
+        inbuf: *const ::libc::c_char,
+        inbuflen: size_t,
+        outbuf: *mut *mut ::libc::c_char,
+        outbuflen: *mut size_t,
+        outs: *mut *mut ::libc::c_char,
+        outslen: *mut size_t,
+    ) -> ::libc::c_int;
     pub fn rados_monitor_log(
         cluster: rados_t,
         level: *const ::libc::c_char,
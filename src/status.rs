@@ -12,6 +12,378 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::cmd::HealthStatus;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_osds_and_pgs_in_detail() {
+        let check: HealthCheck = serde_json::from_value(json!({
+            "severity": "HEALTH_WARN",
+            "summary": {"message": "3 slow ops, oldest one blocked for 35 sec, daemons [osd.3,osd.12] have slow ops."},
+            "detail": [
+                {"message": "osd.12 (root=default,host=node3) is down"},
+                {"message": "daemons [osd.3,osd.12] have slow ops."},
+                {"message": "pg 2.1f is stuck undersized for 61s, current state active+undersized, last acting [0,1]"},
+            ],
+        }))
+        .unwrap();
+        assert_eq!(check.osds(), vec![3, 12]);
+        assert_eq!(check.pgs(), vec!["2.1f"]);
+        assert_eq!(check.count(), 3);
+        assert!(!check.muted);
+    }
+}
+
+/// `ceph status` as of Luminous.  Fields a release added or dropped are
+/// optional, and anything not modelled here lands in `other`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ClusterStatus {
+    pub fsid: String,
+    pub health: StatusHealth,
+    pub election_epoch: u64,
+    pub quorum: Vec<u64>,
+    pub quorum_names: Vec<String>,
+    pub quorum_age: Option<u64>, //Nautilus
+    pub monmap: StatusMonMap,
+    pub osdmap: StatusOsdMap,
+    pub pgmap: StatusPgMap,
+    pub fsmap: Option<StatusFsMap>,
+    pub mgrmap: Option<StatusMgrMap>,
+    pub servicemap: Option<ServiceMap>,
+    #[serde(default)]
+    pub progress_events: HashMap<String, ProgressEvent>, //Nautilus
+    // other output not captured through the above attributes
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusHealth {
+    pub status: HealthStatus,
+    #[serde(default)]
+    pub checks: HashMap<String, HealthCheck>,
+    #[serde(default)]
+    pub mutes: Vec<HealthMute>, //Octopus
+    pub overall_status: Option<HealthStatus>, //Luminous and Mimic
+}
+
+/// `health detail` has the same layout, with `detail` filled in on each
+/// check
+pub type HealthDetail = StatusHealth;
+
+impl StatusHealth {
+    pub fn check(&self, code: &str) -> Option<&HealthCheck> {
+        self.checks.get(code)
+    }
+
+    /// Whether a check such as OSD_DOWN is raised and not muted
+    pub fn is_firing(&self, code: &str) -> bool {
+        self.check(code).is_some_and(|check| !check.muted)
+    }
+
+    /// Codes of the checks raised and not muted, sorted
+    pub fn firing(&self) -> Vec<&str> {
+        let mut codes: Vec<&str> = self
+            .checks
+            .iter()
+            .filter(|(_, check)| !check.muted)
+            .map(|(code, _)| code.as_str())
+            .collect();
+        codes.sort_unstable();
+        codes
+    }
+
+    /// The OSD ids named in a check's detail, e.g. the OSDs down for OSD_DOWN
+    pub fn osds(&self, code: &str) -> Vec<u64> {
+        self.check(code).map(HealthCheck::osds).unwrap_or_default()
+    }
+}
+
+/// A health check such as OSD_DOWN.  `detail` is only filled in by
+/// `health detail`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct HealthCheck {
+    pub severity: HealthStatus,
+    pub summary: HealthCheckSummary,
+    #[serde(default)]
+    pub detail: Vec<HealthCheckDetail>,
+    #[serde(default)]
+    pub muted: bool, //Octopus
+}
+
+impl HealthCheck {
+    /// How many things the check is about, e.g. OSDs down or PGs degraded
+    pub fn count(&self) -> u64 {
+        self.summary.count.unwrap_or(self.detail.len() as u64)
+    }
+
+    pub fn detail_lines(&self) -> Vec<&str> {
+        self.detail.iter().map(|d| d.message.as_str()).collect()
+    }
+
+    /// Every `osd.N` the summary and detail lines mention, sorted and
+    /// without duplicates.  SLOW_OPS only names them in the summary.
+    pub fn osds(&self) -> Vec<u64> {
+        let mut osds: Vec<u64> = std::iter::once(&self.summary.message)
+            .chain(self.detail.iter().map(|d| &d.message))
+            .flat_map(|message| {
+                message
+                    .match_indices("osd.")
+                    .map(move |(i, _)| &message[i + 4..])
+            })
+            .filter_map(|rest| {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                rest[..end].parse().ok()
+            })
+            .collect();
+        osds.sort_unstable();
+        osds.dedup();
+        osds
+    }
+
+    /// The pgids of detail lines like `pg 2.1f is stuck undersized ...`
+    pub fn pgs(&self) -> Vec<&str> {
+        self.detail
+            .iter()
+            .filter_map(|d| d.message.strip_prefix("pg "))
+            .filter_map(|rest| rest.split_whitespace().next())
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HealthCheckSummary {
+    pub message: String,
+    pub count: Option<u64>, //Octopus
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HealthCheckDetail {
+    pub message: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HealthMute {
+    pub code: String,
+    /// When the mute expires, if it was given a ttl
+    pub ttl: Option<String>,
+    pub sticky: bool,
+    pub summary: String,
+    pub count: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusMonMap {
+    pub epoch: u64,
+    pub min_mon_release_name: Option<String>, //Nautilus
+    pub num_mons: Option<u64>,                //Octopus
+    // Luminous to Nautilus print the whole monmap
+    pub fsid: Option<String>,
+    pub modified: Option<String>,
+    pub created: Option<String>,
+    #[serde(default)]
+    pub mons: Vec<StatusMon>,
+}
+
+impl StatusMonMap {
+    pub fn mon_count(&self) -> u64 {
+        self.num_mons.unwrap_or(self.mons.len() as u64)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusMon {
+    pub rank: Option<u64>,
+    pub name: String,
+    pub addr: Option<String>,
+    pub public_addr: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum StatusOsdMap {
+    // Luminous to Nautilus
+    Wrapped { osdmap: OsdMapSummary },
+    UnWrapped(OsdMapSummary),
+}
+
+impl StatusOsdMap {
+    pub fn summary(&self) -> &OsdMapSummary {
+        match self {
+            StatusOsdMap::Wrapped { osdmap } => osdmap,
+            StatusOsdMap::UnWrapped(osdmap) => osdmap,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct OsdMapSummary {
+    pub epoch: u64,
+    pub num_osds: u64,
+    pub num_up_osds: u64,
+    pub num_in_osds: u64,
+    pub osd_up_since: Option<u64>, //Octopus
+    pub osd_in_since: Option<u64>, //Octopus
+    pub full: Option<bool>,        //Before Octopus
+    pub nearfull: Option<bool>,    //Before Octopus
+    pub num_remapped_pgs: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusPgState {
+    pub state_name: String,
+    pub count: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusPgMap {
+    #[serde(default)]
+    pub pgs_by_state: Vec<StatusPgState>,
+    pub num_pgs: u64,
+    pub num_pools: Option<u64>,
+    pub num_objects: Option<u64>,
+    pub data_bytes: Option<u64>,
+    pub bytes_used: Option<u64>,
+    pub bytes_avail: Option<u64>,
+    pub bytes_total: Option<u64>,
+    // client io and recovery rates, only printed when nonzero
+    pub read_bytes_sec: Option<u64>,
+    pub write_bytes_sec: Option<u64>,
+    pub read_op_per_sec: Option<u64>,
+    pub write_op_per_sec: Option<u64>,
+    pub recovering_objects_per_sec: Option<u64>,
+    pub recovering_bytes_per_sec: Option<u64>,
+    pub recovering_keys_per_sec: Option<u64>,
+    pub num_objects_recovered: Option<u64>,
+    pub num_bytes_recovered: Option<u64>,
+    pub num_keys_recovered: Option<u64>,
+    pub degraded_objects: Option<u64>,
+    pub degraded_total: Option<u64>,
+    pub degraded_ratio: Option<f64>,
+    pub misplaced_objects: Option<u64>,
+    pub misplaced_total: Option<u64>,
+    pub misplaced_ratio: Option<f64>,
+    pub unfound_objects: Option<u64>,
+    pub unfound_total: Option<u64>,
+    pub unfound_ratio: Option<f64>,
+    pub inactive_pgs_ratio: Option<f64>,
+    pub unknown_pgs_ratio: Option<f64>,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+impl StatusPgMap {
+    /// PGs whose state has every one of `states`, e.g. `&["active", "clean"]`
+    pub fn count_in_state(&self, states: &[&str]) -> u64 {
+        self.pgs_by_state
+            .iter()
+            .filter(|s| {
+                let parts: Vec<&str> = s.state_name.split('+').collect();
+                states.iter().all(|state| parts.contains(state))
+            })
+            .map(|s| s.count)
+            .sum()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusFsMap {
+    pub epoch: u64,
+    pub id: Option<i64>,
+    pub up: Option<u64>,
+    #[serde(rename = "in")]
+    pub in_: Option<u64>,
+    pub max: Option<u64>,
+    #[serde(default)]
+    pub by_rank: Vec<StatusMdsRank>,
+    #[serde(rename = "up:standby")]
+    pub up_standby: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusMdsRank {
+    pub filesystem_id: Option<i64>,
+    pub rank: i64,
+    pub name: String,
+    pub status: String,
+    pub gid: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusMgrMap {
+    pub available: bool,
+    pub num_standbys: Option<u64>, //Octopus
+    // Luminous to Nautilus print the whole mgrmap
+    pub epoch: Option<u64>,
+    pub active_name: Option<String>,
+    #[serde(default)]
+    pub standbys: Vec<StatusMgrStandby>,
+    #[serde(default)]
+    pub modules: Vec<String>,
+    #[serde(default)]
+    pub services: HashMap<String, String>,
+}
+
+impl StatusMgrMap {
+    pub fn standby_count(&self) -> u64 {
+        self.num_standbys.unwrap_or(self.standbys.len() as u64)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusMgrStandby {
+    pub gid: u64,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ServiceMap {
+    pub epoch: u64,
+    pub modified: Option<String>,
+    #[serde(default)]
+    pub services: HashMap<String, Service>,
+}
+
+/// A service registered by its daemons, such as rgw or rbd-mirror
+#[derive(Deserialize, Debug, Clone)]
+pub struct Service {
+    /// Keyed by daemon name, next to an empty "summary" entry
+    #[serde(default)]
+    pub daemons: HashMap<String, Value>,
+}
+
+impl Service {
+    pub fn daemon_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .daemons
+            .keys()
+            .map(|name| name.as_str())
+            .filter(|name| *name != "summary")
+            .collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// Background work reported by the mgr progress module, like recovery after
+/// an OSD is marked out
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProgressEvent {
+    pub message: String,
+    pub progress: f64,
+    #[serde(default)]
+    pub add_to_ceph_s: bool,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct CephStatus {
     health: CephStatusHealth,
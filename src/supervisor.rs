@@ -0,0 +1,422 @@
+//! A cluster handle that watches its own connection and reconnects.
+//!
+//! A background thread checks the connection every `check_interval` by
+//! pinging the mons and waiting for the latest OSD map.  After enough
+//! failures in a row it builds a fresh `Rados` from the same `RadosBuilder`
+//! and swaps it in.  `SupervisedIoCtx` handles notice the swap and re-create
+//! their `IoCtx` on next use, so callers don't have to.
+//!
+//! The OSD map wait blocks until the mons answer, so give the builder a
+//! `with_mon_op_timeout` or a dead connection can stall the checks.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::ceph::{IoCtx, Rados, RadosBuilder};
+use crate::cmd;
+use crate::error::{RadosError, RadosResult};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_backs_off_exponentially() {
+        let options = SupervisorOptions::new()
+            .with_reconnect_delay(Duration::from_secs(1), Duration::from_secs(10));
+        assert_eq!(options.reconnect_delay(1), Duration::from_secs(1));
+        assert_eq!(options.reconnect_delay(2), Duration::from_secs(2));
+        assert_eq!(options.reconnect_delay(4), Duration::from_secs(8));
+        assert_eq!(options.reconnect_delay(5), Duration::from_secs(10));
+        assert_eq!(options.reconnect_delay(100), Duration::from_secs(10));
+    }
+}
+
+/// Changes in the connection, sent to every `subscribe` receiver
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    /// A liveness check failed
+    CheckFailed { failures: u32, error: String },
+    /// A liveness check passed after failing before
+    Recovered,
+    /// Building a new connection
+    Reconnecting { attempt: u32 },
+    /// A reconnect attempt failed, another follows after the backoff delay
+    ReconnectFailed { attempt: u32, error: String },
+    /// A new handle is in use.  generation counts the connections made.
+    Reconnected { generation: u64 },
+}
+
+/// Options for `SupervisedRados`
+#[derive(Debug, Clone)]
+pub struct SupervisorOptions {
+    check_interval: Duration,
+    failures_before_reconnect: u32,
+    min_reconnect_delay: Duration,
+    max_reconnect_delay: Duration,
+}
+
+impl Default for SupervisorOptions {
+    fn default() -> Self {
+        SupervisorOptions {
+            check_interval: Duration::from_secs(30),
+            failures_before_reconnect: 2,
+            min_reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl SupervisorOptions {
+    pub fn new() -> SupervisorOptions {
+        SupervisorOptions::default()
+    }
+
+    /// Time between liveness checks
+    pub fn with_check_interval(mut self, check_interval: Duration) -> SupervisorOptions {
+        self.check_interval = check_interval;
+        self
+    }
+
+    /// Failed checks in a row before reconnecting
+    pub fn with_failures_before_reconnect(mut self, failures: u32) -> SupervisorOptions {
+        self.failures_before_reconnect = failures.max(1);
+        self
+    }
+
+    /// Wait min before retrying a failed reconnect, doubling each time up
+    /// to max
+    pub fn with_reconnect_delay(mut self, min: Duration, max: Duration) -> SupervisorOptions {
+        self.min_reconnect_delay = min;
+        self.max_reconnect_delay = max.max(min);
+        self
+    }
+
+    fn reconnect_delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+        self.min_reconnect_delay
+            .checked_mul(factor)
+            .map_or(self.max_reconnect_delay, |d| {
+                d.min(self.max_reconnect_delay)
+            })
+    }
+}
+
+struct Connection {
+    rados: Arc<Rados>,
+    generation: u64,
+    /// Mon names to ping, from the monmap at connect time
+    mons: Vec<String>,
+}
+
+struct Shared {
+    builder: RadosBuilder,
+    options: SupervisorOptions,
+    connection: Mutex<Connection>,
+    subscribers: Mutex<Vec<Sender<ConnectionEvent>>>,
+    stop: Mutex<bool>,
+    wake: Condvar,
+    /// Set while the thread builds a new connection, only ever while stop
+    /// is false and its lock held
+    connecting: AtomicBool,
+}
+
+/// A `Rados` handle that is checked periodically and transparently
+/// replaced when the connection stops working.
+///
+/// Dropping it stops the supervisor thread.  If that thread is in the middle
+/// of connecting, which can take up to `client_mount_timeout`, it is
+/// detached rather than waited for and exits once the attempt is over.
+pub struct SupervisedRados {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SupervisedRados {
+    /// Connect and start supervising the connection
+    pub fn connect(
+        builder: RadosBuilder,
+        options: SupervisorOptions,
+    ) -> RadosResult<SupervisedRados> {
+        let rados = builder.connect()?;
+        let mons = mon_names(&rados);
+        let shared = Arc::new(Shared {
+            builder,
+            options,
+            connection: Mutex::new(Connection {
+                rados: Arc::new(rados),
+                generation: 1,
+                mons,
+            }),
+            subscribers: Mutex::new(Vec::new()),
+            stop: Mutex::new(false),
+            wake: Condvar::new(),
+            connecting: AtomicBool::new(false),
+        });
+        let thread_shared = shared.clone();
+        let thread = thread::Builder::new()
+            .name("rados-supervisor".to_string())
+            .spawn(move || thread_shared.run())
+            .map_err(|e| RadosError::new(format!("Unable to start supervisor thread: {}", e)))?;
+        Ok(SupervisedRados {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// The current handle.  Hold on to it only briefly, it's replaced on
+    /// reconnect.
+    pub fn rados(&self) -> Arc<Rados> {
+        self.shared.connection().rados.clone()
+    }
+
+    /// Counts connections made, starting at 1
+    pub fn generation(&self) -> u64 {
+        self.shared.connection().generation
+    }
+
+    /// Receive connection events from now on
+    pub fn subscribe(&self) -> Receiver<ConnectionEvent> {
+        let (tx, rx) = channel();
+        self.shared.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Run a liveness check now
+    pub fn check(&self) -> RadosResult<()> {
+        let (rados, mons) = {
+            let connection = self.shared.connection();
+            (connection.rados.clone(), connection.mons.clone())
+        };
+        check_connection(&rados, &mons)
+    }
+
+    /// Replace the connection now, without checking it first
+    pub fn reconnect(&self) -> RadosResult<u64> {
+        self.shared.reconnect_once()
+    }
+
+    /// An io context for a pool that follows reconnects
+    pub fn ioctx(&self, pool_name: &str) -> RadosResult<SupervisedIoCtx> {
+        let ioctx = SupervisedIoCtx {
+            shared: self.shared.clone(),
+            pool: PoolRef::Name(pool_name.to_string()),
+            namespace: Mutex::new(None),
+            current: Mutex::new(None),
+        };
+        ioctx.get()?;
+        Ok(ioctx)
+    }
+
+    /// An io context for a pool id that follows reconnects
+    pub fn ioctx2(&self, pool_id: i64) -> RadosResult<SupervisedIoCtx> {
+        let ioctx = SupervisedIoCtx {
+            shared: self.shared.clone(),
+            pool: PoolRef::Id(pool_id),
+            namespace: Mutex::new(None),
+            current: Mutex::new(None),
+        };
+        ioctx.get()?;
+        Ok(ioctx)
+    }
+}
+
+impl Drop for SupervisedRados {
+    fn drop(&mut self) {
+        let connecting = {
+            let mut stop = self.shared.stop.lock().unwrap();
+            *stop = true;
+            self.shared.connecting.load(Ordering::SeqCst)
+        };
+        self.shared.wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            // A connect can't be interrupted, so leave the thread to finish
+            // it on its own
+            if !connecting {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+enum PoolRef {
+    Name(String),
+    Id(i64),
+}
+
+/// An `IoCtx` that is re-created on first use after the cluster handle
+/// has been replaced
+pub struct SupervisedIoCtx {
+    shared: Arc<Shared>,
+    pool: PoolRef,
+    namespace: Mutex<Option<String>>,
+    current: Mutex<Option<(u64, Arc<IoCtx>)>>,
+}
+
+impl SupervisedIoCtx {
+    /// The io context for the current connection
+    pub fn get(&self) -> RadosResult<Arc<IoCtx>> {
+        let (rados, generation) = {
+            let connection = self.shared.connection();
+            (connection.rados.clone(), connection.generation)
+        };
+        let mut current = self.current.lock().unwrap();
+        if let Some((ioctx_generation, ioctx)) = current.as_ref() {
+            if *ioctx_generation == generation {
+                return Ok(ioctx.clone());
+            }
+        }
+        let ioctx = match &self.pool {
+            PoolRef::Name(name) => rados.get_rados_ioctx(name)?,
+            PoolRef::Id(id) => rados.get_rados_ioctx2(*id)?,
+        };
+        if let Some(namespace) = self.namespace.lock().unwrap().as_deref() {
+            ioctx.rados_set_namespace(namespace)?;
+        }
+        let ioctx = Arc::new(ioctx);
+        *current = Some((generation, ioctx.clone()));
+        Ok(ioctx)
+    }
+
+    /// Set the namespace, now and on every re-created io context
+    pub fn set_namespace(&self, namespace: &str) -> RadosResult<()> {
+        *self.namespace.lock().unwrap() = Some(namespace.to_string());
+        if let Some((_, ioctx)) = self.current.lock().unwrap().as_ref() {
+            ioctx.rados_set_namespace(namespace)?;
+        }
+        Ok(())
+    }
+}
+
+impl Shared {
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap()
+    }
+
+    fn emit(&self, event: ConnectionEvent) {
+        debug!("rados supervisor: {:?}", event);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    /// Sleep, returning true if asked to stop meanwhile
+    fn sleep(&self, duration: Duration) -> bool {
+        let stop = self.stop.lock().unwrap();
+        let (stop, _) = self
+            .wake
+            .wait_timeout_while(stop, duration, |stop| !*stop)
+            .unwrap();
+        *stop
+    }
+
+    /// Mark the thread as connecting, unless asked to stop
+    fn start_connecting(&self) -> bool {
+        let stop = self.stop.lock().unwrap();
+        if *stop {
+            return false;
+        }
+        self.connecting.store(true, Ordering::SeqCst);
+        true
+    }
+
+    fn reconnect_once(&self) -> RadosResult<u64> {
+        let rados = self.builder.connect()?;
+        let mons = mon_names(&rados);
+        let mut connection = self.connection();
+        // The old handle shuts down once the last IoCtx using it is dropped
+        connection.rados = Arc::new(rados);
+        connection.mons = mons;
+        connection.generation += 1;
+        let generation = connection.generation;
+        drop(connection);
+        self.emit(ConnectionEvent::Reconnected { generation });
+        Ok(generation)
+    }
+
+    fn run(&self) {
+        let mut failures = 0;
+        while !self.sleep(self.options.check_interval) {
+            let (rados, mons) = {
+                let connection = self.connection();
+                (connection.rados.clone(), connection.mons.clone())
+            };
+            match check_connection(&rados, &mons) {
+                Ok(()) => {
+                    if failures > 0 {
+                        failures = 0;
+                        self.emit(ConnectionEvent::Recovered);
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    failures += 1;
+                    self.emit(ConnectionEvent::CheckFailed {
+                        failures,
+                        error: e.to_string(),
+                    });
+                }
+            }
+            if failures < self.options.failures_before_reconnect {
+                continue;
+            }
+
+            let mut attempt = 1;
+            loop {
+                if !self.start_connecting() {
+                    return;
+                }
+                self.emit(ConnectionEvent::Reconnecting { attempt });
+                let result = self.reconnect_once();
+                self.connecting.store(false, Ordering::SeqCst);
+                match result {
+                    Ok(_) => break,
+                    Err(e) => self.emit(ConnectionEvent::ReconnectFailed {
+                        attempt,
+                        error: e.to_string(),
+                    }),
+                }
+                if self.sleep(self.options.reconnect_delay(attempt)) {
+                    return;
+                }
+                attempt += 1;
+            }
+            failures = 0;
+        }
+    }
+}
+
+fn mon_names(rados: &Rados) -> Vec<String> {
+    match cmd::mon_getmap_decoded(rados, None) {
+        Ok(monmap) => monmap.mons.into_iter().map(|m| m.name).collect(),
+        Err(e) => {
+            debug!("Unable to fetch the monmap: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Any mon answering a ping shows they're reachable, and getting the latest
+/// OSD map shows this client's session and tickets still work
+fn check_connection(rados: &Rados, mons: &[String]) -> RadosResult<()> {
+    if !mons.is_empty() {
+        let mut last_error = None;
+        let pinged = mons.iter().any(|mon| match rados.ping_monitor(mon) {
+            Ok(_) => true,
+            Err(e) => {
+                last_error = Some(e);
+                false
+            }
+        });
+        if !pinged {
+            if let Some(e) = last_error {
+                return Err(e);
+            }
+        }
+    }
+    rados.wait_for_latest_osdmap()
+}
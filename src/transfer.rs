@@ -0,0 +1,259 @@
+//! Chunked, parallel copies between local files and RADOS objects.
+//!
+//! Large files are split into `chunk_size` pieces so no single request goes
+//! over the OSD's `osd_max_write_size`.  Up to `parallelism` chunks are in
+//! flight at once.  Uploads record how far they got in an xattr on the object
+//! and downloads write the local file strictly in order, so an interrupted
+//! transfer can be resumed with `with_resume(true)`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::thread;
+
+use crate::ceph::IoCtx;
+use crate::error::{RadosError, RadosResult};
+
+/// Extended attribute holding the committed offset of an unfinished upload
+pub const UPLOAD_OFFSET_XATTR: &str = "ceph-rust.upload-offset";
+
+/// Default chunk size.  Well under the default osd_max_write_size of 90MB.
+pub const DEFAULT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// Default number of chunks transferred concurrently
+pub const DEFAULT_PARALLELISM: usize = 4;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_splits_a_range_into_chunks() {
+        assert_eq!(chunks(0, 10, 4), vec![(0, 4), (4, 4), (8, 2)]);
+        assert_eq!(chunks(4, 8, 4), vec![(4, 4)]);
+        assert!(chunks(8, 8, 4).is_empty());
+    }
+}
+
+/// Options for `IoCtx::upload_file` and `IoCtx::download_to_file`
+pub struct TransferOptions<'a> {
+    chunk_size: usize,
+    parallelism: usize,
+    resume: bool,
+    progress: Option<Box<dyn FnMut(u64, u64) + 'a>>,
+}
+
+impl<'a> Default for TransferOptions<'a> {
+    fn default() -> Self {
+        TransferOptions {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            parallelism: DEFAULT_PARALLELISM,
+            resume: false,
+            progress: None,
+        }
+    }
+}
+
+impl<'a> TransferOptions<'a> {
+    pub fn new() -> TransferOptions<'a> {
+        TransferOptions::default()
+    }
+
+    /// Size of each read or write request
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> TransferOptions<'a> {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Number of chunks transferred concurrently
+    pub fn with_parallelism(mut self, parallelism: usize) -> TransferOptions<'a> {
+        self.parallelism = parallelism;
+        self
+    }
+
+    /// Continue an interrupted transfer instead of starting over
+    pub fn with_resume(mut self, resume: bool) -> TransferOptions<'a> {
+        self.resume = resume;
+        self
+    }
+
+    /// Called with (bytes transferred, total bytes) after every chunk
+    pub fn with_progress<F: FnMut(u64, u64) + 'a>(mut self, progress: F) -> TransferOptions<'a> {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    fn validate(&self) -> RadosResult<()> {
+        if self.chunk_size == 0 || self.parallelism == 0 {
+            return Err(RadosError::new(
+                "chunk_size and parallelism must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, done: u64, total: u64) {
+        if let Some(ref mut progress) = self.progress {
+            progress(done, total);
+        }
+    }
+}
+
+/// Split [start, end) into (offset, length) pieces of at most chunk_size
+pub(crate) fn chunks(start: u64, end: u64, chunk_size: usize) -> Vec<(u64, usize)> {
+    (start..end)
+        .step_by(chunk_size)
+        .map(|offset| (offset, (end - offset).min(chunk_size as u64) as usize))
+        .collect()
+}
+
+fn join<T>(handle: thread::ScopedJoinHandle<RadosResult<T>>) -> RadosResult<T> {
+    handle
+        .join()
+        .map_err(|_| RadosError::new("transfer worker thread panicked".to_string()))?
+}
+
+/// Read exactly len bytes at offset, failing if the object is shorter
+fn read_object_at(
+    ioctx: &IoCtx,
+    object_name: &str,
+    offset: u64,
+    len: usize,
+) -> RadosResult<Vec<u8>> {
+    let mut buffer: Vec<u8> = Vec::with_capacity(len);
+    while buffer.len() < len {
+        let mut chunk: Vec<u8> = Vec::with_capacity(len - buffer.len());
+        let read =
+            ioctx.rados_object_read(object_name, &mut chunk, offset + buffer.len() as u64)?;
+        if read == 0 {
+            return Err(RadosError::new(format!(
+                "{} shrank while it was being downloaded",
+                object_name
+            )));
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    Ok(buffer)
+}
+
+fn upload_offset(ioctx: &IoCtx, object_name: &str) -> RadosResult<Option<u64>> {
+    match ioctx.get_xattr_typed(object_name, UPLOAD_OFFSET_XATTR) {
+        Ok(offset) => Ok(Some(offset)),
+        Err(RadosError::ApiError(nix::errno::Errno::ENOENT))
+        | Err(RadosError::ApiError(nix::errno::Errno::ENODATA)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn upload_file(
+    ioctx: &IoCtx,
+    path: &Path,
+    object_name: &str,
+    mut opts: TransferOptions,
+) -> RadosResult<u64> {
+    opts.validate()?;
+    let file = File::open(path)?;
+    let total = file.metadata()?.len();
+
+    let resume_from = if opts.resume {
+        upload_offset(ioctx, object_name)?.filter(|offset| *offset <= total)
+    } else {
+        None
+    };
+    let mut offset = match resume_from {
+        Some(offset) => offset,
+        None => {
+            // Start from an empty object and mark it as in progress
+            ioctx.rados_object_write_full(object_name, &[])?;
+            ioctx.set_xattr_typed(object_name, UPLOAD_OFFSET_XATTR, &0u64)?;
+            0
+        }
+    };
+    ioctx.rados_object_set_alloc_hint(object_name, total, opts.chunk_size as u64)?;
+    opts.report(offset, total);
+
+    let wave_size = (opts.chunk_size * opts.parallelism) as u64;
+    while offset < total {
+        let wave_end = total.min(offset + wave_size);
+        let pieces = chunks(offset, wave_end, opts.chunk_size);
+        let results: Vec<RadosResult<usize>> = thread::scope(|s| {
+            let handles: Vec<_> = pieces
+                .iter()
+                .map(|&(chunk_offset, len)| {
+                    let file = &file;
+                    s.spawn(move || {
+                        let mut buffer = vec![0; len];
+                        file.read_exact_at(&mut buffer, chunk_offset)?;
+                        ioctx.rados_object_write(object_name, &buffer, chunk_offset)?;
+                        Ok(len)
+                    })
+                })
+                .collect();
+            handles.into_iter().map(join).collect()
+        });
+        for result in results {
+            offset += result? as u64;
+            opts.report(offset, total);
+        }
+        ioctx.set_xattr_typed(object_name, UPLOAD_OFFSET_XATTR, &offset)?;
+    }
+
+    // An earlier, larger upload may have left data past the end
+    ioctx.rados_object_trunc(object_name, total)?;
+    ioctx.rados_object_rmxattr(object_name, UPLOAD_OFFSET_XATTR)?;
+    Ok(total)
+}
+
+pub(crate) fn download_to_file(
+    ioctx: &IoCtx,
+    object_name: &str,
+    path: &Path,
+    mut opts: TransferOptions,
+) -> RadosResult<u64> {
+    opts.validate()?;
+    let (total, _) = ioctx.rados_object_stat(object_name)?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+
+    // The file is only ever written in order so its length is how far a
+    // previous download got
+    let mut offset = if opts.resume {
+        let len = file.metadata()?.len();
+        if len <= total {
+            len
+        } else {
+            0
+        }
+    } else {
+        0
+    };
+    file.set_len(offset)?;
+    file.seek(SeekFrom::Start(offset))?;
+    opts.report(offset, total);
+
+    let wave_size = (opts.chunk_size * opts.parallelism) as u64;
+    while offset < total {
+        let wave_end = total.min(offset + wave_size);
+        let pieces = chunks(offset, wave_end, opts.chunk_size);
+        let results: Vec<RadosResult<Vec<u8>>> = thread::scope(|s| {
+            let handles: Vec<_> = pieces
+                .iter()
+                .map(|&(chunk_offset, len)| {
+                    s.spawn(move || read_object_at(ioctx, object_name, chunk_offset, len))
+                })
+                .collect();
+            handles.into_iter().map(join).collect()
+        });
+        for result in results {
+            let buffer = result?;
+            file.write_all(&buffer)?;
+            offset += buffer.len() as u64;
+            opts.report(offset, total);
+        }
+    }
+    file.sync_all()?;
+    Ok(total)
+}
@@ -2,9 +2,23 @@ extern crate ceph;
 extern crate serde;
 extern crate serde_json;
 
-use ceph::cmd::{ClusterHealth, CrushTree, MonStatus};
+use ceph::ceph::connect_to_ceph;
+use ceph::cluster_log::ClusterLogLevel;
+use ceph::cmd::{
+    ClusterHealth, CrushTree, HealthStatus, MonStatus, OsdPerfDump, PerfCounter, PgQuery,
+};
+use ceph::command_desc::{ArgType, CommandDescriptions};
+use ceph::copy::CopyOptions;
+use ceph::crush::CrushMap;
+use ceph::placement::{ObjectPlacement, OsdMap, Placement};
+use ceph::status::{ClusterStatus, HealthDetail, StatusOsdMap};
+use ceph::MonCommand;
+use std::env;
 use std::fs::File;
 use std::io::Read;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 #[test]
 fn test_ceph_health_jewel() {
@@ -77,3 +91,453 @@ fn test_osd_tree_jewel() {
     let tree: CrushTree = serde_json::from_str(&json).unwrap();
     println!("osd_tree: {:#?}", tree);
 }
+
+#[test]
+fn test_placement_squid() {
+    let read = |name: &str| {
+        let mut buff = String::new();
+        let mut f = File::open(name).unwrap();
+        f.read_to_string(&mut buff).unwrap();
+        buff
+    };
+    let crush = CrushMap::from_json(&read("tests/osd_crush_dump-squid")).unwrap();
+    let osdmap = OsdMap::from_json(&read("tests/osd_dump-squid")).unwrap();
+    let placement = Placement::new(crush, osdmap);
+    let expected: Vec<ObjectPlacement> =
+        serde_json::from_str(&read("tests/osd_map-squid")).unwrap();
+    for osd_map in expected {
+        let (namespace, object) = match osd_map.objname.find('/') {
+            Some(i) => (&osd_map.objname[..i], &osd_map.objname[i + 1..]),
+            None => ("", osd_map.objname.as_str()),
+        };
+        let computed = placement
+            .map_object_in(&osd_map.pool, namespace, None, object)
+            .unwrap();
+        assert_eq!(computed, osd_map);
+    }
+}
+
+#[test]
+fn test_crushmap_squid() {
+    let bytes = std::fs::read("tests/crushmap-squid").unwrap();
+    let crush = CrushMap::decode(&bytes).unwrap();
+    assert_eq!(crush.encode(), bytes);
+
+    let mut json = String::new();
+    File::open("tests/osd_crush_dump-squid")
+        .unwrap()
+        .read_to_string(&mut json)
+        .unwrap();
+    assert_eq!(crush, CrushMap::from_json(&json).unwrap());
+}
+
+#[test]
+fn test_crushmap_jewel() {
+    let bytes = std::fs::read("tests/crushmap-jewel").unwrap();
+    let crush = CrushMap::decode(&bytes).unwrap();
+    println!("crushmap: {:#?}", crush);
+    assert_eq!(crush.tunables.msr_descents, None);
+    // Only the device class and later sections are added
+    let encoded = crush.encode();
+    assert!(encoded.starts_with(&bytes));
+    assert_eq!(CrushMap::decode(&encoded).unwrap(), crush);
+}
+
+#[test]
+fn test_command_descriptions_squid() {
+    let mut json = String::new();
+    File::open("tests/get_command_descriptions-squid")
+        .unwrap()
+        .read_to_string(&mut json)
+        .unwrap();
+    let descs = CommandDescriptions::from_json(&json).unwrap();
+    let create = descs.find("osd pool create").next().unwrap();
+    assert_eq!(create.module, "osd");
+    assert_eq!(
+        create.arg("pg_num").unwrap().arg_type,
+        ArgType::Int {
+            min: Some(0),
+            max: None
+        }
+    );
+    assert!(create
+        .to_string()
+        .starts_with("osd pool create <pool> [<pg_num>]"));
+
+    let cmd = MonCommand::new()
+        .with_prefix("osd pool create")
+        .with("pool", "rbd")
+        .with_int("pg_num", 32)
+        .with("pool_type", "replicated")
+        .with_bool("bulk", true);
+    descs.validate(&cmd).unwrap();
+    descs
+        .validate(
+            &MonCommand::new()
+                .with_prefix("osd out")
+                .with_strings("ids", &["1", "2"]),
+        )
+        .unwrap();
+    descs
+        .validate(
+            &MonCommand::new()
+                .with_prefix("pg repair")
+                .with("pgid", "2.1f"),
+        )
+        .unwrap();
+
+    let err = |cmd: MonCommand| descs.validate(&cmd).unwrap_err().to_string();
+    assert!(err(MonCommand::new().with_prefix("osd pool create"))
+        .contains("Missing required argument pool"));
+    assert!(err(cmd.clone().with_int("pg_num", -1)).contains("pg_num"));
+    assert!(err(cmd.clone().with("pool_type", "mirrored")).contains("replicated|erasure"));
+    assert!(err(cmd.clone().with("pgnum", "32")).contains("Unknown argument pgnum"));
+    assert!(err(MonCommand::new()
+        .with_prefix("osd reweight")
+        .with("id", "osd.1")
+        .with_float("weight", 1.5))
+    .contains("weight"));
+    assert!(err(MonCommand::new()
+        .with_prefix("osd crush reweight")
+        .with("name", "osd 1")
+        .with_float("weight", 1.0))
+    .contains("allowed characters"));
+    assert!(err(MonCommand::new().with_prefix("osd destroy")).contains("Unknown command"));
+}
+
+#[test]
+fn test_pg_query_squid() {
+    let json = {
+        let mut buff = String::new();
+        let mut f = File::open("tests/pg_query-squid").unwrap();
+        f.read_to_string(&mut buff).unwrap();
+        buff
+    };
+    let query: PgQuery = serde_json::from_str(&json).unwrap();
+    assert_eq!(query.state, "active+clean");
+    assert_eq!(query.acting, vec![2, 0, 1]);
+    assert_eq!(query.info.pgid, "2.1f");
+    assert_eq!(query.info.stats.acting_primary, 2);
+    assert_eq!(query.info.stats.stat_sum.num_objects, 11);
+    assert_eq!(query.info.history.last_scrub, "58'22");
+    assert_eq!(query.peer_info.len(), 2);
+    assert_eq!(query.recovery_state[0].name, "Started/Primary/Active");
+    assert!(query.other_meta.contains_key("scrubber"));
+}
+
+#[test]
+fn test_osd_perf_dump_squid() {
+    let json = {
+        let mut buff = String::new();
+        let mut f = File::open("tests/osd_perf_dump-squid").unwrap();
+        f.read_to_string(&mut buff).unwrap();
+        buff
+    };
+    let dump: OsdPerfDump = serde_json::from_str(&json).unwrap();
+    assert_eq!(dump.osd.op, 1873);
+    assert_eq!(dump.osd.op_w_latency.avgcount, 272);
+    assert!(dump.osd.op_w_latency.avgtime.unwrap() > 0.01);
+    assert_eq!(dump.osd.numpg, 65);
+    assert_eq!(dump.osd.other.get("loadavg"), Some(&PerfCounter::Count(37)));
+    match dump.counter("bluestore", "kv_commit_lat") {
+        Some(PerfCounter::Average(avg)) => assert_eq!(avg.avgcount, 544),
+        other => panic!("unexpected counter {:?}", other),
+    }
+    assert_eq!(
+        dump.counter("bluefs", "db_used_bytes"),
+        Some(&PerfCounter::Count(33554432))
+    );
+    assert!(matches!(
+        dump.counter("AsyncMessenger::Worker-0", "msgr_running_total_time"),
+        Some(PerfCounter::Time(_))
+    ));
+}
+
+// The status fixtures are assembled from the documented layout of each
+// release rather than captured from a cluster.
+fn read_status(path: &str) -> ClusterStatus {
+    let mut buff = String::new();
+    let mut f = File::open(path).unwrap();
+    f.read_to_string(&mut buff).unwrap();
+    serde_json::from_str(&buff).unwrap()
+}
+
+#[test]
+fn test_status_luminous() {
+    let status = read_status("tests/status-luminous");
+    assert_eq!(status.fsid, "0b8e7b1c-54f2-4f4e-9c8a-3d1f6a2e7b01");
+    assert_eq!(status.quorum_names, vec!["a", "b", "c"]);
+    assert_eq!(status.monmap.mon_count(), 3);
+    assert_eq!(status.monmap.mons[0].name, "a");
+    assert_eq!(status.monmap.mons[2].rank, Some(2));
+    assert_eq!(status.health.status, HealthStatus::Warn);
+    assert_eq!(status.health.overall_status, Some(HealthStatus::Warn));
+    assert!(status.health.checks.contains_key("POOL_APP_NOT_ENABLED"));
+    assert!(matches!(status.osdmap, StatusOsdMap::Wrapped { .. }));
+    assert_eq!(status.osdmap.summary().num_osds, 3);
+    assert_eq!(status.osdmap.summary().full, Some(false));
+    assert_eq!(status.pgmap.num_pgs, 64);
+    assert_eq!(status.pgmap.bytes_total, Some(324076281856));
+    let mgrmap = status.mgrmap.unwrap();
+    assert_eq!(mgrmap.active_name.as_deref(), Some("a"));
+    assert_eq!(mgrmap.standby_count(), 1);
+    let servicemap = status.servicemap.unwrap();
+    assert!(servicemap.services.is_empty());
+    assert_eq!(servicemap.modified.as_deref(), Some("0.000000"));
+    assert!(status.fsmap.unwrap().by_rank.is_empty());
+    assert!(status.quorum_age.is_none());
+    assert!(status.other.is_empty());
+}
+
+#[test]
+fn test_status_mimic() {
+    let status = read_status("tests/status-mimic");
+    assert_eq!(status.fsid, "1c9f8c2d-65a3-4a5f-8d9b-4e2a7b3f8c12");
+    assert_eq!(status.monmap.mon_count(), 3);
+    assert_eq!(status.osdmap.summary().num_osds, 6);
+    assert_eq!(status.osdmap.summary().num_remapped_pgs, Some(0));
+    assert_eq!(status.health.status, HealthStatus::Ok);
+    assert!(status.health.checks.is_empty());
+    assert_eq!(status.pgmap.num_pgs, 128);
+    assert!(status.pgmap.read_bytes_sec.is_none());
+    let rank = &status.fsmap.unwrap().by_rank[0];
+    assert_eq!(rank.status, "up:active");
+    assert_eq!(rank.gid, Some(24123));
+    let servicemap = status.servicemap.unwrap();
+    assert_eq!(servicemap.services["rgw"].daemon_names(), vec!["14201"]);
+}
+
+#[test]
+fn test_status_nautilus() {
+    let status = read_status("tests/status-nautilus");
+    assert_eq!(status.fsid, "2da09d3e-76b4-4b6a-9eac-5f3b8c4a9d23");
+    assert_eq!(status.quorum_names, vec!["a", "b", "c", "d", "e"]);
+    assert_eq!(status.quorum_age, Some(86341));
+    assert_eq!(status.monmap.mon_count(), 5);
+    assert_eq!(
+        status.monmap.min_mon_release_name.as_deref(),
+        Some("nautilus")
+    );
+    assert!(matches!(status.osdmap, StatusOsdMap::Wrapped { .. }));
+    assert_eq!(status.osdmap.summary().num_osds, 4);
+    assert_eq!(status.osdmap.summary().num_up_osds, 3);
+    assert_eq!(status.pgmap.num_pgs, 96);
+    assert_eq!(status.pgmap.count_in_state(&["degraded"]), 25);
+    assert_eq!(status.pgmap.count_in_state(&["active", "clean"]), 71);
+    assert_eq!(status.pgmap.degraded_ratio, Some(0.230456));
+    assert_eq!(status.mgrmap.unwrap().standby_count(), 2);
+    assert_eq!(status.progress_events.len(), 1);
+    assert!(status.health.checks["OSD_DOWN"].summary.count.is_none());
+}
+
+#[test]
+fn test_status_octopus() {
+    let status = read_status("tests/status-octopus");
+    assert_eq!(status.fsid, "3eb1ae4f-87c5-4c7b-8fbd-6a4c9d5bae34");
+    assert_eq!(status.health.status, HealthStatus::Ok);
+    assert!(status.health.mutes.is_empty());
+    assert!(status.monmap.mons.is_empty());
+    assert_eq!(status.monmap.mon_count(), 3);
+    assert!(matches!(status.osdmap, StatusOsdMap::UnWrapped(_)));
+    assert_eq!(status.osdmap.summary().num_osds, 8);
+    assert_eq!(status.osdmap.summary().osd_up_since, Some(1727769151));
+    assert_eq!(status.pgmap.num_pgs, 129);
+    let mgrmap = status.mgrmap.unwrap();
+    assert_eq!(mgrmap.standby_count(), 1);
+    assert!(mgrmap.services.contains_key("dashboard"));
+}
+
+#[test]
+fn test_status_pacific() {
+    let status = read_status("tests/status-pacific");
+    assert_eq!(status.fsid, "4fc2bf5a-98d6-4d8c-9ace-7b5dae6cbf45");
+    assert_eq!(status.monmap.mon_count(), 5);
+    assert_eq!(
+        status.monmap.min_mon_release_name.as_deref(),
+        Some("pacific")
+    );
+    assert_eq!(status.osdmap.summary().num_osds, 12);
+    assert_eq!(status.pgmap.num_pgs, 161);
+    assert_eq!(status.pgmap.write_op_per_sec, Some(48));
+    assert_eq!(status.mgrmap.unwrap().standby_count(), 2);
+    assert_eq!(status.fsmap.unwrap().up_standby, Some(2));
+}
+
+#[test]
+fn test_status_quincy() {
+    let status = read_status("tests/status-quincy");
+    assert_eq!(status.fsid, "5ad3ca6b-a9e7-4e9d-8bdf-8c6ebf7dca56");
+    assert_eq!(status.osdmap.summary().num_osds, 6);
+    assert_eq!(status.osdmap.summary().num_up_osds, 5);
+    assert_eq!(status.pgmap.num_pgs, 97);
+    assert_eq!(status.pgmap.count_in_state(&["active", "clean"]), 72);
+    let degraded = &status.health.checks["PG_DEGRADED"];
+    assert_eq!(degraded.severity, HealthStatus::Warn);
+    assert_eq!(degraded.summary.count, Some(25));
+    assert!(!degraded.muted);
+    assert_eq!(status.pgmap.recovering_objects_per_sec, Some(12));
+    let event = status.progress_events.values().next().unwrap();
+    assert!(event.message.starts_with("Global Recovery Event"));
+    assert!(event.progress > 0.25);
+}
+
+#[test]
+fn test_status_reef() {
+    let status = read_status("tests/status-reef");
+    assert_eq!(status.fsid, "6be4db7c-baf8-4fae-9cea-9d7fca8edb67");
+    assert_eq!(status.osdmap.summary().num_osds, 9);
+    assert_eq!(status.pgmap.num_pgs, 225);
+    assert!(status.health.checks.contains_key("RECENT_CRASH"));
+    assert_eq!(status.progress_events.len(), 1);
+    let servicemap = status.servicemap.unwrap();
+    assert_eq!(servicemap.services["rbd-mirror"].daemon_names().len(), 1);
+}
+
+#[test]
+fn test_status_squid() {
+    let status = read_status("tests/status-squid");
+    assert_eq!(status.fsid, "7cf5ec8d-cb09-4abf-8dfb-ae8adb9fec78");
+    assert_eq!(status.osdmap.summary().num_osds, 12);
+    assert_eq!(status.osdmap.summary().full, None);
+    assert_eq!(status.pgmap.num_pgs, 289);
+    assert_eq!(status.mgrmap.unwrap().standby_count(), 0);
+    assert!(status.health.checks["POOL_NEARFULL"].muted);
+    let mute = &status.health.mutes[0];
+    assert_eq!(mute.code, "POOL_NEARFULL");
+    assert_eq!(mute.count, 2);
+    assert!(mute.ttl.is_some());
+    assert!(!mute.sticky);
+}
+
+#[test]
+fn test_status_without_optional_fields() {
+    let mut buff = String::new();
+    let mut f = File::open("tests/status-luminous").unwrap();
+    f.read_to_string(&mut buff).unwrap();
+    let mut json: serde_json::Value = serde_json::from_str(&buff).unwrap();
+    for field in &["data_bytes", "bytes_used", "bytes_avail", "bytes_total"] {
+        json["pgmap"].as_object_mut().unwrap().remove(*field);
+    }
+    json["pgmap"]
+        .as_object_mut()
+        .unwrap()
+        .remove("pgs_by_state");
+    json["osdmap"]["osdmap"]
+        .as_object_mut()
+        .unwrap()
+        .remove("num_remapped_pgs");
+    json["servicemap"]
+        .as_object_mut()
+        .unwrap()
+        .remove("modified");
+    json["monmap"]["mons"][0]
+        .as_object_mut()
+        .unwrap()
+        .remove("rank");
+    json["fsmap"]["by_rank"] = serde_json::json!([
+        {"filesystem_id": 1, "rank": 0, "name": "node1", "status": "up:active"}
+    ]);
+
+    let status: ClusterStatus = serde_json::from_value(json).unwrap();
+    assert!(status.pgmap.bytes_total.is_none());
+    assert!(status.pgmap.pgs_by_state.is_empty());
+    assert!(status.osdmap.summary().num_remapped_pgs.is_none());
+    assert!(status.servicemap.unwrap().modified.is_none());
+    assert!(status.monmap.mons[0].rank.is_none());
+    assert!(status.fsmap.unwrap().by_rank[0].gid.is_none());
+}
+
+#[test]
+fn test_health_detail_luminous() {
+    let json = {
+        let mut buff = String::new();
+        let mut f = File::open("tests/health_detail-luminous").unwrap();
+        f.read_to_string(&mut buff).unwrap();
+        buff
+    };
+    let health: HealthDetail = serde_json::from_str(&json).unwrap();
+    assert_eq!(health.status, HealthStatus::Warn);
+    assert!(health.is_firing("OSD_DOWN"));
+    assert_eq!(health.osds("OSD_DOWN"), vec![2]);
+    let degraded = health.check("PG_DEGRADED").unwrap();
+    assert_eq!(degraded.pgs(), vec!["1.0", "1.1", "1.2"]);
+    assert_eq!(degraded.count(), 3);
+    assert!(!health.is_firing("SLOW_OPS"));
+}
+
+#[test]
+fn test_health_detail_squid() {
+    let json = {
+        let mut buff = String::new();
+        let mut f = File::open("tests/health_detail-squid").unwrap();
+        f.read_to_string(&mut buff).unwrap();
+        buff
+    };
+    let health: HealthDetail = serde_json::from_str(&json).unwrap();
+    assert_eq!(health.status, HealthStatus::Err);
+    assert_eq!(
+        health.firing(),
+        vec![
+            "OSD_DOWN",
+            "OSD_HOST_DOWN",
+            "OSD_SCRUB_ERRORS",
+            "PG_DAMAGED",
+            "SLOW_OPS"
+        ]
+    );
+    assert_eq!(health.osds("OSD_DOWN"), vec![4, 11]);
+    assert_eq!(health.check("OSD_DOWN").unwrap().count(), 2);
+    // Muted checks are still reported, just not firing
+    assert!(!health.is_firing("OSD_NEARFULL"));
+    assert_eq!(health.osds("OSD_NEARFULL"), vec![7]);
+    assert!(health.mutes[0].sticky);
+    let damaged = health.check("PG_DAMAGED").unwrap();
+    assert_eq!(damaged.severity, HealthStatus::Err);
+    assert_eq!(damaged.pgs(), vec!["3.a"]);
+    assert_eq!(health.check("SLOW_OPS").unwrap().count(), 14);
+    assert_eq!(health.osds("SLOW_OPS"), vec![2, 9]);
+}
+
+#[test]
+#[ignore]
+fn test_copy_object_onto_itself() {
+    // Needs a live cluster: run with --ignored and CEPH_TEST_POOL set to a
+    // pool client.admin can write
+    let pool = env::var("CEPH_TEST_POOL").expect("CEPH_TEST_POOL is not set");
+    let rados = Arc::new(connect_to_ceph("admin", "/etc/ceph/ceph.conf").unwrap());
+    let src = rados.get_rados_ioctx(&pool).unwrap();
+    let dst = rados.get_rados_ioctx(&pool).unwrap();
+    src.rados_object_write_full("copy-onto-itself", b"payload")
+        .unwrap();
+
+    let opts = CopyOptions::new().with_delete_source(true);
+    assert!(src
+        .copy_object("copy-onto-itself", &dst, "copy-onto-itself", &opts)
+        .is_err());
+    let streamed = CopyOptions::new().with_server_side(false);
+    assert!(src
+        .copy_object("copy-onto-itself", &dst, "copy-onto-itself", &streamed)
+        .is_err());
+    assert_eq!(src.rados_object_stat("copy-onto-itself").unwrap().0, 7);
+
+    src.rados_object_remove("copy-onto-itself").unwrap();
+}
+
+#[test]
+#[ignore]
+fn test_cluster_log_watch_on_another_thread() {
+    // Needs a live cluster: run with --ignored
+    let rados = Arc::new(connect_to_ceph("admin", "/etc/ceph/ceph.conf").unwrap());
+    let watch = rados.watch_cluster_log(ClusterLogLevel::Info).unwrap();
+    let reader = thread::spawn(move || {
+        while let Ok(entry) = watch.recv_timeout(Duration::from_secs(30)) {
+            if entry.message.contains("cluster log watch test") {
+                return true;
+            }
+        }
+        false
+    });
+    let cmd = serde_json::json!({"prefix": "log", "logtext": ["cluster log watch test"]});
+    rados.ceph_mon_command_without_data(&cmd).unwrap();
+    assert!(reader.join().unwrap());
+}